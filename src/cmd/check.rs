@@ -1,64 +1,80 @@
-use crate::config::{read_config, Config};
-use crate::procfile::read_procfile;
+use ultraman::config::{load_config, RawConfig};
+use ultraman::error::{Result, UltramanError};
+use ultraman::procfile::read_procfile;
 
 use std::path::PathBuf;
 use structopt::{clap, StructOpt};
 
-#[cfg(not(test))]
-use std::process::exit;
-
 #[derive(StructOpt, Debug)]
 #[structopt(setting(clap::AppSettings::ColoredHelp))]
 pub struct CheckOpts {
     /// Specify an Procfile to load
     #[structopt(name = "PROCFILE", short = "f", long = "procfile", parse(from_os_str))]
     pub procfile_path: Option<PathBuf>,
+
+    /// Fail when the Procfile has lines that can't be parsed or processes that are defined more than once
+    #[structopt(long = "strict")]
+    pub is_strict: bool,
+
+    /// Use the named profile from .ultraman (also ULTRAMAN_PROFILE)
+    #[structopt(name = "PROFILE", long = "profile")]
+    pub profile: Option<String>,
 }
 
-pub fn run(input_opts: CheckOpts) {
-    let dotconfig = read_config(PathBuf::from("./ultraman")).unwrap();
-    let opts = merged_opts(&input_opts, dotconfig);
-
-    let procfile_path = opts.procfile_path.unwrap();
-    if !&procfile_path.exists() {
-        let display_path = procfile_path.into_os_string().into_string().unwrap();
-        eprintln!("{} does not exist.", &display_path);
-        // https://www.reddit.com/r/rust/comments/emz456/testing_whether_functions_exit/
-        #[cfg(not(test))]
-        exit(1);
-        #[cfg(test)]
-        panic!("exit {}", 1);
+pub fn run(input_opts: CheckOpts) -> Result<()> {
+    let opts = load_config(RawConfig::from(&input_opts))?;
+
+    let procfile_path = opts.procfile_path;
+    let display_path = procfile_path.display().to_string();
+    let procfile = read_procfile(procfile_path.clone())?;
+
+    let level = if input_opts.is_strict {
+        "error"
+    } else {
+        "warning"
+    };
+    for issue in procfile.issues.iter() {
+        eprintln!("{}: {}: {}", level, &display_path, issue);
+    }
+    if input_opts.is_strict && !procfile.issues.is_empty() {
+        return Err(UltramanError::ProcfileInvalid {
+            path: procfile_path,
+            issues: procfile.issues.len(),
+        });
     }
-    let procfile = read_procfile(procfile_path).expect("failed read Procfile");
 
     if !procfile.check() {
         eprintln!("no process defined");
     } else {
         println!("valid procfile detected ({})", procfile.process_names());
     }
+
+    Ok(())
 }
 
-fn merged_opts(input_opts: &CheckOpts, dotconfig: Config) -> CheckOpts {
-    CheckOpts {
-        procfile_path: match &input_opts.procfile_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => Some(dotconfig.procfile_path),
-        },
+impl From<&CheckOpts> for RawConfig {
+    fn from(opts: &CheckOpts) -> Self {
+        RawConfig {
+            procfile: opts.procfile_path.clone(),
+            profile: opts.profile.clone(),
+            ..RawConfig::default()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use std::fs::File;
     use std::io::Write;
     use tempfile::tempdir;
+    use ultraman::config::{Config, ConfigLoader};
 
-    fn prepare_dotconfig() -> Config {
+    fn load_with_dotconfig(input_opts: &CheckOpts) -> Config {
         let dir = tempdir().ok().unwrap();
         let file_path = dir.path().join(".ultraman");
         let mut file = File::create(file_path.clone()).ok().unwrap();
-        // Writing a comment causes a parse error
         writeln!(
             file,
             r#"
@@ -74,7 +90,7 @@ no-timestamp: true
 app: app-for-runit
 log: /var/app/log/ultraman.log
 run: /tmp/pids/ultraman.pid
-template: ../../src/cmd/export/templates/supervisord
+template: ../../src/export/templates/supervisord
 user: root
 root: /home/app
 
@@ -83,40 +99,62 @@ hoge: hogehoge
         )
         .unwrap();
 
-        let dotconfig = read_config(file_path).expect("failed read .ultraman");
-        dotconfig
+        ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::from(input_opts))
+            .expect("failed load .ultraman")
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_dotconfig() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_dotconfig() -> anyhow::Result<()> {
         let input_opts = CheckOpts {
             procfile_path: None,
+            is_strict: false,
+            profile: None,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
 
-        assert_eq!(
-            result.procfile_path.unwrap(),
-            PathBuf::from("./tmp/Procfile")
-        );
+        assert_eq!(result.procfile_path, PathBuf::from("./tmp/Procfile"));
 
         Ok(())
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_input_opts() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_input_opts() -> anyhow::Result<()> {
         let input_opts = CheckOpts {
             procfile_path: Some(PathBuf::from("./test/Procfile")),
+            is_strict: false,
+            profile: None,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
+
+        assert_eq!(result.procfile_path, PathBuf::from("./test/Procfile"));
+
+        Ok(())
+    }
 
-        assert_eq!(
-            result.procfile_path.unwrap(),
-            PathBuf::from("./test/Procfile")
-        );
+    #[test]
+    fn test_run_when_strict() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let procfile_path = dir.path().join("Procfile");
+        let mut file = File::create(procfile_path.clone())?;
+        writeln!(file, "web: ./web.sh\nworker = ./worker.sh")?;
+
+        let result = run(CheckOpts {
+            procfile_path: Some(procfile_path.clone()),
+            is_strict: false,
+            profile: None,
+        });
+        assert!(result.is_ok());
+
+        let result = run(CheckOpts {
+            procfile_path: Some(procfile_path),
+            is_strict: true,
+            profile: None,
+        })
+        .unwrap_err();
+        assert_eq!(result.exit_code(), 65);
 
         Ok(())
     }
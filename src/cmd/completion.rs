@@ -1,3 +1,4 @@
+use ultraman::error::Result;
 use crate::opt::Opt;
 use std::io;
 use structopt::clap::Shell;
@@ -14,7 +15,7 @@ pub struct CompletionOpts {
     pub shell: Shell,
 }
 
-pub fn run(opts: CompletionOpts) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run(opts: CompletionOpts) -> Result<()> {
     let mut app = Opt::clap();
     let app_name = app.get_name().to_string();
     app.gen_completions_to(app_name, opts.shell, &mut io::stdout());
@@ -0,0 +1,82 @@
+use ultraman::config::{load_config, Config, RawConfig};
+use ultraman::error::Result;
+
+use structopt::{clap, StructOpt};
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting(clap::AppSettings::ColoredHelp))]
+pub struct ConfigOpts {
+    /// Show where each value comes from (default, config file, environment variable or command line)
+    #[structopt(long = "show-origin")]
+    pub is_show_origin: bool,
+
+    /// Use the named profile from .ultraman (also ULTRAMAN_PROFILE)
+    #[structopt(name = "PROFILE", long = "profile")]
+    pub profile: Option<String>,
+}
+
+pub fn run(opts: ConfigOpts) -> Result<()> {
+    let config = load_config(RawConfig {
+        profile: opts.profile.clone(),
+        ..RawConfig::default()
+    })?;
+    for line in format_config(&config, opts.is_show_origin) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn format_config(config: &Config, is_show_origin: bool) -> Vec<String> {
+    config
+        .entries()
+        .into_iter()
+        .map(|(key, value)| {
+            // unset keys are printed as yaml null
+            let value = value.unwrap_or_else(|| String::from("~"));
+            if is_show_origin {
+                format!("{}\t{}: {}", config.origin(key), key, value)
+            } else {
+                format!("{}: {}", key, value)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use ultraman::config::Origin;
+
+    #[test]
+    fn test_format_config() -> anyhow::Result<()> {
+        let mut config = Config::default();
+        config.apply(
+            RawConfig {
+                formation: Some(String::from("web=2")),
+                ..RawConfig::default()
+            },
+            |_| Origin::File(PathBuf::from("/app/.ultraman")),
+        );
+        config.apply(
+            RawConfig {
+                port: Some(6000),
+                ..RawConfig::default()
+            },
+            |_| Origin::Env(String::from("ULTRAMAN_PORT")),
+        );
+
+        let result = format_config(&config, false);
+        assert_eq!(result[0], "procfile: Procfile");
+        assert_eq!(result[2], "formation: web=2");
+        assert_eq!(result[5], "port: 6000");
+        assert_eq!(result[6], "app: ~");
+
+        let result = format_config(&config, true);
+        assert_eq!(result[0], "default\tprocfile: Procfile");
+        assert_eq!(result[2], "/app/.ultraman\tformation: web=2");
+        assert_eq!(result[5], "$ULTRAMAN_PORT\tport: 6000");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+use ultraman::config::{load_config, Config, RawConfig};
+use ultraman::env::{read_env, Env};
+use ultraman::env_for;
+use ultraman::error::{Result, UltramanError};
+use ultraman::procfile::read_procfile;
+
+use serde_json::{json, Map, Value as Json};
+use shellwords::escape;
+use std::collections::BTreeMap;
+use std::env as std_env;
+use std::path::PathBuf;
+use structopt::{clap, StructOpt};
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting(clap::AppSettings::ColoredHelp))]
+pub struct EnvOpts {
+    /// Process to inspect, optionally with an instance number (e.g. web or web.2)
+    #[structopt(name = "PROCESS")]
+    pub process: Option<String>,
+
+    /// Output format
+    #[structopt(
+        name = "FORMAT",
+        short = "o",
+        long = "format",
+        default_value = "shell",
+        possible_values = &["shell", "json", "dotenv"]
+    )]
+    pub format: String,
+
+    /// Only print variables that are added or changed compared to the current shell environment
+    #[structopt(long = "diff")]
+    pub is_diff: bool,
+
+    /// Specify an environment file to load
+    #[structopt(name = "ENV", short = "e", long = "env", parse(from_os_str))]
+    pub env_path: Option<PathBuf>,
+
+    /// Specify an Procfile to load
+    #[structopt(name = "PROCFILE", short = "f", long = "procfile", parse(from_os_str))]
+    pub procfile_path: Option<PathBuf>,
+
+    /// Specify which port to use as the base for this application. Should be a multiple of 1000
+    #[structopt(name = "PORT", short = "p", long = "port")]
+    pub port: Option<u32>,
+
+    /// Use the named profile from .ultraman (also ULTRAMAN_PROFILE)
+    #[structopt(name = "PROFILE", long = "profile")]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Change {
+    Added(String),
+    Changed { from: String, to: String },
+}
+
+type EnvDiff = BTreeMap<String, Change>;
+
+pub fn run(input_opts: EnvOpts) -> Result<()> {
+    let config = load_config(RawConfig::from(&input_opts))?;
+
+    // Reading the env file also exports it into our own environment, so take a snapshot first
+    let shell_env: Env = std_env::vars().collect();
+    let env = effective_env(&input_opts, &config)?;
+
+    let output = if input_opts.is_diff {
+        format_diff(&diff_env(&shell_env, &env), &input_opts.format)
+    } else {
+        format_env(&env, &input_opts.format)
+    };
+    if !output.is_empty() {
+        println!("{}", output);
+    }
+
+    Ok(())
+}
+
+// The environment of ultraman is inherited whether the env file exists or not, and the file
+// (plus PORT and PS for a process) goes on top of it
+fn effective_env(opts: &EnvOpts, config: &Config) -> Result<Env> {
+    let env_path = config.env_path.clone();
+
+    let mut env: Env = std_env::vars().collect();
+    env.extend(match &opts.process {
+        Some(process) => {
+            let (name, concurrency_index) = parse_instance(process)?;
+            let procfile = read_procfile(config.procfile_path.clone())?;
+            procfile.find_by(name)?;
+            env_for(name, env_path, config.port, concurrency_index)?
+        }
+        None => read_env(env_path)?,
+    });
+    Ok(env)
+}
+
+// e.g.) web -> ("web", 0), web.2 -> ("web", 1)
+fn parse_instance(process: &str) -> Result<(&str, usize)> {
+    match process.rsplit_once('.') {
+        Some((name, number)) => match number.parse::<usize>() {
+            Ok(n) if n >= 1 => Ok((name, n - 1)),
+            _ => Err(UltramanError::InvalidInstance(process.to_string())),
+        },
+        None => Ok((process, 0)),
+    }
+}
+
+fn diff_env(base: &Env, env: &Env) -> EnvDiff {
+    let mut diff = EnvDiff::new();
+    for (key, value) in env.iter() {
+        match base.get(key) {
+            None => {
+                diff.insert(key.clone(), Change::Added(value.clone()));
+            }
+            Some(from) if from != value => {
+                diff.insert(
+                    key.clone(),
+                    Change::Changed {
+                        from: from.clone(),
+                        to: value.clone(),
+                    },
+                );
+            }
+            _ => (),
+        }
+    }
+    diff
+}
+
+fn format_env(env: &Env, format: &str) -> String {
+    let sorted: BTreeMap<&String, &String> = env.iter().collect();
+    match format {
+        "json" => to_json_string(json!(sorted)),
+        _ => sorted
+            .iter()
+            .map(|(key, value)| format_pair(key, value, format))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn format_diff(diff: &EnvDiff, format: &str) -> String {
+    match format {
+        "json" => {
+            let mut added = Map::new();
+            let mut changed = Map::new();
+            for (key, change) in diff.iter() {
+                match change {
+                    Change::Added(value) => {
+                        added.insert(key.clone(), json!(value));
+                    }
+                    Change::Changed { from, to } => {
+                        changed.insert(key.clone(), json!({ "from": from, "to": to }));
+                    }
+                }
+            }
+            to_json_string(json!({ "added": added, "changed": changed }))
+        }
+        _ => diff
+            .iter()
+            .map(|(key, change)| match change {
+                Change::Added(value) => {
+                    format!("# added\n{}", format_pair(key, value, format))
+                }
+                Change::Changed { from, to } => format!(
+                    "# changed from {}\n{}",
+                    format_value(from, format),
+                    format_pair(key, to, format)
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn format_pair(key: &str, value: &str, format: &str) -> String {
+    match format {
+        "dotenv" => format!("{}={}", key, format_value(value, format)),
+        _ => format!("export {}={}", key, format_value(value, format)),
+    }
+}
+
+fn format_value(value: &str, format: &str) -> String {
+    match format {
+        "dotenv" => format!(
+            "\"{}\"",
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        ),
+        _ => escape(value),
+    }
+}
+
+fn to_json_string(value: Json) -> String {
+    // Unlike to_string_pretty, formatting a Value can't fail
+    format!("{:#}", value)
+}
+
+impl From<&EnvOpts> for RawConfig {
+    fn from(opts: &EnvOpts) -> Self {
+        RawConfig {
+            procfile: opts.procfile_path.clone(),
+            env: opts.env_path.clone(),
+            port: opts.port,
+            profile: opts.profile.clone(),
+            ..RawConfig::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+    use ultraman::config::ConfigLoader;
+
+    fn load_with_dotconfig(input_opts: &EnvOpts) -> Config {
+        let dir = tempdir().ok().unwrap();
+        let file_path = dir.path().join(".ultraman");
+        let mut file = File::create(file_path.clone()).ok().unwrap();
+        writeln!(
+            file,
+            r#"
+procfile: ./tmp/Procfile
+env: ./tmp/.env
+port: 6000
+      "#
+        )
+        .unwrap();
+
+        ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::from(input_opts))
+            .expect("failed load .ultraman")
+    }
+
+    fn env_of(pairs: &[(&str, &str)]) -> Env {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_load_config_when_prefer_dotconfig() -> anyhow::Result<()> {
+        let input_opts = EnvOpts {
+            process: Some(String::from("web.2")),
+            format: String::from("json"),
+            is_diff: true,
+            env_path: None,
+            procfile_path: None,
+            port: None,
+            profile: None,
+        };
+
+        let result = load_with_dotconfig(&input_opts);
+
+        assert_eq!(input_opts.process.unwrap(), "web.2");
+        assert_eq!(input_opts.format, "json");
+        assert!(input_opts.is_diff);
+        assert_eq!(result.env_path, PathBuf::from("./tmp/.env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./tmp/Procfile"));
+        assert_eq!(result.port.unwrap(), 6000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_config_when_prefer_input_opts() -> anyhow::Result<()> {
+        let input_opts = EnvOpts {
+            process: None,
+            format: String::from("shell"),
+            is_diff: false,
+            env_path: Some(PathBuf::from("./test/.env")),
+            procfile_path: Some(PathBuf::from("./test/Procfile")),
+            port: Some(9999),
+            profile: None,
+        };
+
+        let result = load_with_dotconfig(&input_opts);
+
+        assert_eq!(input_opts.process, None);
+        assert_eq!(result.env_path, PathBuf::from("./test/.env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./test/Procfile"));
+        assert_eq!(result.port.unwrap(), 9999);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_effective_env_when_env_file_is_missing() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let procfile_path = dir.path().join("Procfile");
+        writeln!(File::create(&procfile_path)?, "web: ./web.sh")?;
+        let config = Config {
+            procfile_path,
+            env_path: dir.path().join(".env"),
+            port: Some(6000),
+            ..Config::default()
+        };
+        let opts = |process: Option<&str>| EnvOpts {
+            process: process.map(String::from),
+            format: String::from("shell"),
+            is_diff: false,
+            env_path: None,
+            procfile_path: None,
+            port: None,
+            profile: None,
+        };
+
+        let env = effective_env(&opts(None), &config)?;
+        assert_eq!(env.get("PATH"), std_env::var("PATH").ok().as_ref());
+
+        let env = effective_env(&opts(Some("web.2")), &config)?;
+        assert_eq!(env.get("PATH"), std_env::var("PATH").ok().as_ref());
+        assert_eq!(env.get("PORT").unwrap(), "6001");
+        assert_eq!(env.get("PS").unwrap(), "web.2");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_instance() -> anyhow::Result<()> {
+        assert_eq!(parse_instance("web").unwrap(), ("web", 0));
+        assert_eq!(parse_instance("web.1").unwrap(), ("web", 0));
+        assert_eq!(parse_instance("web.3").unwrap(), ("web", 2));
+        assert!(parse_instance("web.0").is_err());
+        assert!(parse_instance("web.x").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_env() -> anyhow::Result<()> {
+        let base = env_of(&[("HOME", "/root"), ("PORT", "3000")]);
+        let env = env_of(&[("HOME", "/root"), ("PORT", "5000"), ("PS", "web.1")]);
+        let result = diff_env(&base, &env);
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(
+            result.get("PORT").unwrap(),
+            &Change::Changed {
+                from: String::from("3000"),
+                to: String::from("5000")
+            }
+        );
+        assert_eq!(
+            result.get("PS").unwrap(),
+            &Change::Added(String::from("web.1"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_env() -> anyhow::Result<()> {
+        let env = env_of(&[("PS", "web.1"), ("GREETING", "hello world")]);
+
+        assert_eq!(
+            format_env(&env, "shell"),
+            "export GREETING=hello\\ world\nexport PS=web.1"
+        );
+        assert_eq!(
+            format_env(&env, "dotenv"),
+            "GREETING=\"hello world\"\nPS=\"web.1\""
+        );
+        let parsed: Json = serde_json::from_str(&format_env(&env, "json"))?;
+        assert_eq!(parsed, json!({ "GREETING": "hello world", "PS": "web.1" }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_diff() -> anyhow::Result<()> {
+        let base = env_of(&[("PORT", "3000")]);
+        let env = env_of(&[("PORT", "5000"), ("PS", "web.1")]);
+        let diff = diff_env(&base, &env);
+
+        assert_eq!(
+            format_diff(&diff, "shell"),
+            "# changed from 3000\nexport PORT=5000\n# added\nexport PS=web.1"
+        );
+        let parsed: Json = serde_json::from_str(&format_diff(&diff, "json"))?;
+        assert_eq!(
+            parsed,
+            json!({
+                "added": { "PS": "web.1" },
+                "changed": { "PORT": { "from": "3000", "to": "5000" } }
+            })
+        );
+
+        Ok(())
+    }
+}
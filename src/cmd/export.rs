@@ -0,0 +1,10 @@
+use ultraman::config::{load_config, RawConfig};
+use ultraman::error::Result;
+use ultraman::export::{self, ExportOpts};
+
+pub fn run(input_opts: ExportOpts) -> Result<()> {
+    let config = load_config(RawConfig::from(&input_opts))?;
+    let opts = export::resolved_opts(&input_opts, config);
+    let exporter = export::new(&opts)?;
+    exporter.export()
+}
@@ -0,0 +1,86 @@
+use ultraman::config::{load_config, RawConfig};
+use ultraman::control::{socket_path, Logs, Request};
+use ultraman::error::Result;
+use ultraman::{ColorChoice, LogFormat, TimestampFormat};
+
+use std::path::PathBuf;
+use structopt::{clap, StructOpt};
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting(clap::AppSettings::ColoredHelp))]
+pub struct LogsOpts {
+    /// Processes or instances to show (e.g. web or web.2), every process if none are given
+    #[structopt(name = "PROCESS")]
+    pub names: Vec<String>,
+
+    /// Specify the number of recent lines of each instance to show
+    #[structopt(name = "LINES", short = "n", long = "lines", default_value = "100")]
+    pub lines: usize,
+
+    /// Keep printing new lines until `ultraman start` exits
+    #[structopt(short = "f", long = "follow")]
+    pub is_follow: bool,
+
+    /// Specify the Procfile `ultraman start` was started with, which picks its control socket
+    #[structopt(name = "PROCFILE", long = "procfile", parse(from_os_str))]
+    pub procfile_path: Option<PathBuf>,
+
+    /// Specify the control socket of `ultraman start` (if it was started with --socket)
+    #[structopt(name = "SOCKET", long = "socket", parse(from_os_str))]
+    pub socket_path: Option<PathBuf>,
+
+    /// Include timestamp in output
+    #[structopt(name = "NOTIMESTAMP", long = "no-timestamp")]
+    pub is_no_timestamp: Option<bool>,
+
+    /// Specify how timestamps are written: time, time-ms, iso8601, utc, relative or a strftime format (prefix it with utc: for UTC)
+    #[structopt(name = "TIMESTAMP_FORMAT", long = "timestamp-format")]
+    pub timestamp_format: Option<TimestampFormat>,
+
+    /// When to color output: auto colors it when stdout is a terminal (and honors NO_COLOR and CLICOLOR_FORCE)
+    #[structopt(name = "COLOR", long = "color", possible_values = &["auto", "always", "never"])]
+    pub color: Option<ColorChoice>,
+
+    /// How lines are written: text, one JSON object per line or logfmt
+    #[structopt(name = "LOG_FORMAT", long = "log-format", possible_values = &["text", "json", "logfmt"])]
+    pub log_format: Option<LogFormat>,
+
+    /// Use the named profile from .ultraman (also ULTRAMAN_PROFILE)
+    #[structopt(name = "PROFILE", long = "profile")]
+    pub profile: Option<String>,
+}
+
+pub fn run(input_opts: LogsOpts) -> Result<()> {
+    let opts = load_config(RawConfig::from(&input_opts))?;
+    let socket = match opts.socket_path {
+        Some(path) => path,
+        None => socket_path(&opts.procfile_path)?,
+    };
+
+    Logs::new(socket)
+        .request(Request {
+            names: input_opts.names,
+            lines: input_opts.lines,
+            follow: input_opts.is_follow,
+        })
+        .timestamp(!opts.is_no_timestamp)
+        .timestamp_format(opts.timestamp_format)
+        .color(opts.color)
+        .log_format(opts.log_format)
+        .run()
+}
+
+impl From<&LogsOpts> for RawConfig {
+    fn from(opts: &LogsOpts) -> Self {
+        RawConfig {
+            procfile: opts.procfile_path.clone(),
+            socket: opts.socket_path.clone(),
+            no_timestamp: opts.is_no_timestamp,
+            timestamp_format: opts.timestamp_format.clone(),
+            color: opts.color,
+            log_format: opts.log_format,
+            profile: opts.profile.clone(),
+            ..RawConfig::default()
+        }
+    }
+}
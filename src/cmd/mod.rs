@@ -1,5 +1,8 @@
 pub mod check;
 pub mod completion;
+pub mod config;
+pub mod env;
 pub mod export;
+pub mod logs;
 pub mod run;
-pub mod start;
\ No newline at end of file
+pub mod start;
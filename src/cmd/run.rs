@@ -1,22 +1,24 @@
-use crate::config::{read_config, Config};
-use crate::env::read_env;
-use crate::procfile::read_procfile;
-
-use nix::sys::wait::WaitStatus;
-use nix::unistd::{fork, pause, ForkResult};
-use nix::{self};
-use std::env::{self as std_env};
+use ultraman::config::{load_config, Config, RawConfig};
+use ultraman::env::{read_env, Env};
+use ultraman::error::{Result, UltramanError};
+use ultraman::procfile::{read_procfile, Procfile};
+use ultraman::{env_for, port_for, Runner};
+
+use shellwords::join;
+use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
-use std::process::{exit, Command};
-use std::thread;
+use std::process::Command;
 use structopt::{clap, StructOpt};
 
 #[derive(StructOpt, Debug)]
-#[structopt(setting(clap::AppSettings::ColoredHelp))]
+#[structopt(
+    setting(clap::AppSettings::ColoredHelp),
+    setting(clap::AppSettings::TrailingVarArg)
+)]
 pub struct RunOpts {
-    /// App Name
-    #[structopt(name = "APP_NAME")]
-    pub app_name: String,
+    /// A process in the Procfile (e.g. web), or a command (e.g. rails console). Everything after it is passed to the command
+    #[structopt(name = "COMMAND", required = true)]
+    pub command: Vec<String>,
 
     /// Specify an environment file to load
     #[structopt(name = "ENV", short = "e", long = "env", parse(from_os_str))]
@@ -25,90 +27,138 @@ pub struct RunOpts {
     /// Specify an Procfile to load
     #[structopt(name = "PROCFILE", short = "f", long = "procfile", parse(from_os_str))]
     pub procfile_path: Option<PathBuf>,
+
+    /// Specify which port to use as PORT
+    #[structopt(name = "PORT", short = "p", long = "port")]
+    pub port: Option<u32>,
+
+    /// Use the named profile from .ultraman (also ULTRAMAN_PROFILE)
+    #[structopt(name = "PROFILE", long = "profile")]
+    pub profile: Option<String>,
+
+    /// Specify the shell the command is run with
+    #[structopt(name = "SHELL", long = "shell", parse(from_os_str))]
+    pub shell: Option<PathBuf>,
+
+    /// Run the command directly, split into words, instead of with the shell
+    #[structopt(long = "exec")]
+    pub is_exec: bool,
 }
 
-pub fn run(input_opts: RunOpts) {
-    let dotconfig = read_config(PathBuf::from(".ultraman")).unwrap();
-    let opts = merged_opts(&input_opts, dotconfig);
-
-    let app_name = opts.app_name;
-    let procfile_path = opts.procfile_path.unwrap();
-    let env_path = opts.env_path.unwrap();
-
-    let procfile = read_procfile(procfile_path).expect("failed read Procfile");
-    let pe = procfile.find_by(&app_name);
-
-    let mut read_env = read_env(env_path).expect("failed read .env");
-    read_env.insert(String::from("PORT"), String::from("5000"));
-    read_env.insert(String::from("PS"), String::from(&app_name));
-
-    let shell = std_env::var("SHELL").expect("$SHELL is not set");
-
-    unsafe {
-        match fork() {
-            Ok(fork_result) => match fork_result {
-                ForkResult::Child => {
-                    let _ = Command::new(shell)
-                        .arg("-c")
-                        .arg(&pe.command)
-                        .envs(read_env)
-                        .spawn()
-                        .expect("failed execute command");
-                    // we need the child to stay alive until the parent calls read
-                    pause();
-                }
-                ForkResult::Parent { child } => {
-                    let check_for_child_termination_thread = thread::Builder::new()
-                        .name(String::from("check_for_child_termination_thread"))
-                        .spawn(move || {
-                            if let Ok(exit_status) = nix::sys::wait::waitpid(child, None) {
-                                match exit_status {
-                                    WaitStatus::Exited(_, code) => exit(code),
-                                    _ => (),
-                                }
-                            }
-                        })
-                        .expect("failed spawn check_for_child_termination");
-
-                    check_for_child_termination_thread
-                        .join()
-                        .expect("failed join handle_signal_thread");
-                }
-            },
-            Err(e) => {
-                println!("failed ultraman run");
-                println!("error: {}", &e)
+// ultraman becomes the command, so it has the terminal, gets signals and exits as the
+// command does. Only returns if the command could not be run
+pub fn run(input_opts: RunOpts) -> Result<()> {
+    let opts = load_config(RawConfig::from(&input_opts))?;
+    // A command can be run without a Procfile
+    let procfile = match read_procfile(opts.procfile_path.clone()) {
+        Ok(procfile) => Some(procfile),
+        Err(UltramanError::ProcfileNotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    let env = env_for_run(&input_opts.command, procfile.as_ref(), &opts)?;
+    let (mut command, runner, cmd) =
+        command_for(&input_opts.command, procfile.as_ref(), &opts, &env)?;
+    command.envs(env);
+    let source = command.exec();
+    Err(runner.spawn_error(&cmd, source))
+}
+
+// e.g.)
+// web -> the command of web in the Procfile
+// web --verbose -> the command of web in the Procfile, then --verbose (quoted for the shell)
+// "echo $PORT" -> run with the shell
+// rails console -> run directly, as the words are already split
+fn command_for(
+    args: &[String],
+    procfile: Option<&Procfile>,
+    opts: &Config,
+    env: &Env,
+) -> Result<(Command, Runner, String)> {
+    let runner_for = |name: &str| {
+        let is_exec = opts
+            .exec
+            .iter()
+            .flat_map(|names| names.split(','))
+            .any(|n| n.trim() == name || n.trim() == "all");
+        if is_exec {
+            Runner::Exec
+        } else {
+            Runner::Shell(opts.shell.clone())
+        }
+    };
+
+    let pe = match (args, procfile) {
+        ([name, ..], Some(procfile)) => procfile.find_by(name).ok(),
+        _ => None,
+    };
+    match (args, pe) {
+        ([name, rest @ ..], Some(pe)) => {
+            let mut cmd = pe.command.clone();
+            if !rest.is_empty() {
+                let rest = rest.iter().map(String::as_str).collect::<Vec<_>>();
+                cmd = format!("{} {}", cmd, join(&rest));
             }
+            let runner = runner_for(name);
+            Ok((runner.command(&cmd, env)?, runner, cmd))
+        }
+        ([cmd], None) => {
+            let runner = runner_for("all");
+            Ok((runner.command(cmd, env)?, runner, cmd.clone()))
+        }
+        ([program, rest @ ..], None) => {
+            let mut command = Command::new(program);
+            command.args(rest);
+            Ok((command, Runner::Exec, args.join(" ")))
+        }
+        ([], _) => unreachable!("COMMAND is required"),
+    }
+}
+
+// The env file plus PORT, and PS for a process in the Procfile
+fn env_for_run(args: &[String], procfile: Option<&Procfile>, opts: &Config) -> Result<Env> {
+    match (args, procfile) {
+        ([name, ..], Some(procfile)) if procfile.find_by(name).is_ok() => {
+            env_for(name, opts.env_path.clone(), opts.port, 0)
+        }
+        _ => {
+            let mut env = read_env(opts.env_path.clone())?;
+            env.insert(
+                String::from("PORT"),
+                port_for(&opts.env_path, opts.port, 0)?.to_string(),
+            );
+            Ok(env)
         }
     }
 }
 
-fn merged_opts(input_opts: &RunOpts, dotconfig: Config) -> RunOpts {
-    RunOpts {
-        app_name: input_opts.app_name.to_string(),
-        env_path: match &input_opts.env_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => Some(dotconfig.env_path),
-        },
-        procfile_path: match &input_opts.procfile_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => Some(dotconfig.procfile_path),
-        },
+impl From<&RunOpts> for RawConfig {
+    fn from(opts: &RunOpts) -> Self {
+        RawConfig {
+            procfile: opts.procfile_path.clone(),
+            env: opts.env_path.clone(),
+            port: opts.port,
+            profile: opts.profile.clone(),
+            shell: opts.shell.clone(),
+            exec: Some(String::from("all")).filter(|_| opts.is_exec),
+            ..RawConfig::default()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use std::fs::File;
     use std::io::Write;
     use tempfile::tempdir;
+    use ultraman::config::{Config, ConfigLoader};
 
-    fn prepare_dotconfig() -> Config {
+    fn load_with_dotconfig(input_opts: &RunOpts) -> Config {
         let dir = tempdir().ok().unwrap();
         let file_path = dir.path().join(".ultraman");
         let mut file = File::create(file_path.clone()).ok().unwrap();
-        // Writing a comment causes a parse error
         writeln!(
             file,
             r#"
@@ -124,7 +174,7 @@ no-timestamp: true
 app: app-for-runit
 log: /var/app/log/ultraman.log
 run: /tmp/pids/ultraman.pid
-template: ../../src/cmd/export/templates/supervisord
+template: ../../src/export/templates/supervisord
 user: root
 root: /home/app
 
@@ -133,48 +183,116 @@ hoge: hogehoge
         )
         .unwrap();
 
-        let dotconfig = read_config(file_path).expect("failed read .ultraman");
-        dotconfig
+        ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::from(input_opts))
+            .expect("failed load .ultraman")
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_dotconfig() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_dotconfig() -> anyhow::Result<()> {
         let input_opts = RunOpts {
-            app_name: String::from("web"),
+            command: vec![String::from("web")],
             env_path: None,
             procfile_path: None,
+            port: None,
+            profile: None,
+            shell: None,
+            is_exec: false,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
 
-        assert_eq!(result.app_name, String::from("web"));
-        assert_eq!(result.env_path.unwrap(), PathBuf::from("./tmp/.env"));
-        assert_eq!(
-            result.procfile_path.unwrap(),
-            PathBuf::from("./tmp/Procfile")
-        );
+        assert_eq!(result.env_path, PathBuf::from("./tmp/.env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./tmp/Procfile"));
 
         Ok(())
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_input_opts() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_input_opts() -> anyhow::Result<()> {
         let input_opts = RunOpts {
-            app_name: String::from("web"),
+            command: vec![String::from("web")],
             env_path: Some(PathBuf::from("./test/.env")),
             procfile_path: Some(PathBuf::from("./test/Procfile")),
+            port: Some(9999),
+            profile: None,
+            shell: None,
+            is_exec: false,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
 
-        assert_eq!(result.app_name, String::from("web"));
-        assert_eq!(result.env_path.unwrap(), PathBuf::from("./test/.env"));
+        assert_eq!(result.env_path, PathBuf::from("./test/.env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./test/Procfile"));
+        assert_eq!(result.port.unwrap(), 9999);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_for() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("web: ./web.sh --port $PORT");
+        let opts = Config::default();
+        let env = Env::new();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let program_and_args = |command: &Command| {
+            let mut words = vec![command.get_program().to_string_lossy().into_owned()];
+            words.extend(
+                command
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().into_owned()),
+            );
+            words
+        };
+
+        let (command, _, cmd) = command_for(&args(&["web"]), Some(&procfile), &opts, &env)?;
+        assert_eq!(
+            program_and_args(&command),
+            ["/bin/sh", "-c", "./web.sh --port $PORT"]
+        );
+        assert_eq!(cmd, "./web.sh --port $PORT");
+
+        let (command, _, _) = command_for(&args(&["echo $PORT"]), Some(&procfile), &opts, &env)?;
+        assert_eq!(program_and_args(&command), ["/bin/sh", "-c", "echo $PORT"]);
+
+        let (command, runner, cmd) = command_for(&args(&["rails", "console"]), None, &opts, &env)?;
+        assert_eq!(program_and_args(&command), ["rails", "console"]);
+        assert_eq!(runner, Runner::Exec);
+        assert_eq!(cmd, "rails console");
+
+        // Arguments after a process in the Procfile are passed to its command
+        let (command, runner, cmd) = command_for(
+            &args(&["web", "--verbose", "it's"]),
+            Some(&procfile),
+            &opts,
+            &env,
+        )?;
         assert_eq!(
-            result.procfile_path.unwrap(),
-            PathBuf::from("./test/Procfile")
+            program_and_args(&command),
+            ["/bin/sh", "-c", "./web.sh --port $PORT --verbose it\\'s"]
         );
+        assert_eq!(runner, Runner::Shell(PathBuf::from("/bin/sh")));
+        assert_eq!(cmd, "./web.sh --port $PORT --verbose it\\'s");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_for_run() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("web: ./web.sh --port $PORT");
+        let opts = Config {
+            port: Some(6000),
+            ..Config::default()
+        };
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let env = env_for_run(&args(&["web", "--verbose"]), Some(&procfile), &opts)?;
+        assert_eq!(env.get("PS").map(String::as_str), Some("web.1"));
+        assert_eq!(env.get("PORT").map(String::as_str), Some("6000"));
+
+        let env = env_for_run(&args(&["rails", "console"]), Some(&procfile), &opts)?;
+        assert_eq!(env.get("PS"), None);
+        assert_eq!(env.get("PORT").map(String::as_str), Some("6000"));
 
         Ok(())
     }
@@ -1,18 +1,19 @@
-use crate::config::{read_config, Config};
-use crate::opt::DisplayOpts;
-use crate::output;
-use crate::process::{self, Process};
-use crate::procfile::read_procfile;
-use crate::signal;
+use ultraman::config::{load_config, RawConfig};
+use ultraman::control::socket_path;
+use ultraman::error::Result;
+use ultraman::procfile::read_procfile;
+use ultraman::{
+    ColorChoice, Decoding, LinePattern, LogFiles, LogFormat, Rotation, Stream, Supervisor,
+    TimestampFormat,
+};
 
 use std::path::PathBuf;
-use std::sync::{Arc, Barrier, Mutex};
 use structopt::{clap, StructOpt};
 
 #[derive(StructOpt, Debug)]
 #[structopt(setting(clap::AppSettings::ColoredHelp))]
 pub struct StartOpts {
-    /// Specify the number of each process type to run. The value passed in should be in the format process=num,process=num
+    /// Specify the number of each process type to run. The value passed in should be in the format process=num,process=num, where all=num sets the number for processes not listed
     #[structopt(name = "APP=NUMBER", short = "m", long = "formation")]
     pub formation: Option<String>,
 
@@ -35,141 +36,241 @@ pub struct StartOpts {
     /// Include timestamp in output
     #[structopt(name = "NOTIMESTAMP", short = "n", long = "no-timestamp")]
     pub is_no_timestamp: Option<bool>,
-}
 
-pub fn run(input_opts: StartOpts) -> Result<(), Box<dyn std::error::Error>> {
-    let mut proc_handles = vec![];
-    let procs: Arc<Mutex<Vec<Arc<Mutex<process::Process>>>>> = Arc::new(Mutex::new(vec![]));
-    let dotconfig = read_config(PathBuf::from(".ultraman")).unwrap();
-    let opts = merged_opts(&input_opts, dotconfig);
-
-    let procfile = read_procfile(opts.procfile_path.unwrap()).expect("failed read Procfile");
-    // Read the formation from the command line option and always call it before process_len for the convenience of setting concurrency
-    procfile.set_concurrency(&opts.formation.unwrap());
-
-    let process_len = procfile.process_len();
-    let padding = procfile.padding();
-
-    let barrier = Arc::new(Barrier::new(process_len + 1));
-    let mut total = 0;
-    let is_timestamp = !opts.is_no_timestamp.unwrap();
-    let display_opts = DisplayOpts {
-        padding,
-        is_timestamp,
-    };
+    /// Specify how timestamps are written: time, time-ms, iso8601, utc, relative or a strftime format (prefix it with utc: for UTC)
+    #[structopt(name = "TIMESTAMP_FORMAT", long = "timestamp-format")]
+    pub timestamp_format: Option<TimestampFormat>,
 
-    for (name, pe) in procfile.data.iter() {
-        let con = pe.concurrency.get();
-        let app_index = total;
-        let output = Arc::new(output::Output::new(app_index, display_opts.clone()));
-        total += 1;
-
-        for n in 0..con {
-            let barrier = barrier.clone();
-            let procs = procs.clone();
-            let output = output.clone();
-            let process_name = name.clone();
-            let cmd = pe.command.clone();
-            let env_path = opts.env_path.clone();
-            let port = opts.port.clone();
-            let opts = display_opts.clone();
-
-            let exec_and_output_thread = process::build_exec_and_output_thread(move || {
-                let proc = Process::new(
-                    &process_name,
-                    &cmd,
-                    env_path.unwrap(),
-                    port,
-                    n,
-                    app_index,
-                    Some(opts),
-                );
-                let proc2 = Arc::new(Mutex::new(proc));
-                let proc3 = Arc::clone(&proc2);
-                let child_id = proc2.lock().unwrap().child.id() as i32;
-
-                output.log.output(
-                    "system",
-                    &format!(
-                        "{0:1$} start at pid: {2}",
-                        &proc2.lock().unwrap().name,
-                        padding,
-                        &child_id
-                    ),
-                );
-
-                procs.lock().unwrap().push(proc2);
-                barrier.wait();
-
-                output.handle_output(&proc3);
-            });
-            proc_handles.push(exec_and_output_thread);
-        }
-    }
+    /// Use the named profile from .ultraman (also ULTRAMAN_PROFILE)
+    #[structopt(name = "PROFILE", long = "profile")]
+    pub profile: Option<String>,
+
+    /// How output that is not valid UTF-8 is shown: replaced with �, escaped as \xNN or written as it is
+    #[structopt(name = "DECODE", long = "decode", possible_values = &["lossy", "hex", "raw"])]
+    pub decode: Option<Decoding>,
+
+    /// When to color output: auto colors it when stdout is a terminal (and honors NO_COLOR and CLICOLOR_FORCE)
+    #[structopt(name = "COLOR", long = "color", possible_values = &["auto", "always", "never"])]
+    pub color: Option<ColorChoice>,
+
+    /// How lines are written: text, one JSON object per line or logfmt
+    #[structopt(name = "LOG_FORMAT", long = "log-format", possible_values = &["text", "json", "logfmt"])]
+    pub log_format: Option<LogFormat>,
+
+    /// Specify the directory to place process logs in. Each instance also writes to its own file (e.g. web.1.log)
+    #[structopt(name = "LOG_DIR", long = "log-dir", parse(from_os_str))]
+    pub log_dir: Option<PathBuf>,
+
+    /// When log files are rotated: never, daily, hourly or once they reach a size (e.g. 10M)
+    #[structopt(name = "ROTATE", long = "log-rotate")]
+    pub log_rotate: Option<Rotation>,
+
+    /// Specify the number of rotated log files to keep
+    #[structopt(name = "KEEP", long = "log-keep")]
+    pub log_keep: Option<usize>,
+
+    /// Compress rotated log files with gzip
+    #[structopt(name = "GZIP", long = "log-gzip")]
+    pub log_gzip: Option<bool>,
+
+    /// Don't print what these processes write to stdout. The value passed in should be in the format process,process
+    #[structopt(name = "HIDE_STDOUT", long = "hide-stdout")]
+    pub hide_stdout: Option<String>,
 
-    barrier.wait();
+    /// Don't print what these processes write to stderr. The value passed in should be in the format process,process
+    #[structopt(name = "HIDE_STDERR", long = "hide-stderr")]
+    pub hide_stderr: Option<String>,
 
-    // use handle_signal
-    let procs2 = Arc::clone(&procs);
-    let check_for_child_termination_thread =
-        process::build_check_for_child_termination_thread(procs2, display_opts.clone());
-    proc_handles.push(check_for_child_termination_thread);
+    /// Only print what these processes write (the others keep running). The value passed in should be in the format process,process
+    #[structopt(name = "ONLY", long = "only")]
+    pub only: Option<String>,
 
-    let procs = Arc::clone(&procs);
-    proc_handles.push(signal::handle_signal_thread(
-        procs,
-        opts.timeout.unwrap(),
-        display_opts,
-    ));
+    /// Don't print what these processes write (they keep running). The value passed in should be in the format process,process
+    #[structopt(name = "QUIET", long = "quiet")]
+    pub quiet: Option<String>,
 
-    for handle in proc_handles {
-        handle.join().expect("failed join");
+    /// Only print lines of a process that match the regex. The value passed in should be in the format process=regex, and may be given several times
+    #[structopt(name = "INCLUDE", long = "include", number_of_values = 1)]
+    pub include: Vec<LinePattern>,
+
+    /// Don't print lines of a process that match the regex. The value passed in should be in the format process=regex, and may be given several times
+    #[structopt(name = "EXCLUDE", long = "exclude", number_of_values = 1)]
+    pub exclude: Vec<LinePattern>,
+
+    /// Specify the number of lines of each process kept for `ultraman logs`
+    #[structopt(name = "HISTORY", long = "history")]
+    pub history: Option<usize>,
+
+    /// Specify the control socket `ultraman logs` connects to (by default one for the Procfile)
+    #[structopt(name = "SOCKET", long = "socket", parse(from_os_str))]
+    pub socket_path: Option<PathBuf>,
+
+    /// Run processes on a pseudo-terminal of their own, so they color and line-buffer their output as in a terminal (stderr is printed as stdout). Every process, or these ones in the format process,process
+    #[structopt(name = "PTY", long = "pty")]
+    pub pty: Option<Option<String>>,
+
+    /// Specify the shell commands are run with
+    #[structopt(name = "SHELL", long = "shell", parse(from_os_str))]
+    pub shell: Option<PathBuf>,
+
+    /// Run commands directly, split into words, instead of with the shell (so they can't use pipes or &&, but $VARIABLES are expanded). Every process, or these ones in the format process,process
+    #[structopt(name = "EXEC", long = "exec")]
+    pub exec: Option<Option<String>>,
+
+    /// Show processes in an interactive dashboard instead of printing their output
+    #[structopt(long = "tui")]
+    pub is_tui: bool,
+
+    /// Write what is typed to this process (e.g. web, or web.2 for an instance) instead of ignoring it. Ctrl-] and a process name, then Enter, switches it
+    #[structopt(name = "ATTACH", long = "attach", conflicts_with = "is-tui")]
+    pub attach: Option<String>,
+}
+
+pub fn run(input_opts: StartOpts) -> Result<()> {
+    let opts = load_config(RawConfig::from(&input_opts))?;
+    let procfile = read_procfile(opts.procfile_path.clone())?;
+
+    let defined = procfile.data.keys().cloned().collect::<Vec<_>>();
+    let socket = match opts.socket_path {
+        Some(path) => path,
+        None => socket_path(&opts.procfile_path)?,
+    };
+    let mut supervisor = Supervisor::new(procfile)
+        .formation(opts.formation.parse()?)
+        .env_path(opts.env_path.clone())
+        .port(opts.port)
+        .timeout(opts.timeout)
+        .timestamp(!opts.is_no_timestamp)
+        .timestamp_format(opts.timestamp_format)
+        .decoding(opts.decoding)
+        .color(opts.color)
+        .log_format(opts.log_format)
+        .history(opts.history)
+        .control_socket(socket)
+        .shell(opts.shell);
+    for (names, stream) in [
+        (&opts.hide_stdout, Stream::Stdout),
+        (&opts.hide_stderr, Stream::Stderr),
+    ] {
+        for name in names.iter().flat_map(|names| process_names(names)) {
+            supervisor = supervisor.hide(name, stream);
+        }
+    }
+    for name in opts.only.iter().flat_map(|names| process_names(names)) {
+        supervisor = supervisor.only(name);
+    }
+    for name in opts.quiet.iter().flat_map(|names| process_names(names)) {
+        supervisor = supervisor.quiet(name);
+    }
+    for name in opts
+        .pty
+        .iter()
+        .flat_map(|names| every_process(names, &defined))
+    {
+        supervisor = supervisor.pty(name);
+    }
+    for name in opts
+        .exec
+        .iter()
+        .flat_map(|names| every_process(names, &defined))
+    {
+        supervisor = supervisor.exec(name);
+    }
+    for pattern in input_opts.include {
+        supervisor = supervisor.include(&pattern.process, pattern.regex);
     }
+    for pattern in input_opts.exclude {
+        supervisor = supervisor.exclude(&pattern.process, pattern.regex);
+    }
+    if let Some(dir) = opts.log_dir {
+        supervisor = supervisor.log_files(LogFiles {
+            dir,
+            rotation: opts.log_rotation,
+            keep: opts.log_keep,
+            is_gzip: opts.is_log_gzip,
+        });
+    }
+    if let Some(name) = &input_opts.attach {
+        supervisor = supervisor.attach(name);
+    }
+    if input_opts.is_tui {
+        supervisor.run_tui()
+    } else {
+        supervisor.run()
+    }
+}
 
-    Ok(())
+// e.g.) "web, worker" -> ["web", "worker"]
+fn process_names(names: &str) -> Vec<&str> {
+    names
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .collect()
 }
 
-fn merged_opts(input_opts: &StartOpts, dotconfig: Config) -> StartOpts {
-    StartOpts {
-        formation: match &input_opts.formation {
-            Some(r) => Some(r.to_string()),
-            None => Some(dotconfig.formation),
-        },
-        env_path: match &input_opts.env_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => Some(dotconfig.env_path),
-        },
-        procfile_path: match &input_opts.procfile_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => Some(dotconfig.procfile_path),
-        },
-        timeout: match &input_opts.timeout {
-            Some(r) => Some(*r),
-            None => Some(dotconfig.timeout),
-        },
-        is_no_timestamp: match &input_opts.is_no_timestamp {
-            Some(r) => Some(r.clone()),
-            None => Some(dotconfig.is_no_timestamp),
-        },
-        port: match &input_opts.port {
-            Some(r) => Some(*r),
-            None => dotconfig.port.map(|r| r as u32),
-        },
+// e.g.) "all" -> every process defined in the Procfile
+fn every_process<'a>(names: &'a str, defined: &'a [String]) -> Vec<&'a str> {
+    let names = process_names(names);
+    if names.contains(&"all") {
+        defined.iter().map(|name| name.as_str()).collect()
+    } else {
+        names
+    }
+}
+
+impl From<&StartOpts> for RawConfig {
+    fn from(opts: &StartOpts) -> Self {
+        RawConfig {
+            procfile: opts.procfile_path.clone(),
+            env: opts.env_path.clone(),
+            formation: opts.formation.clone(),
+            timeout: opts.timeout,
+            no_timestamp: opts.is_no_timestamp,
+            timestamp_format: opts.timestamp_format.clone(),
+            port: opts.port,
+            profile: opts.profile.clone(),
+            decode: opts.decode,
+            color: opts.color,
+            log_format: opts.log_format,
+            log_dir: opts.log_dir.clone(),
+            log_rotate: opts.log_rotate,
+            log_keep: opts.log_keep,
+            log_gzip: opts.log_gzip,
+            hide_stdout: opts.hide_stdout.clone(),
+            hide_stderr: opts.hide_stderr.clone(),
+            only: opts.only.clone(),
+            quiet: opts.quiet.clone(),
+            history: opts.history,
+            socket: opts.socket_path.clone(),
+            // --pty alone is every process
+            pty: opts
+                .pty
+                .clone()
+                .map(|names| names.unwrap_or_else(|| String::from("all"))),
+            shell: opts.shell.clone(),
+            exec: opts
+                .exec
+                .clone()
+                .map(|names| names.unwrap_or_else(|| String::from("all"))),
+            ..RawConfig::default()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use std::fs::File;
     use std::io::Write;
     use tempfile::tempdir;
+    use ultraman::config::{Config, ConfigLoader};
 
-    fn prepare_dotconfig() -> Config {
+    fn load_with_dotconfig(input_opts: &StartOpts) -> Config {
         let dir = tempdir().ok().unwrap();
         let file_path = dir.path().join(".ultraman");
         let mut file = File::create(file_path.clone()).ok().unwrap();
-        // Writing a comment causes a parse error
         writeln!(
             file,
             r#"
@@ -181,11 +282,24 @@ port: 6000
 timeout: 5000
 
 no-timestamp: true
+timestamp-format: "utc:%H:%M:%S%.3f"
+decode: raw
+color: never
+log-format: json
+log-rotate: 10M
+log-keep: 3
+log-gzip: true
+hide-stderr: app
+quiet: app
+history: 50
+pty: web
+shell: /bin/bash
+exec: app
 
 app: app-for-runit
 log: /var/app/log/ultraman.log
 run: /tmp/pids/ultraman.pid
-template: ../../src/cmd/export/templates/supervisord
+template: ../../src/export/templates/supervisord
 user: root
 root: /home/app
 
@@ -194,12 +308,13 @@ hoge: hogehoge
         )
         .unwrap();
 
-        let dotconfig = read_config(file_path).expect("failed read .ultraman");
-        dotconfig
+        ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::from(input_opts))
+            .expect("failed load .ultraman")
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_dotconfig() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_dotconfig() -> anyhow::Result<()> {
         let input_opts = StartOpts {
             formation: None,
             env_path: None,
@@ -207,23 +322,69 @@ hoge: hogehoge
             port: None,
             timeout: None,
             is_no_timestamp: None,
+            timestamp_format: None,
+            profile: None,
+            decode: None,
+            color: None,
+            log_format: None,
+            log_dir: None,
+            log_rotate: None,
+            log_keep: None,
+            log_gzip: None,
+            hide_stdout: None,
+            hide_stderr: None,
+            only: None,
+            quiet: None,
+            include: vec![],
+            exclude: vec![],
+            history: None,
+            socket_path: None,
+            pty: None,
+            shell: None,
+            exec: None,
+            is_tui: false,
+            attach: None,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
 
-        assert_eq!(result.formation.unwrap(), "app=1,web=2");
-        assert_eq!(result.env_path.unwrap(), PathBuf::from(".env"));
-        assert_eq!(result.procfile_path.unwrap(), PathBuf::from("./Procfile"));
+        assert_eq!(result.formation, "app=1,web=2");
+        assert_eq!(result.env_path, PathBuf::from(".env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./Procfile"));
         assert_eq!(result.port.unwrap(), 6000);
-        assert_eq!(result.timeout.unwrap(), 5000);
-        assert_eq!(result.is_no_timestamp.unwrap(), true);
+        assert_eq!(result.timeout, 5000);
+        assert_eq!(result.is_no_timestamp, true);
+        assert_eq!(result.decoding, Decoding::Raw);
+        assert_eq!(
+            result.timestamp_format,
+            TimestampFormat::Custom {
+                format: String::from("%H:%M:%S%.3f"),
+                is_utc: true
+            }
+        );
+        assert_eq!(result.color, ColorChoice::Never);
+        assert_eq!(result.log_format, LogFormat::Json);
+        // `log` is the log file of export, which doesn't turn on log files for start
+        assert_eq!(
+            result.log_path.unwrap(),
+            PathBuf::from("/var/app/log/ultraman.log")
+        );
+        assert_eq!(result.log_dir, None);
+        assert_eq!(result.log_rotation, Rotation::Size(10 << 20));
+        assert_eq!(result.log_keep, 3);
+        assert!(result.is_log_gzip);
+        assert_eq!(result.hide_stderr.unwrap(), "app");
+        assert_eq!(result.quiet.unwrap(), "app");
+        assert_eq!(result.history, 50);
+        assert_eq!(result.pty.unwrap(), "web");
+        assert_eq!(result.shell, PathBuf::from("/bin/bash"));
+        assert_eq!(result.exec.unwrap(), "app");
 
         Ok(())
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_input_opts() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_input_opts() -> anyhow::Result<()> {
         let input_opts = StartOpts {
             formation: Some("app=2,web=2,server=2".to_string()),
             env_path: Some(PathBuf::from("./tmp/.env")),
@@ -231,20 +392,62 @@ hoge: hogehoge
             port: Some(9999),
             timeout: Some(1),
             is_no_timestamp: Some(false),
+            profile: None,
+            decode: Some(Decoding::Hex),
+            timestamp_format: Some(TimestampFormat::Relative),
+            color: Some(ColorChoice::Always),
+            log_format: Some(LogFormat::Logfmt),
+            log_dir: Some(PathBuf::from("./tmp/log")),
+            log_rotate: Some(Rotation::Daily),
+            log_keep: Some(7),
+            log_gzip: Some(false),
+            hide_stdout: Some(String::from("web,server")),
+            hide_stderr: None,
+            only: Some(String::from("web")),
+            quiet: None,
+            include: vec![],
+            exclude: vec![],
+            history: Some(20),
+            socket_path: Some(PathBuf::from("./tmp/ultraman.sock")),
+            pty: Some(None),
+            shell: Some(PathBuf::from("/bin/zsh")),
+            exec: Some(Some(String::from("web"))),
+            is_tui: false,
+            attach: None,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
 
-        assert_eq!(result.formation.unwrap(), "app=2,web=2,server=2");
-        assert_eq!(result.env_path.unwrap(), PathBuf::from("./tmp/.env"));
+        assert_eq!(result.formation, "app=2,web=2,server=2");
+        assert_eq!(result.env_path, PathBuf::from("./tmp/.env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./tmp/Procfile"));
+        assert_eq!(result.port.unwrap(), 9999);
+        assert_eq!(result.timeout, 1);
+        assert_eq!(result.is_no_timestamp, false);
+        assert_eq!(result.decoding, Decoding::Hex);
+        assert_eq!(result.timestamp_format, TimestampFormat::Relative);
+        assert_eq!(result.color, ColorChoice::Always);
+        assert_eq!(result.log_format, LogFormat::Logfmt);
+        assert_eq!(result.log_dir.unwrap(), PathBuf::from("./tmp/log"));
         assert_eq!(
-            result.procfile_path.unwrap(),
-            PathBuf::from("./tmp/Procfile")
+            result.log_path.unwrap(),
+            PathBuf::from("/var/app/log/ultraman.log")
         );
-        assert_eq!(result.port.unwrap(), 9999);
-        assert_eq!(result.timeout.unwrap(), 1);
-        assert_eq!(result.is_no_timestamp.unwrap(), false);
+        assert_eq!(result.log_rotation, Rotation::Daily);
+        assert_eq!(result.log_keep, 7);
+        assert!(!result.is_log_gzip);
+        assert_eq!(result.hide_stdout.unwrap(), "web,server");
+        assert_eq!(result.hide_stderr.unwrap(), "app");
+        assert_eq!(result.only.unwrap(), "web");
+        assert_eq!(result.history, 20);
+        assert_eq!(
+            result.socket_path.unwrap(),
+            PathBuf::from("./tmp/ultraman.sock")
+        );
+        assert_eq!(result.quiet.unwrap(), "app");
+        assert_eq!(result.pty.unwrap(), "all");
+        assert_eq!(result.shell, PathBuf::from("/bin/zsh"));
+        assert_eq!(result.exec.unwrap(), "web");
 
         Ok(())
     }
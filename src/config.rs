@@ -1,13 +1,24 @@
-extern crate yaml_rust;
+use crate::control::DEFAULT_HISTORY;
+use crate::log::file::{Rotation, DEFAULT_KEEP};
+use crate::log::{ColorChoice, LogFormat, TimestampFormat};
+use crate::output::Decoding;
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
-use yaml_rust::YamlLoader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-pub const DEFAULT_FORMATION: &'static str = "all=1";
-const DEFAULT_ENV: &'static str = ".env";
-const DEFAULT_PROCFILE: &'static str = "Procfile";
-const DEFAULT_TIMEOUT: u64 = 5;
+pub const DEFAULT_FORMATION: &str = "all=1";
+pub const DEFAULT_ENV: &str = ".env";
+const DEFAULT_PROCFILE: &str = "Procfile";
+pub const DEFAULT_TIMEOUT: u64 = 5;
+pub const DEFAULT_SHELL: &str = "/bin/sh";
 const DEFAULT_NO_TIMESTAMP: bool = false;
+const PROJECT_CONFIG: &str = ".ultraman";
+const ENV_PREFIX: &str = "ULTRAMAN_";
+const PROFILES_KEY: &str = "profiles";
 
 #[derive(Debug)]
 pub struct Config {
@@ -16,26 +27,247 @@ pub struct Config {
     pub formation: String,
     pub timeout: u64,
     pub is_no_timestamp: bool,
-    pub port: Option<i64>,
+    pub timestamp_format: TimestampFormat,
+    pub port: Option<u32>,
     pub app: Option<String>,
     pub log_path: Option<PathBuf>,
     pub run_path: Option<PathBuf>,
     pub template_path: Option<PathBuf>,
     pub user: Option<String>,
     pub root_path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub decoding: Decoding,
+    pub color: ColorChoice,
+    pub log_format: LogFormat,
+    // where `ultraman start` writes a file per instance, unlike `log` which is for export
+    pub log_dir: Option<PathBuf>,
+    pub log_rotation: Rotation,
+    pub log_keep: usize,
+    pub is_log_gzip: bool,
+    // comma separated process names
+    pub hide_stdout: Option<String>,
+    pub hide_stderr: Option<String>,
+    pub only: Option<String>,
+    pub quiet: Option<String>,
+    pub history: usize,
+    pub socket_path: Option<PathBuf>,
+    // comma separated process names, or all
+    pub pty: Option<String>,
+    pub shell: PathBuf,
+    // comma separated process names, or all
+    pub exec: Option<String>,
+    pub origins: BTreeMap<&'static str, Origin>,
+    pub warnings: Vec<ConfigWarning>,
 }
 
-// Ultraman settings read and parse .ultraman written in yaml
-pub fn read_config(filepath: PathBuf) -> Result<Config, Box<dyn std::error::Error>> {
-    let config: Config;
+// RawConfig, KNOWN_KEYS, Config::apply, Config::entries and the ULTRAMAN_* variables are
+// generated from one row per key, so a new key can't be missing from any of them:
+//
+// raw_field: Type = "key" => value|option config_field, how its variable is read;
+//
+// `option` fields of Config stay None until a layer sets them
+macro_rules! config_keys {
+    ($($raw:ident: $ty:ty = $key:literal => $kind:ident $field:ident, $parse:ident $(($expected:literal))?;)*) => {
+        // Keys that may appear in .ultraman (and in each of its profiles)
+        const KNOWN_KEYS: &[&str] = &[$($key),*];
 
-    if !filepath.exists() {
-        config = Config {
+        // One layer of settings (a config file, the environment or command line flags).
+        // Every key is optional and keys that are not set leave the lower layers untouched.
+        #[derive(Deserialize, Debug, Default, Clone)]
+        pub struct RawConfig {
+            $(
+                #[serde(rename = $key)]
+                pub $raw: Option<$ty>,
+            )*
+            // Named sets of overrides, e.g.) profiles: { ci: { formation: all=2 } }
+            pub profiles: Option<BTreeMap<String, RawConfig>>,
+        }
+
+        impl Config {
+            // Overwrite every key that is set in the layer and remember where it came from
+            pub fn apply<F>(&mut self, layer: RawConfig, origin_of: F)
+            where
+                F: Fn(&'static str) -> Origin,
+            {
+                $(
+                    if let Some(value) = layer.$raw {
+                        self.origins.insert($key, origin_of($key));
+                        config_keys!(@set $kind self.$field, value);
+                    }
+                )*
+            }
+
+            // Effective values in .ultraman notation, unset keys are None
+            pub fn entries(&self) -> Vec<(&'static str, Option<String>)> {
+                vec![$(($key, config_keys!(@show $kind self.$field)),)*]
+            }
+        }
+
+        impl ConfigLoader {
+            fn env_layer(&self) -> Result<RawConfig, ConfigError> {
+                Ok(RawConfig {
+                    $($raw: self.$parse($key $(, $expected)?)?,)*
+                    profiles: None,
+                })
+            }
+        }
+    };
+    (@set value $field:expr, $value:expr) => {
+        $field = $value
+    };
+    (@set option $field:expr, $value:expr) => {
+        $field = Some($value)
+    };
+    (@show value $field:expr) => {
+        Some(ConfigValue::show(&$field))
+    };
+    (@show option $field:expr) => {
+        $field.as_ref().map(ConfigValue::show)
+    };
+}
+
+config_keys! {
+    procfile: PathBuf = "procfile" => value procfile_path, var;
+    env: PathBuf = "env" => value env_path, var;
+    formation: String = "formation" => value formation, var;
+    timeout: u64 = "timeout" => value timeout, parse_var("an integer");
+    no_timestamp: bool = "no-timestamp" => value is_no_timestamp, parse_flag;
+    port: u32 = "port" => option port, parse_var("a port number");
+    app: String = "app" => option app, var;
+    log: PathBuf = "log" => option log_path, var;
+    run: PathBuf = "run" => option run_path, var;
+    template: PathBuf = "template" => option template_path, var;
+    user: String = "user" => option user, var;
+    root: PathBuf = "root" => option root_path, var;
+    profile: String = "profile" => option profile, var;
+    decode: Decoding = "decode" => value decoding, parse_var("lossy, hex or raw");
+    timestamp_format: TimestampFormat = "timestamp-format" => value timestamp_format,
+        parse_var("time, time-ms, iso8601, utc, relative or a strftime format");
+    color: ColorChoice = "color" => value color, parse_var("auto, always or never");
+    log_format: LogFormat = "log-format" => value log_format, parse_var("text, json or logfmt");
+    log_dir: PathBuf = "log-dir" => option log_dir, var;
+    log_rotate: Rotation = "log-rotate" => value log_rotation,
+        parse_var("never, daily, hourly or a size such as 10M");
+    log_keep: usize = "log-keep" => value log_keep, parse_var("an integer");
+    log_gzip: bool = "log-gzip" => value is_log_gzip, parse_flag;
+    hide_stdout: String = "hide-stdout" => option hide_stdout, var;
+    hide_stderr: String = "hide-stderr" => option hide_stderr, var;
+    only: String = "only" => option only, var;
+    quiet: String = "quiet" => option quiet, var;
+    history: usize = "history" => value history, parse_var("an integer");
+    socket: PathBuf = "socket" => option socket_path, var;
+    pty: String = "pty" => option pty, var;
+    shell: PathBuf = "shell" => value shell, var;
+    exec: String = "exec" => option exec, var;
+}
+
+// How a value is written in .ultraman
+trait ConfigValue {
+    fn show(&self) -> String;
+}
+
+impl ConfigValue for PathBuf {
+    fn show(&self) -> String {
+        self.display().to_string()
+    }
+}
+
+macro_rules! display_config_value {
+    ($($ty:ty),*) => {
+        $(impl ConfigValue for $ty {
+            fn show(&self) -> String {
+                self.to_string()
+            }
+        })*
+    };
+}
+
+display_config_value!(
+    String,
+    bool,
+    u32,
+    u64,
+    usize,
+    Decoding,
+    TimestampFormat,
+    ColorChoice,
+    LogFormat,
+    Rotation
+);
+// Where the effective value of a key came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Profile { path: PathBuf, name: String },
+    Env(String),
+    Cli,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "{}", path.display()),
+            Origin::Profile { path, name } => write!(f, "{} (profile {})", path.display(), name),
+            Origin::Env(name) => write!(f, "${}", name),
+            Origin::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub origin: Origin,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl std::error::Error for ConfigError {}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "{}:{}:{}: {}", self.origin, line, column, self.message)
+            }
+            _ => write!(f, "{}: {}", self.origin, self.message),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigWarning {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub key: String,
+    pub suggestion: Option<&'static str>,
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: ", self.path.display(), line)?,
+            None => write!(f, "{}: ", self.path.display())?,
+        }
+        write!(f, "unknown key `{}`", self.key)?;
+        if let Some(suggestion) = self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
             procfile_path: PathBuf::from(DEFAULT_PROCFILE),
             env_path: PathBuf::from(DEFAULT_ENV),
             formation: DEFAULT_FORMATION.to_string(),
             timeout: DEFAULT_TIMEOUT,
             is_no_timestamp: DEFAULT_NO_TIMESTAMP,
+            timestamp_format: TimestampFormat::default(),
             port: None,
             app: None,
             log_path: None,
@@ -43,65 +275,331 @@ pub fn read_config(filepath: PathBuf) -> Result<Config, Box<dyn std::error::Erro
             template_path: None,
             user: None,
             root_path: None,
+            profile: None,
+            decoding: Decoding::default(),
+            color: ColorChoice::default(),
+            log_format: LogFormat::default(),
+            log_dir: None,
+            log_rotation: Rotation::default(),
+            log_keep: DEFAULT_KEEP,
+            is_log_gzip: false,
+            hide_stdout: None,
+            hide_stderr: None,
+            only: None,
+            quiet: None,
+            history: DEFAULT_HISTORY,
+            socket_path: None,
+            pty: None,
+            shell: PathBuf::from(DEFAULT_SHELL),
+            exec: None,
+            origins: KNOWN_KEYS
+                .iter()
+                .map(|key| (*key, Origin::Default))
+                .collect(),
+            warnings: vec![],
+        }
+    }
+}
+
+impl Config {
+    pub fn origin(&self, key: &str) -> &Origin {
+        self.origins.get(key).unwrap_or(&Origin::Default)
+    }
+}
+
+impl RawConfig {
+    // Relative paths in a config file are relative to the directory that contains it
+    fn rebase(&mut self, dir: &Path) {
+        for path in [
+            &mut self.procfile,
+            &mut self.env,
+            &mut self.log,
+            &mut self.log_dir,
+            &mut self.run,
+            &mut self.template,
+            &mut self.root,
+            &mut self.socket,
+        ] {
+            if let Some(p) = path.as_mut() {
+                if p.is_relative() {
+                    *p = dir.join(&p);
+                }
+            }
+        }
+        if let Some(profiles) = self.profiles.as_mut() {
+            for profile in profiles.values_mut() {
+                profile.rebase(dir);
+            }
+        }
+    }
+
+    fn profile_names(&self) -> Vec<&str> {
+        self.profiles
+            .iter()
+            .flat_map(|profiles| profiles.keys())
+            .map(|name| name.as_str())
+            .collect()
+    }
+}
+
+// Resolves the effective settings from every layer, lowest priority first:
+// built-in defaults, $XDG_CONFIG_HOME/ultraman/config.yml, the nearest .ultraman
+// walking up from the current directory, ULTRAMAN_* environment variables and command line flags.
+pub struct ConfigLoader {
+    cwd: PathBuf,
+    vars: HashMap<String, String>,
+}
+
+impl ConfigLoader {
+    pub fn new(cwd: PathBuf, vars: HashMap<String, String>) -> Self {
+        ConfigLoader { cwd, vars }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(
+            env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            env::vars().collect(),
+        )
+    }
+
+    pub fn load(&self, cli: RawConfig) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        let mut files = vec![];
+        for path in [self.global_path(), self.project_path()].iter().flatten() {
+            let (mut layer, warnings) = read_layer(path)?;
+            if let Some(dir) = path.parent() {
+                if dir != self.cwd {
+                    layer.rebase(dir);
+                }
+            }
+            config.warnings.extend(warnings);
+            files.push((path.clone(), layer));
         }
-    } else {
-        let filepath_str = filepath.to_str().unwrap();
-        let config_str = fs::read_to_string(&filepath)
-            .expect(&format!("Failed to read the file: {}", &filepath_str));
-        let docs = YamlLoader::load_from_str(&config_str)
-            .expect(&format!("Failed to parse yaml file: {}", &filepath_str));
-        let doc = &docs[0];
-
-        config = Config {
-            procfile_path: match doc["procfile"].as_str() {
-                Some(r) => PathBuf::from(r),
-                None => PathBuf::from(DEFAULT_PROCFILE),
-            },
-            env_path: match doc["env"].as_str() {
-                Some(r) => PathBuf::from(r),
-                None => PathBuf::from(DEFAULT_ENV),
-            },
-            formation: match doc["formation"].as_str() {
-                Some(r) => r.to_string(),
-                None => DEFAULT_FORMATION.to_string(),
-            },
-            timeout: doc["timeout"]
-                .as_i64()
-                .map(|r| r as u64)
-                .unwrap_or(DEFAULT_TIMEOUT),
-            is_no_timestamp: doc["no-timestamp"]
-                .as_bool()
-                .unwrap_or(DEFAULT_NO_TIMESTAMP),
-            port: doc["port"].as_i64(),
-            app: match doc["app"].as_str() {
-                Some(r) => Some(r.to_string()),
-                None => None,
-            },
-            log_path: match doc["log"].as_str() {
-                Some(r) => Some(PathBuf::from(r)),
-                None => None,
-            },
-            run_path: match doc["run"].as_str() {
-                Some(r) => Some(PathBuf::from(r)),
-                None => None,
-            },
-            template_path: match doc["template"].as_str() {
-                Some(r) => Some(PathBuf::from(r)),
-                None => None,
-            },
-            user: match doc["user"].as_str() {
-                Some(r) => Some(r.to_string()),
-                None => None,
-            },
-            root_path: match doc["root"].as_str() {
-                Some(r) => Some(PathBuf::from(r)),
-                None => None,
-            },
+        let env_layer = self.env_layer()?;
+
+        // The profile is itself a layered key, so it has to be known before the files are applied
+        let selected = match (&cli.profile, &env_layer.profile) {
+            (Some(name), _) => Some((name.clone(), Origin::Cli)),
+            (None, Some(name)) => Some((name.clone(), Origin::Env(env_var_name("profile")))),
+            _ => files.iter().rev().find_map(|(path, layer)| {
+                layer
+                    .profile
+                    .clone()
+                    .map(|name| (name, Origin::File(path.clone())))
+            }),
+        };
+        if let Some((name, origin)) = &selected {
+            let defined: Vec<&str> = files
+                .iter()
+                .flat_map(|(_, layer)| layer.profile_names())
+                .collect();
+            if !defined.contains(&name.as_str()) {
+                return Err(ConfigError {
+                    origin: origin.clone(),
+                    line: None,
+                    column: None,
+                    message: format!(
+                        "unknown profile `{}` (defined: {})",
+                        name,
+                        if defined.is_empty() {
+                            String::from("none")
+                        } else {
+                            defined.join(", ")
+                        }
+                    ),
+                });
+            }
+        }
+        let profile = selected.map(|(name, _)| name);
+
+        for (path, mut layer) in files {
+            let overrides = match (&profile, layer.profiles.take()) {
+                (Some(name), Some(mut profiles)) => profiles.remove(name),
+                _ => None,
+            };
+            config.apply(layer, |_| Origin::File(path.clone()));
+            if let Some(overrides) = overrides {
+                config.apply(overrides, |_| Origin::Profile {
+                    path: path.clone(),
+                    name: profile.clone().unwrap_or_default(),
+                });
+            }
+        }
+
+        config.apply(env_layer, |key| Origin::Env(env_var_name(key)));
+        config.apply(cli, |_| Origin::Cli);
+
+        Ok(config)
+    }
+
+    fn global_path(&self) -> Option<PathBuf> {
+        let config_home = match self.vars.get("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(self.vars.get("HOME")?).join(".config"),
+        };
+        let path = config_home.join("ultraman").join("config.yml");
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    fn project_path(&self) -> Option<PathBuf> {
+        self.cwd
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG))
+            .find(|path| path.is_file())
+    }
+
+    // e.g.) ULTRAMAN_PROCFILE
+    fn var<T: From<String>>(&self, key: &str) -> Result<Option<T>, ConfigError> {
+        Ok(self.vars.get(&env_var_name(key)).cloned().map(T::from))
+    }
+
+    // e.g.) ULTRAMAN_TIMEOUT=10, which has to be `expected`
+    fn parse_var<T: FromStr>(&self, key: &str, expected: &str) -> Result<Option<T>, ConfigError> {
+        let value = match self.vars.get(&env_var_name(key)) {
+            Some(value) => value,
+            None => return Ok(None),
         };
+        value.parse::<T>().map(Some).map_err(|_| ConfigError {
+            origin: Origin::Env(env_var_name(key)),
+            line: None,
+            column: None,
+            message: format!("invalid value `{}`, expected {}", value, expected),
+        })
+    }
+
+    // e.g.) ULTRAMAN_NO_TIMESTAMP=1, or true
+    fn parse_flag(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.vars.get(&env_var_name(key)).map(String::as_str) {
+            Some("1") => Ok(Some(true)),
+            Some("0") => Ok(Some(false)),
+            _ => self.parse_var(key, "true or false"),
+        }
+    }
+}
+
+// Resolve the effective settings for the current directory and print config warnings
+pub fn load_config(cli: RawConfig) -> Result<Config, ConfigError> {
+    let config = ConfigLoader::from_env().load(cli)?;
+    for warning in config.warnings.iter() {
+        eprintln!("warning: {}", warning);
     }
     Ok(config)
 }
 
+// e.g.) no-timestamp -> ULTRAMAN_NO_TIMESTAMP
+pub fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase().replace('-', "_"))
+}
+
+fn read_layer(filepath: &PathBuf) -> Result<(RawConfig, Vec<ConfigWarning>), ConfigError> {
+    let config_str = fs::read_to_string(filepath).map_err(|e| ConfigError {
+        origin: Origin::File(filepath.clone()),
+        line: None,
+        column: None,
+        message: format!("failed to read the file: {}", e),
+    })?;
+    parse_layer(filepath, &config_str)
+}
+
+fn parse_layer(
+    filepath: &Path,
+    config_str: &str,
+) -> Result<(RawConfig, Vec<ConfigWarning>), ConfigError> {
+    // A document with nothing but comments and blank lines is not valid yaml, but it is a valid .ultraman
+    let is_blank = config_str
+        .lines()
+        .map(|line| line.trim())
+        .all(|line| line.is_empty() || line.starts_with('#'));
+    if is_blank {
+        return Ok((RawConfig::default(), vec![]));
+    }
+
+    let mut unknown_keys = vec![];
+    let deserializer = serde_yaml::Deserializer::from_str(config_str);
+    let raw: RawConfig = serde_ignored::deserialize(deserializer, |path| {
+        // Option values show up as `?` segments, e.g.) profiles.?.ci.timout
+        let key = path
+            .to_string()
+            .split('.')
+            .filter(|segment| *segment != "?")
+            .collect::<Vec<_>>()
+            .join(".");
+        unknown_keys.push(key)
+    })
+    .map_err(|e| {
+        let location = e.location();
+        let message = e.to_string();
+        // serde_yaml appends the location to the message, but we already print it in front
+        let message = match message.rfind(" at line ") {
+            Some(pos) if location.is_some() => message[..pos].to_string(),
+            _ => message,
+        };
+        ConfigError {
+            origin: Origin::File(filepath.to_path_buf()),
+            line: location.as_ref().map(|l| l.line()),
+            column: location.as_ref().map(|l| l.column()),
+            message,
+        }
+    })?;
+
+    let warnings = unknown_keys
+        .into_iter()
+        .map(|key| ConfigWarning {
+            path: filepath.to_path_buf(),
+            line: find_key_line(config_str, &key),
+            suggestion: suggest_key(&key),
+            key,
+        })
+        .collect();
+
+    Ok((raw, warnings))
+}
+
+// 1-indexed line on which a key is defined, nested keys are looked up by their last segment
+fn find_key_line(config_str: &str, key: &str) -> Option<usize> {
+    let key = key.rsplit('.').next().unwrap_or(key);
+    config_str
+        .lines()
+        .position(|line| {
+            line.trim_start()
+                .strip_prefix(key)
+                .map(|rest| rest.trim_start().starts_with(':'))
+                .unwrap_or(false)
+        })
+        .map(|index| index + 1)
+}
+
+fn suggest_key(key: &str) -> Option<&'static str> {
+    let key = key.rsplit('.').next().unwrap_or(key);
+    KNOWN_KEYS
+        .iter()
+        .chain([PROFILES_KEY].iter())
+        .map(|known| (*known, edit_distance(key, known)))
+        .filter(|(_, distance)| *distance <= std::cmp::max(1, key.len() / 3))
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(known, _)| known)
+}
+
+// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,21 +607,35 @@ mod tests {
     use std::io::Write;
     use tempfile::tempdir;
 
+    fn write_file(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = File::create(path).unwrap();
+        writeln!(file, "{}", content).unwrap();
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
     fn test_yaml_data(yaml_str: &str) -> anyhow::Result<Config> {
         let dir = tempdir().ok().unwrap();
         let file_path = dir.path().join(".ultraman");
         let mut file = File::create(file_path.clone()).ok().unwrap();
         writeln!(file, "{}", yaml_str).unwrap();
 
-        let result = read_config(file_path).unwrap();
+        let result = ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::default())?;
         Ok(result)
     }
 
     #[test]
     fn test_read_config_do_not_exist() -> anyhow::Result<()> {
         let dir = tempdir()?;
-        let file_path = dir.path().join("do_not_exist");
-        let result = read_config(file_path).unwrap();
+        let result = ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::default())?;
 
         assert_eq!(result.procfile_path, PathBuf::from(DEFAULT_PROCFILE));
         assert_eq!(result.env_path, PathBuf::from(DEFAULT_ENV));
@@ -155,6 +667,7 @@ procfile: ./tmp/Procfile
     #[test]
     fn test_read_config() -> anyhow::Result<()> {
         let yaml_str = r#"
+# comments are allowed
 procfile: ./tmp/Procfile
 env: ./tmp/.env
 
@@ -167,7 +680,7 @@ no-timestamp: true
 app: app-for-runit
 log: /var/app/log/ultraman.log
 run: /tmp/pids/ultraman.pid
-template: ../../src/cmd/export/templates/supervisord
+template: ../../src/export/templates/supervisord
 user: root
 root: /home/app
 
@@ -193,10 +706,316 @@ hoge: hogehoge
         );
         assert_eq!(
             result.template_path.unwrap(),
-            PathBuf::from("../../src/cmd/export/templates/supervisord")
+            PathBuf::from("../../src/export/templates/supervisord")
         );
         assert_eq!(result.user.unwrap(), "root");
         assert_eq!(result.root_path.unwrap(), PathBuf::from("/home/app"));
+        assert_eq!(result.warnings.len(), 1);
+        assert_eq!(result.warnings[0].key, "hoge");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_only_comments() -> anyhow::Result<()> {
+        let yaml_str = r#"
+# formation: web=2
+"#;
+
+        let result = test_yaml_data(yaml_str)?;
+        assert_eq!(result.formation, DEFAULT_FORMATION);
+        assert!(result.warnings.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_layer_unknown_key() -> anyhow::Result<()> {
+        let yaml_str = "procfile: Procfile\nfromation: web=2\n";
+        let (layer, warnings) = parse_layer(&PathBuf::from(".ultraman"), yaml_str)?;
+
+        assert_eq!(layer.formation, None);
+        assert_eq!(
+            warnings,
+            vec![ConfigWarning {
+                path: PathBuf::from(".ultraman"),
+                line: Some(2),
+                key: String::from("fromation"),
+                suggestion: Some("formation"),
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            ".ultraman:2: unknown key `fromation` (did you mean `formation`?)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_layer_type_error() -> anyhow::Result<()> {
+        let yaml_str = "procfile: Procfile\ntimeout: soon\n";
+        let result = parse_layer(&PathBuf::from(".ultraman"), yaml_str).unwrap_err();
+
+        assert_eq!(result.line, Some(2));
+        assert_eq!(
+            result.to_string(),
+            ".ultraman:2:10: timeout: invalid type: string \"soon\", expected u64"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_key() -> anyhow::Result<()> {
+        assert_eq!(suggest_key("procfle"), Some("procfile"));
+        assert_eq!(suggest_key("no_timestamp"), Some("no-timestamp"));
+        assert_eq!(suggest_key("hoge"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_layers() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let home = dir.path().join("home");
+        let project = dir.path().join("project");
+        write_file(
+            &home.join(".config/ultraman/config.yml"),
+            "timeout: 10\nport: 3000\nuser: global",
+        );
+        write_file(&project.join(".ultraman"), "timeout: 20\nport: 4000");
+
+        let loader = ConfigLoader::new(
+            project.clone(),
+            vars(&[
+                ("HOME", home.to_str().unwrap()),
+                ("ULTRAMAN_PORT", "5000"),
+                ("ULTRAMAN_NO_TIMESTAMP", "true"),
+            ]),
+        );
+        let result = loader.load(RawConfig {
+            formation: Some(String::from("web=2")),
+            ..RawConfig::default()
+        })?;
+
+        assert_eq!(result.procfile_path, PathBuf::from(DEFAULT_PROCFILE));
+        assert_eq!(result.origin("procfile"), &Origin::Default);
+        assert_eq!(result.user.as_deref(), Some("global"));
+        assert_eq!(
+            result.origin("user"),
+            &Origin::File(home.join(".config/ultraman/config.yml"))
+        );
+        assert_eq!(result.timeout, 20);
+        assert_eq!(
+            result.origin("timeout"),
+            &Origin::File(project.join(".ultraman"))
+        );
+        assert_eq!(result.port, Some(5000));
+        assert_eq!(
+            result.origin("port"),
+            &Origin::Env(String::from("ULTRAMAN_PORT"))
+        );
+        assert!(result.is_no_timestamp);
+        assert_eq!(result.formation, "web=2");
+        assert_eq!(result.origin("formation"), &Origin::Cli);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_prefers_xdg_config_home() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        write_file(&dir.path().join("xdg/ultraman/config.yml"), "app: xdg");
+        write_file(
+            &dir.path().join("home/.config/ultraman/config.yml"),
+            "app: home",
+        );
+
+        let loader = ConfigLoader::new(
+            dir.path().join("cwd"),
+            vars(&[
+                ("HOME", dir.path().join("home").to_str().unwrap()),
+                ("XDG_CONFIG_HOME", dir.path().join("xdg").to_str().unwrap()),
+            ]),
+        );
+        let result = loader.load(RawConfig::default())?;
+        assert_eq!(result.app.as_deref(), Some("xdg"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_nearest_project_config() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let project = dir.path().join("project");
+        let nested = project.join("app/models");
+        fs::create_dir_all(&nested)?;
+        write_file(&dir.path().join(".ultraman"), "app: outer");
+        write_file(
+            &project.join(".ultraman"),
+            "app: inner\nprocfile: Procfile.dev\nlog: /var/log/app",
+        );
+
+        let result = ConfigLoader::new(nested, HashMap::new()).load(RawConfig::default())?;
+
+        assert_eq!(result.app.as_deref(), Some("inner"));
+        // relative paths are resolved against the directory of .ultraman
+        assert_eq!(result.procfile_path, project.join("Procfile.dev"));
+        assert_eq!(result.log_path, Some(PathBuf::from("/var/log/app")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_invalid_env_var() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let loader = ConfigLoader::new(
+            dir.path().to_path_buf(),
+            vars(&[("ULTRAMAN_TIMEOUT", "soon")]),
+        );
+        let result = loader.load(RawConfig::default()).unwrap_err();
+
+        assert_eq!(
+            result.to_string(),
+            "$ULTRAMAN_TIMEOUT: invalid value `soon`, expected an integer"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_every_key_from_env_vars() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let loader = ConfigLoader::new(
+            dir.path().to_path_buf(),
+            vars(&[
+                ("ULTRAMAN_LOG_GZIP", "1"),
+                ("ULTRAMAN_LOG_KEEP", "3"),
+                ("ULTRAMAN_EXEC", "web"),
+            ]),
+        );
+        let result = loader.load(RawConfig::default())?;
+        assert!(result.is_log_gzip);
+        assert_eq!(result.log_keep, 3);
+        assert_eq!(result.exec, Some(String::from("web")));
+        assert_eq!(result.origin("exec"), &Origin::Env(env_var_name("exec")));
+
+        // Every key is shown by `ultraman config`
+        let keys = result.entries().into_iter().map(|(key, _)| key);
+        assert!(keys.eq(KNOWN_KEYS.iter().copied()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_var_name() -> anyhow::Result<()> {
+        assert_eq!(env_var_name("procfile"), "ULTRAMAN_PROCFILE");
+        assert_eq!(env_var_name("no-timestamp"), "ULTRAMAN_NO_TIMESTAMP");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_profile() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        write_file(
+            &dir.path().join(".ultraman"),
+            r#"
+formation: all=1
+timeout: 5
+profiles:
+  ci:
+    formation: web=2,worker=1
+    env: .env.ci
+  test:
+    timeout: 1
+"#,
+        );
+        let loader =
+            |pairs: &[(&str, &str)]| ConfigLoader::new(dir.path().to_path_buf(), vars(pairs));
+
+        let result = loader(&[]).load(RawConfig::default())?;
+        assert_eq!(result.formation, "all=1");
+        assert_eq!(result.profile, None);
+
+        let result = loader(&[]).load(RawConfig {
+            profile: Some(String::from("ci")),
+            ..RawConfig::default()
+        })?;
+        assert_eq!(result.formation, "web=2,worker=1");
+        assert_eq!(result.env_path, PathBuf::from(".env.ci"));
+        assert_eq!(result.timeout, 5);
+        assert_eq!(result.profile.as_deref(), Some("ci"));
+        assert_eq!(
+            result.origin("formation"),
+            &Origin::Profile {
+                path: dir.path().join(".ultraman"),
+                name: String::from("ci")
+            }
+        );
+
+        let result = loader(&[("ULTRAMAN_PROFILE", "test")]).load(RawConfig::default())?;
+        assert_eq!(result.formation, "all=1");
+        assert_eq!(result.timeout, 1);
+
+        // command line flags still win over the profile
+        let result = loader(&[]).load(RawConfig {
+            profile: Some(String::from("ci")),
+            formation: Some(String::from("web=5")),
+            ..RawConfig::default()
+        })?;
+        assert_eq!(result.formation, "web=5");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_profile_selected_in_file() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        write_file(
+            &dir.path().join(".ultraman"),
+            "profile: dev\nprofiles:\n  dev:\n    port: 3000\n",
+        );
+
+        let result = ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::default())?;
+        assert_eq!(result.port, Some(3000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_unknown_profile() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        write_file(
+            &dir.path().join(".ultraman"),
+            "profiles:\n  dev:\n    port: 3000\n  ci:\n    port: 4000\n",
+        );
+
+        let result = ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig {
+                profile: Some(String::from("e2e")),
+                ..RawConfig::default()
+            })
+            .unwrap_err();
+        assert_eq!(
+            result.to_string(),
+            "command line: unknown profile `e2e` (defined: ci, dev)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_layer_unknown_key_in_profile() -> anyhow::Result<()> {
+        let yaml_str = "profiles:\n  ci:\n    timout: 3\n";
+        let (_, warnings) = parse_layer(&PathBuf::from(".ultraman"), yaml_str)?;
+
+        assert_eq!(
+            warnings[0].to_string(),
+            ".ultraman:3: unknown key `profiles.ci.timout` (did you mean `timeout`?)"
+        );
 
         Ok(())
     }
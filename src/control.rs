@@ -0,0 +1,565 @@
+// The control socket of a running `ultraman start`, which `ultraman logs` reads recent
+// and live output from
+
+use crate::error::{Result, UltramanError};
+use crate::log::TimestampFormat;
+use crate::log::{self, ColorChoice, Log, LogFormat, LogOpt, Printable, Source, Stream};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use crossbeam::channel::{bounded, Sender, TrySendError};
+use nix::unistd::getuid;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Lines of each instance kept for `ultraman logs`
+pub const DEFAULT_HISTORY: usize = 1000;
+// Lines queued for a follower that reads slower than processes write, before it is dropped
+const FOLLOW_BUFFER: usize = 10_000;
+// How long a client may take to send its request once connected
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Where `ultraman start` listens when no socket is given, e.g.) /run/user/1000/ultraman-<hash>.sock,
+// or /tmp/ultraman-1000/ultraman-<hash>.sock without XDG_RUNTIME_DIR.
+// Every command run with the same Procfile finds the same socket, from any directory
+pub fn socket_path(procfile_path: &Path) -> Result<PathBuf> {
+    let procfile_path = fs::canonicalize(procfile_path).map_err(|source| match source.kind() {
+        io::ErrorKind::NotFound => UltramanError::ProcfileNotFound(procfile_path.to_path_buf()),
+        _ => UltramanError::ProcfileRead {
+            path: procfile_path.to_path_buf(),
+            source,
+        },
+    })?;
+
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            // Anyone can write to /tmp, so the socket goes in a directory only this user can
+            let dir = env::temp_dir().join(format!("ultraman-{}", getuid()));
+            private_dir(&dir).map_err(|source| UltramanError::Socket {
+                path: dir.clone(),
+                source,
+            })?;
+            dir
+        }
+    };
+    Ok(dir.join(format!(
+        "ultraman-{:016x}.sock",
+        fnv1a(procfile_path.as_os_str().as_bytes())
+    )))
+}
+
+// FNV-1a, which unlike DefaultHasher hashes the same in every build of ultraman
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// Creates `dir` that only this user can access, or checks that the existing one is such
+fn private_dir(dir: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e),
+    }
+    // a symlink is not followed, as anyone could have made it
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != getuid().as_raw() || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not a directory that only this user can access",
+        ));
+    }
+    Ok(())
+}
+
+// What `ultraman logs` asks for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Request {
+    // processes (e.g. web) or instances (e.g. web.1), every instance if it is empty
+    pub names: Vec<String>,
+    // lines of history of each instance
+    pub lines: usize,
+    pub follow: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Reply {
+    // Sent first, so lines are aligned and timed like on the console
+    Start { padding: usize, started_at: String },
+    Line(Record),
+    UnknownProcess { name: String, defined: String },
+}
+
+// A line of an instance, or a message about it (e.g. exited with code 1) without a stream
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Record {
+    pub name: String,
+    pub process: String,
+    // index of the process in the Procfile, which picks the color
+    pub index: usize,
+    pub pid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<Stream>,
+    // RFC 3339
+    pub at: String,
+    pub line: String,
+}
+
+impl Record {
+    pub fn at(at: &DateTime<Local>) -> String {
+        at.to_rfc3339_opts(SecondsFormat::Nanos, false)
+    }
+
+    fn is_for(&self, names: &[String]) -> bool {
+        names.is_empty() || names.iter().any(|n| *n == self.name || *n == self.process)
+    }
+}
+
+struct Follower {
+    names: Vec<String>,
+    tx: Sender<Reply>,
+}
+
+// The last lines of each instance, and the clients following new ones
+pub(crate) struct History {
+    size: usize,
+    padding: usize,
+    // numbered in the order they were pushed, so instances can be merged
+    lines: HashMap<String, VecDeque<(u64, Record)>>,
+    pushed: u64,
+    followers: Vec<Follower>,
+}
+
+impl History {
+    pub fn new(size: usize, padding: usize) -> Self {
+        History {
+            size,
+            padding,
+            lines: HashMap::new(),
+            pushed: 0,
+            followers: vec![],
+        }
+    }
+
+    pub fn push(&mut self, record: Record) {
+        // A follower that is gone or can't keep up is dropped, which ends its `ultraman logs`
+        self.followers.retain(|follower| {
+            !record.is_for(&follower.names)
+                || match follower.tx.try_send(Reply::Line(record.clone())) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+                }
+        });
+
+        if self.size == 0 {
+            return;
+        }
+        let lines = self.lines.entry(record.name.clone()).or_default();
+        if lines.len() == self.size {
+            lines.pop_front();
+        }
+        self.pushed += 1;
+        lines.push_back((self.pushed, record));
+    }
+
+    // Replies with the last lines of each instance in the order they were written,
+    // and keeps replying with new ones when following
+    pub fn serve(&mut self, request: Request, tx: Sender<Reply>) {
+        let started_at = Record::at(log::started_at());
+        let _ = tx.send(Reply::Start {
+            padding: self.padding,
+            started_at,
+        });
+
+        let mut recent = self
+            .lines
+            .values()
+            .flat_map(|lines| lines.iter().skip(lines.len().saturating_sub(request.lines)))
+            .filter(|(_, record)| record.is_for(&request.names))
+            .collect::<Vec<_>>();
+        recent.sort_by_key(|(n, _)| *n);
+        for (_, record) in recent {
+            let _ = tx.send(Reply::Line(record.clone()));
+        }
+
+        if request.follow {
+            self.followers.push(Follower {
+                names: request.names,
+                tx,
+            });
+        }
+    }
+}
+
+// Accepts `ultraman logs` at a socket until it is dropped, which also removes the socket
+pub(crate) struct Listener {
+    path: PathBuf,
+    is_closed: Arc<AtomicBool>,
+}
+
+impl Listener {
+    // `on_request` is called for each request with where to send the replies, and returns
+    // false once nothing can be served anymore
+    pub fn bind<F>(path: &Path, on_request: F) -> Result<Self>
+    where
+        F: Fn(Request, Sender<Reply>) -> bool + Send + Sync + 'static,
+    {
+        let error = |source| UltramanError::Socket {
+            path: path.to_path_buf(),
+            source,
+        };
+        if path.exists() {
+            // A socket left by an ultraman that was killed
+            if UnixStream::connect(path).is_ok() {
+                return Err(error(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another ultraman is listening",
+                )));
+            }
+            fs::remove_file(path).map_err(error)?;
+        }
+        let listener = UnixListener::bind(path).map_err(error)?;
+
+        let is_closed = Arc::new(AtomicBool::new(false));
+        let closed = Arc::clone(&is_closed);
+        let on_request = Arc::new(on_request);
+        thread::Builder::new()
+            .name(String::from("control socket"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if closed.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    let on_request = Arc::clone(&on_request);
+                    let closed = Arc::clone(&closed);
+                    let _ = thread::Builder::new()
+                        .name(String::from("serve logs"))
+                        .spawn(move || {
+                            if !serve(stream, &*on_request) {
+                                closed.store(true, Ordering::SeqCst);
+                            }
+                        });
+                }
+            })?;
+
+        Ok(Listener {
+            path: path.to_path_buf(),
+            is_closed,
+        })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.is_closed.store(true, Ordering::SeqCst);
+        // Wakes the thread up from accept
+        let _ = UnixStream::connect(&self.path);
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Runs on a thread of its own for each client, so a client that is slow to send its request
+// or read the replies never holds up the others or the supervisor
+fn serve<F>(stream: UnixStream, on_request: &F) -> bool
+where
+    F: Fn(Request, Sender<Reply>) -> bool,
+{
+    let mut line = String::new();
+    let request = match stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .and_then(|_| BufReader::new(&stream).read_line(&mut line))
+    {
+        Ok(_) => serde_json::from_str::<Request>(&line),
+        Err(_) => return true,
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(_) => return true,
+    };
+
+    let (tx, rx) = bounded(FOLLOW_BUFFER);
+    if !on_request(request, tx) {
+        return false;
+    }
+    let mut out = io::BufWriter::new(stream);
+    for reply in rx.iter() {
+        let mut line = serde_json::to_vec(&reply).unwrap_or_default();
+        line.push(b'\n');
+        if out.write_all(&line).is_err() {
+            break;
+        }
+        if rx.is_empty() && out.flush().is_err() {
+            break;
+        }
+    }
+    let _ = out.flush();
+    true
+}
+
+// Prints the lines a running `ultraman start` kept, and follows new ones if asked to
+//
+// e.g.)
+// Logs::new(socket_path(Path::new("Procfile"))?)
+//     .request(Request { names: vec![String::from("web")], lines: 200, follow: true })
+//     .run()?;
+pub struct Logs {
+    path: PathBuf,
+    request: Request,
+    is_timestamp: bool,
+    timestamp_format: TimestampFormat,
+    color: ColorChoice,
+    log_format: LogFormat,
+}
+
+impl Logs {
+    pub fn new(path: PathBuf) -> Self {
+        Logs {
+            path,
+            request: Request {
+                names: vec![],
+                lines: DEFAULT_HISTORY,
+                follow: false,
+            },
+            is_timestamp: true,
+            timestamp_format: TimestampFormat::default(),
+            color: ColorChoice::default(),
+            log_format: LogFormat::default(),
+        }
+    }
+
+    pub fn request(mut self, request: Request) -> Self {
+        self.request = request;
+        self
+    }
+
+    pub fn timestamp(mut self, is_timestamp: bool) -> Self {
+        self.is_timestamp = is_timestamp;
+        self
+    }
+
+    pub fn timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    // Returns when every line is printed, or when `ultraman start` exits while following
+    pub fn run(self) -> Result<()> {
+        let error = |source| UltramanError::Socket {
+            path: self.path.clone(),
+            source,
+        };
+        let mut stream = UnixStream::connect(&self.path).map_err(error)?;
+        let mut request = serde_json::to_vec(&self.request).unwrap_or_default();
+        request.push(b'\n');
+        stream.write_all(&request).map_err(error)?;
+
+        let is_color = self.color.is_color();
+        colored::control::set_override(is_color);
+        let mut opt = LogOpt {
+            format: self.log_format,
+            is_color,
+            padding: 0,
+            is_timestamp: self.is_timestamp,
+            timestamp_format: self.timestamp_format.clone(),
+        };
+        let mut logs: HashMap<usize, Box<dyn Printable + Sync + Send>> = HashMap::new();
+
+        for line in BufReader::new(stream).lines() {
+            let reply = match serde_json::from_str::<Reply>(&line.map_err(error)?) {
+                Ok(reply) => reply,
+                Err(_) => continue,
+            };
+            match reply {
+                Reply::Start {
+                    padding,
+                    started_at,
+                } => {
+                    if self.log_format == LogFormat::Text {
+                        opt.padding = padding;
+                    }
+                    if let Ok(at) = DateTime::parse_from_rfc3339(&started_at) {
+                        log::set_started_at(at.with_timezone(&Local));
+                    }
+                }
+                Reply::Line(record) => {
+                    let at = DateTime::parse_from_rfc3339(&record.at)
+                        .map(|at| at.with_timezone(&Local))
+                        .unwrap_or_else(|_| Local::now());
+                    let source = Source {
+                        name: &record.name,
+                        process: Some(&record.process),
+                        pid: Some(record.pid),
+                        stream: record.stream,
+                        at,
+                    };
+                    logs.entry(record.index)
+                        .or_insert_with(|| Log::new(record.index, &opt))
+                        .output_at(&source, &record.line);
+                }
+                Reply::UnknownProcess { name, defined } => {
+                    return Err(UltramanError::UnknownProcess { name, defined })
+                }
+            }
+        }
+        log::flush();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn record(name: &str, line: &str) -> Record {
+        Record {
+            name: name.to_string(),
+            process: name.split('.').next().unwrap_or_default().to_string(),
+            index: 0,
+            pid: 100,
+            stream: Some(Stream::Stdout),
+            at: Record::at(&Local::now()),
+            line: line.to_string(),
+        }
+    }
+
+    fn lines(replies: Vec<Reply>) -> Vec<String> {
+        replies
+            .into_iter()
+            .filter_map(|reply| match reply {
+                Reply::Line(record) => Some(format!("{} {}", record.name, record.line)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_history() -> anyhow::Result<()> {
+        let mut history = History::new(2, 8);
+        for (name, line) in [
+            ("web.1", "1"),
+            ("web.2", "2"),
+            ("worker.1", "3"),
+            ("web.1", "4"),
+            ("web.1", "5"),
+        ] {
+            history.push(record(name, line));
+        }
+
+        let (tx, rx) = bounded(FOLLOW_BUFFER);
+        let request = Request {
+            names: vec![String::from("web")],
+            lines: 2,
+            follow: true,
+        };
+        history.serve(request, tx);
+        history.push(record("worker.1", "6"));
+        history.push(record("web.2", "7"));
+        drop(history);
+
+        let replies = rx.iter().collect::<Vec<_>>();
+        assert!(matches!(replies[0], Reply::Start { padding: 8, .. }));
+        // web.1 1 went out of the history
+        assert_eq!(
+            lines(replies),
+            vec!["web.2 2", "web.1 4", "web.1 5", "web.2 7"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_listener() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("ultraman.sock");
+        let listener = Listener::bind(&path, |request, tx| {
+            let mut history = History::new(10, 0);
+            history.push(record("web.1", "hello"));
+            history.serve(request, tx);
+            true
+        })?;
+        // a second ultraman can't take the socket over
+        assert!(Listener::bind(&path, |_, _| true).is_err());
+
+        // a client that sends nothing doesn't keep the others waiting
+        let _silent = UnixStream::connect(&path)?;
+        let mut stream = UnixStream::connect(&path)?;
+        stream.write_all(b"{\"names\":[],\"lines\":10,\"follow\":false}\n")?;
+        let replies = BufReader::new(stream)
+            .lines()
+            .map(|line| serde_json::from_str::<Reply>(&line.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines(replies), vec!["web.1 hello"]);
+
+        drop(listener);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_socket_path() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("app"))?;
+        fs::write(dir.path().join("Procfile"), "web: ./web\n")?;
+        fs::write(dir.path().join("app/Procfile"), "web: ./web\n")?;
+
+        let path = socket_path(&dir.path().join("Procfile"))?;
+        // the same Procfile however it is reached
+        assert_eq!(socket_path(&dir.path().join("app/../Procfile"))?, path);
+        assert_ne!(socket_path(&dir.path().join("app/Procfile"))?, path);
+        // a Procfile that isn't there has no socket to guess
+        assert!(matches!(
+            socket_path(&dir.path().join("missing/Procfile")),
+            Err(UltramanError::ProcfileNotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_private_dir() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let private = dir.path().join("private");
+        private_dir(&private)?;
+        assert_eq!(fs::metadata(&private)?.mode() & 0o777, 0o700);
+        private_dir(&private)?;
+
+        // others could replace the socket in it
+        let shared = dir.path().join("shared");
+        fs::DirBuilder::new().mode(0o777).create(&shared)?;
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o777))?;
+        assert!(private_dir(&shared).is_err());
+
+        Ok(())
+    }
+}
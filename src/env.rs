@@ -1,21 +1,34 @@
+use crate::error::{Result, UltramanError};
+
 use dotenv;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
 pub type Env = HashMap<String, String>;
 
-pub fn read_env(filepath: PathBuf) -> Result<Env, Box<dyn std::error::Error>> {
+pub fn read_env(filepath: PathBuf) -> Result<Env> {
     let mut env: Env = HashMap::new();
 
-    if let Some(()) = dotenv::from_path(filepath.as_path()).ok() {
-        let env_vars: Vec<(String, String)> = dotenv::vars().collect();
-        for pair in env_vars {
-            let (key, val) = pair;
-            env.insert(key, val);
+    match dotenv::from_path(filepath.as_path()) {
+        Ok(()) => {
+            let env_vars: Vec<(String, String)> = dotenv::vars().collect();
+            for pair in env_vars {
+                let (key, val) = pair;
+                env.insert(key, val);
+            }
+            Ok(env)
         }
-        return Ok(env);
+        // An env file is optional
+        Err(dotenv::Error::Io(_)) => Ok(env),
+        Err(dotenv::Error::LineParse(line, _)) => Err(UltramanError::Env {
+            path: filepath,
+            message: format!("cannot parse `{}`", line),
+        }),
+        Err(e) => Err(UltramanError::Env {
+            path: filepath,
+            message: e.to_string(),
+        }),
     }
-    Ok(env)
 }
 
 #[cfg(test)]
@@ -48,4 +61,19 @@ PS=1
 
         Ok(())
     }
+
+    #[test]
+    fn test_read_env_when_error() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        assert!(read_env(dir.path().join(".env"))?.is_empty());
+
+        let file_path = dir.path().join(".env.broken");
+        let mut file = File::create(file_path.clone())?;
+        writeln!(file, "NOT A VARIABLE")?;
+
+        let result = read_env(file_path).unwrap_err();
+        assert_eq!(result.exit_code(), 78);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,278 @@
+use crate::config::ConfigError;
+use crate::formation::FormationError;
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+
+const EXPORT_FORMATS: &str = "upstart, systemd, supervisord, runit, launchd, daemon";
+
+// Exit codes follow sysexits(3) so scripts can tell error classes apart
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_UNAVAILABLE: i32 = 69;
+const EX_SOFTWARE: i32 = 70;
+const EX_OSERR: i32 = 71;
+const EX_CANTCREAT: i32 = 73;
+const EX_IOERR: i32 = 74;
+const EX_CONFIG: i32 = 78;
+
+pub type Result<T> = std::result::Result<T, UltramanError>;
+
+#[derive(Debug)]
+pub enum UltramanError {
+    // .ultraman, global config and ULTRAMAN_* variables
+    Config(ConfigError),
+    // Procfile
+    ProcfileNotFound(PathBuf),
+    ProcfileRead { path: PathBuf, source: io::Error },
+    ProcfileInvalid { path: PathBuf, issues: usize },
+    UnknownProcess { name: String, defined: String },
+    InvalidInstance(String),
+    // --formation
+    Formation(FormationError),
+    // env file and PORT
+    Env { path: PathBuf, message: String },
+    // spawning processes
+    Shell { path: PathBuf, source: io::Error },
+    InvalidCommand { command: String, message: String },
+    Spawn { command: String, source: io::Error },
+    // export
+    UnsupportedFormat(String),
+    Export { path: PathBuf, message: String },
+    // --log-dir
+    LogFile { path: PathBuf, source: io::Error },
+    // --tui
+    NotATerminal,
+    // the control socket of `ultraman start`, which `ultraman logs` connects to
+    Socket { path: PathBuf, source: io::Error },
+    // a thread of ultraman itself, by its name
+    Panicked(String),
+    Io(io::Error),
+}
+
+impl UltramanError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            UltramanError::Config(_) | UltramanError::Env { .. } => EX_CONFIG,
+            UltramanError::ProcfileNotFound(_) => EX_NOINPUT,
+            UltramanError::ProcfileRead { .. } | UltramanError::Io(_) => EX_IOERR,
+            UltramanError::ProcfileInvalid { .. } | UltramanError::InvalidCommand { .. } => {
+                EX_DATAERR
+            }
+            UltramanError::UnknownProcess { .. }
+            | UltramanError::InvalidInstance(_)
+            | UltramanError::Formation(_)
+            | UltramanError::UnsupportedFormat(_)
+            | UltramanError::NotATerminal => EX_USAGE,
+            UltramanError::Shell { .. } | UltramanError::Socket { .. } => EX_UNAVAILABLE,
+            UltramanError::Spawn { .. } => EX_OSERR,
+            UltramanError::Panicked(_) => EX_SOFTWARE,
+            UltramanError::Export { .. } | UltramanError::LogFile { .. } => EX_CANTCREAT,
+        }
+    }
+
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            UltramanError::Config(_) => Some(String::from(
+                "run `ultraman config --show-origin` to see where each value comes from",
+            )),
+            UltramanError::ProcfileNotFound(_) => Some(String::from(
+                "create a Procfile or specify one with --procfile",
+            )),
+            UltramanError::ProcfileInvalid { .. } => {
+                Some(String::from("run `ultraman check` to see every issue"))
+            }
+            UltramanError::UnknownProcess { defined, .. } => {
+                Some(format!("defined processes: {}", defined))
+            }
+            UltramanError::InvalidInstance(_) => Some(String::from(
+                "use <name>.<number> with numbers starting at 1 (e.g. web.2)",
+            )),
+            // the message already lists the defined processes
+            UltramanError::Formation(FormationError::UnknownProcess { .. }) => None,
+            UltramanError::Formation(_) => Some(String::from(
+                "the value should be in the format process=num,process=num (e.g. all=1,web=2,worker=0)",
+            )),
+            UltramanError::Shell { .. } => Some(String::from(
+                "set `shell` in .ultraman (or --shell) to a POSIX shell, or run the process without one with --exec",
+            )),
+            UltramanError::InvalidCommand { .. } => Some(String::from(
+                "fix the quoting, or run the process through the shell (without --exec)",
+            )),
+            UltramanError::Spawn { source, .. } if source.kind() == io::ErrorKind::NotFound => {
+                Some(String::from("is the program installed and on PATH?"))
+            }
+            UltramanError::UnsupportedFormat(_) => {
+                Some(format!("supported formats: {}", EXPORT_FORMATS))
+            }
+            UltramanError::Socket { source, .. } if source.kind() == io::ErrorKind::AddrInUse => {
+                Some(String::from(
+                    "stop the other `ultraman start`, or give this one its own --socket",
+                ))
+            }
+            UltramanError::Socket { .. } => Some(String::from(
+                "is `ultraman start` running with the same Procfile (or --socket)?",
+            )),
+            UltramanError::NotATerminal => Some(String::from(
+                "run it in a terminal, or without --tui to print output",
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn report(&self) {
+        eprintln!("error: {}", self);
+        if let Some(hint) = self.hint() {
+            eprintln!("hint: {}", hint);
+        }
+    }
+}
+
+impl std::error::Error for UltramanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UltramanError::Config(e) => Some(e),
+            UltramanError::ProcfileRead { source, .. } => Some(source),
+            UltramanError::Shell { source, .. } => Some(source),
+            UltramanError::Spawn { source, .. } => Some(source),
+            UltramanError::LogFile { source, .. } => Some(source),
+            UltramanError::Socket { source, .. } => Some(source),
+            UltramanError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for UltramanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UltramanError::Config(e) => write!(f, "{}", e),
+            UltramanError::ProcfileNotFound(path) => {
+                write!(f, "{} does not exist", path.display())
+            }
+            UltramanError::ProcfileRead { path, source } => {
+                write!(f, "could not read {}: {}", path.display(), source)
+            }
+            UltramanError::ProcfileInvalid { path, issues } => write!(
+                f,
+                "{} has {} line(s) that can't be used",
+                path.display(),
+                issues
+            ),
+            UltramanError::UnknownProcess { name, .. } => {
+                write!(f, "can't find process called: {}", name)
+            }
+            UltramanError::InvalidInstance(process) => {
+                write!(f, "invalid process instance: {}", process)
+            }
+            UltramanError::Formation(message) => write!(f, "invalid formation: {}", message),
+            UltramanError::Env { path, message } => write!(f, "{}: {}", path.display(), message),
+            UltramanError::Shell { path, source } => {
+                write!(f, "could not run the shell {}: {}", path.display(), source)
+            }
+            UltramanError::InvalidCommand { command, message } => {
+                write!(f, "can't split `{}` into words: {}", command, message)
+            }
+            UltramanError::Spawn { command, source } => {
+                write!(f, "failed to execute `{}`: {}", command, source)
+            }
+            UltramanError::UnsupportedFormat(format) => {
+                write!(f, "do not support format: {}", format)
+            }
+            UltramanError::Export { path, message } => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+            UltramanError::LogFile { path, source } => {
+                write!(f, "could not open {}: {}", path.display(), source)
+            }
+            UltramanError::NotATerminal => write!(f, "--tui needs a terminal"),
+            UltramanError::Socket { path, source } => {
+                write!(f, "control socket {}: {}", path.display(), source)
+            }
+            UltramanError::Panicked(thread) => write!(f, "the {} thread panicked", thread),
+            UltramanError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// The panic itself has been printed by then
+pub fn join<T>(thread: JoinHandle<T>) -> Result<T> {
+    let name = thread.thread().name().unwrap_or("unnamed").to_string();
+    thread.join().map_err(|_| UltramanError::Panicked(name))
+}
+
+impl From<ConfigError> for UltramanError {
+    fn from(e: ConfigError) -> Self {
+        UltramanError::Config(e)
+    }
+}
+
+impl From<FormationError> for UltramanError {
+    fn from(e: FormationError) -> Self {
+        UltramanError::Formation(e)
+    }
+}
+
+impl From<io::Error> for UltramanError {
+    fn from(e: io::Error) -> Self {
+        UltramanError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() -> anyhow::Result<()> {
+        assert_eq!(
+            UltramanError::ProcfileNotFound(PathBuf::from("Procfile")).exit_code(),
+            66
+        );
+        assert_eq!(
+            UltramanError::Formation(FormationError::Empty).exit_code(),
+            64
+        );
+        let error = UltramanError::Shell {
+            path: PathBuf::from("/bin/fish"),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        assert_eq!(error.exit_code(), 69);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_and_hint() -> anyhow::Result<()> {
+        let error = UltramanError::UnsupportedFormat(String::from("docker"));
+        assert_eq!(error.to_string(), "do not support format: docker");
+        assert_eq!(
+            error.hint().unwrap(),
+            "supported formats: upstart, systemd, supervisord, runit, launchd, daemon"
+        );
+
+        let error = UltramanError::UnknownProcess {
+            name: String::from("wrker"),
+            defined: String::from("web, worker"),
+        };
+        assert_eq!(error.to_string(), "can't find process called: wrker");
+        assert_eq!(error.hint().unwrap(), "defined processes: web, worker");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_join() -> anyhow::Result<()> {
+        let thread = std::thread::Builder::new()
+            .name(String::from("supervise"))
+            .spawn(|| panic!("boom"))?;
+        let error = join::<()>(thread).unwrap_err();
+        assert_eq!(error.to_string(), "the supervise thread panicked");
+        assert_eq!(error.exit_code(), 70);
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use crate::cmd::export::ExportOpts;
 use crate::env::read_env;
+use crate::error::{Result, UltramanError};
+use crate::export::ExportOpts;
 
 use handlebars::Handlebars;
 use serde_derive::Serialize;
@@ -8,7 +9,7 @@ use std::env;
 use std::fs::File;
 use std::fs::{create_dir_all, remove_file};
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // Lifetime cannot be set because it will be HashMap data with anonymous runtime
 #[derive(Serialize)]
@@ -23,16 +24,22 @@ pub struct Template {
     pub(crate) output_path: PathBuf,
 }
 
+pub(crate) fn export_error(path: &Path, message: &str, e: impl std::fmt::Display) -> UltramanError {
+    UltramanError::Export {
+        path: path.to_path_buf(),
+        message: format!("{}: {}", message, e),
+    }
+}
+
 pub trait Exportable {
-    fn export(&self) -> Result<(), Box<dyn std::error::Error>>;
+    fn export(&self) -> Result<()>;
     //https://yajamon.hatenablog.com/entry/2018/01/30/202849
     fn ref_opts(&self) -> &ExportOpts;
 
-    fn base_export(&self) -> Result<(), Box<dyn std::error::Error>> {
+    fn base_export(&self) -> Result<()> {
         let opts = self.ref_opts();
         let location = &opts.location;
-        let display = location.clone().into_os_string().into_string().unwrap();
-        create_dir_all(&location).expect(&format!("Could not create: {}", display));
+        create_dir_all(location).map_err(|e| export_error(location, "could not create", e))?;
 
         // self.chown(&username, &self.log_path());
         // self.chown(&username, &self.run_path());
@@ -71,12 +78,12 @@ pub trait Exportable {
             .unwrap_or_else(|| env::current_dir().unwrap())
     }
 
-    fn clean(&self, filepath: &PathBuf) {
-        let display = filepath.clone().into_os_string().into_string().unwrap();
+    fn clean(&self, filepath: &PathBuf) -> Result<()> {
         if filepath.exists() {
-            self.say(&format!("cleaning: {}", display));
-            remove_file(filepath).expect(&format!("Could not remove file: {}", display));
+            self.say(&format!("cleaning: {}", filepath.display()));
+            remove_file(filepath).map_err(|e| export_error(filepath, "could not remove", e))?;
         }
+        Ok(())
     }
 
     fn project_root_path(&self) -> PathBuf {
@@ -87,33 +94,22 @@ pub trait Exportable {
         println!("[ultraman export] {}", msg)
     }
 
-    fn write_template(&self, tmpl: Template) {
+    fn write_template(&self, tmpl: Template) -> Result<()> {
         let handlebars = Handlebars::new();
-        let display_template = tmpl
-            .template_path
-            .clone()
-            .into_os_string()
-            .into_string()
-            .unwrap();
-        let display_output = tmpl
-            .output_path
-            .clone()
-            .into_os_string()
-            .into_string()
-            .unwrap();
-        let mut output_file = File::create(tmpl.output_path)
-            .expect(&format!("Could not create file: {}", &display_output));
-        self.say(&format!("writing: {}", &display_output));
-        let mut data = tmpl.data;
-        let mut template_source = File::open(tmpl.template_path)
-            .expect(&format!("Could not open file: {}", display_template));
+        let template_path = &tmpl.template_path;
+        let output_path = &tmpl.output_path;
+        let mut output_file = File::create(output_path)
+            .map_err(|e| export_error(output_path, "could not create", e))?;
+        self.say(&format!("writing: {}", output_path.display()));
+        let data = tmpl.data;
         let mut template_str = String::new();
-        template_source
-            .read_to_string(&mut template_str)
-            .expect(&format!("Could not read file: {}", display_template));
+        File::open(template_path)
+            .and_then(|mut template_source| template_source.read_to_string(&mut template_str))
+            .map_err(|e| export_error(template_path, "could not read template", e))?;
         handlebars
-            .render_template_to_write(&mut template_str, &mut data, &mut output_file)
-            .expect(&format!("Coult not render file: {}", &display_output));
+            .render_template_to_write(&template_str, &data, &mut output_file)
+            .map_err(|e| export_error(output_path, "could not render", e))?;
+        Ok(())
     }
 
     fn output_path(&self, filename: &str) -> PathBuf {
@@ -121,19 +117,17 @@ pub trait Exportable {
         location.join(filename)
     }
 
-    fn env_without_port(&self) -> Vec<EnvParameter> {
-        let mut env =
-            read_env(self.ref_opts().env_path.clone().unwrap()).expect("failed read .env");
+    fn env_without_port(&self) -> Result<Vec<EnvParameter>> {
+        let mut env = read_env(self.ref_opts().env_path.clone().unwrap())?;
         env.remove("PORT");
         let mut env_without_port: Vec<EnvParameter> = vec![];
         for (key, value) in env {
             env_without_port.push(EnvParameter { key, value });
         }
-        env_without_port
+        Ok(env_without_port)
     }
 
-    fn create_dir_recursive(&self, dir_path: &PathBuf) {
-        let display = dir_path.clone().into_os_string().into_string().unwrap();
-        create_dir_all(dir_path).expect(&format!("Could not create: {}", display))
+    fn create_dir_recursive(&self, dir_path: &PathBuf) -> Result<()> {
+        create_dir_all(dir_path).map_err(|e| export_error(dir_path, "could not create", e))
     }
 }
@@ -1,12 +1,12 @@
 use super::base::{EnvParameter, Exportable, Template};
-use crate::cmd::export::ExportOpts;
 use crate::env::read_env;
+use crate::error::Result;
+use crate::export::ExportOpts;
 use crate::process::port_for;
 use crate::procfile::{Procfile, ProcfileEntry};
 use handlebars::to_json;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
-use std::collections::HashMap;
 use std::env;
 use std::marker::PhantomData;
 use std::path::PathBuf;
@@ -44,9 +44,7 @@ struct ProcessParams<'a> {
 impl<'a> Default for Exporter<'a> {
     fn default() -> Self {
         Exporter {
-            procfile: Procfile {
-                data: HashMap::new(),
-            },
+            procfile: Procfile::default(),
             opts: ExportOpts {
                 format: String::from(""),
                 location: PathBuf::from("location"),
@@ -61,6 +59,7 @@ impl<'a> Default for Exporter<'a> {
                 procfile_path: Some(PathBuf::from("Procfile")),
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: Some(5),
+                profile: None,
             },
             _marker: PhantomData,
         }
@@ -78,17 +77,17 @@ impl<'a> Exporter<'a> {
 
     fn master_tmpl_path(&self) -> PathBuf {
         let path = self.project_root_path();
-        path.join("src/cmd/export/templates/daemon/master.conf.hbs")
+        path.join("src/export/templates/daemon/master.conf.hbs")
     }
 
     fn process_master_tmpl_path(&self) -> PathBuf {
         let path = self.project_root_path();
-        path.join("src/cmd/export/templates/daemon/process_master.conf.hbs")
+        path.join("src/export/templates/daemon/process_master.conf.hbs")
     }
 
     fn process_tmpl_path(&self) -> PathBuf {
         let path = self.project_root_path();
-        path.join("src/cmd/export/templates/daemon/process.conf.hbs")
+        path.join("src/export/templates/daemon/process.conf.hbs")
     }
 
     fn make_master_data(&self) -> Map<String, Json> {
@@ -114,11 +113,11 @@ impl<'a> Exporter<'a> {
         pe: &ProcfileEntry,
         service_name: &str,
         con_index: usize,
-    ) -> Map<String, Json> {
+    ) -> Result<Map<String, Json>> {
         let mut data = Map::new();
         let pp = ProcessParams {
             service_name: service_name,
-            env: self.environment(con_index),
+            env: self.environment(con_index)?,
             user: self.username(),
             work_dir: &self.root_path().into_os_string().into_string().unwrap(),
             pid_path: &self
@@ -137,7 +136,7 @@ impl<'a> Exporter<'a> {
                 .unwrap(),
         };
         data.insert("process".to_string(), to_json(&pp));
-        data
+        Ok(data)
     }
 
     fn command_args_str(&self, pe: &ProcfileEntry) -> String {
@@ -158,13 +157,13 @@ impl<'a> Exporter<'a> {
         result
     }
 
-    fn environment(&self, con_index: usize) -> Vec<EnvParameter> {
+    fn environment(&self, con_index: usize) -> Result<Vec<EnvParameter>> {
         let port = port_for(
             &self.opts.env_path.clone().unwrap(),
             self.opts.port.clone(),
             con_index,
-        );
-        let mut env = read_env(self.opts.env_path.clone().unwrap()).expect("failed read .env");
+        )?;
+        let mut env = read_env(self.opts.env_path.clone().unwrap())?;
         env.insert("PORT".to_string(), port.to_string());
 
         let mut result = vec![];
@@ -175,13 +174,13 @@ impl<'a> Exporter<'a> {
             });
         }
 
-        result
+        Ok(result)
     }
 }
 
 impl<'a> Exportable for Exporter<'a> {
-    fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.base_export().expect("failed execute base_export");
+    fn export(&self) -> Result<()> {
+        self.base_export()?;
 
         let mut clean_paths: Vec<PathBuf> = vec![];
         let mut tmpl_data: Vec<Template> = vec![];
@@ -217,18 +216,18 @@ impl<'a> Exportable for Exporter<'a> {
                 clean_paths.push(output_path.clone());
                 tmpl_data.push(Template {
                     template_path: self.process_tmpl_path(),
-                    data: self.make_process_data(pe, &service_name, n),
+                    data: self.make_process_data(pe, &service_name, n)?,
                     output_path,
                 });
             }
         }
 
         for path in clean_paths {
-            self.clean(&path);
+            self.clean(&path)?;
         }
 
         for tmpl in tmpl_data {
-            self.write_template(tmpl);
+            self.write_template(tmpl)?;
         }
 
         Ok(())
@@ -1,12 +1,12 @@
 use super::base::{EnvParameter, Exportable, Template};
-use crate::cmd::export::ExportOpts;
 use crate::env::read_env;
+use crate::error::Result;
+use crate::export::ExportOpts;
 use crate::process::port_for;
 use crate::procfile::{Procfile, ProcfileEntry};
 use handlebars::to_json;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
-use std::collections::HashMap;
 use std::env;
 use std::marker::PhantomData;
 use std::path::PathBuf;
@@ -31,9 +31,7 @@ struct LaunchdParams<'a> {
 impl<'a> Default for Exporter<'a> {
     fn default() -> Self {
         Exporter {
-            procfile: Procfile {
-                data: HashMap::new(),
-            },
+            procfile: Procfile::default(),
             opts: ExportOpts {
                 format: String::from(""),
                 location: PathBuf::from("location"),
@@ -48,6 +46,7 @@ impl<'a> Default for Exporter<'a> {
                 procfile_path: Some(PathBuf::from("Procfile")),
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: Some(5),
+                profile: None,
             },
             _marker: PhantomData,
         }
@@ -65,7 +64,7 @@ impl<'a> Exporter<'a> {
 
     fn launchd_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/launchd/launchd.plist.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/launchd/launchd.plist.hbs");
         path.push(tmpl_path);
         path
     }
@@ -75,12 +74,12 @@ impl<'a> Exporter<'a> {
         pe: &ProcfileEntry,
         service_name: &str,
         con_index: usize,
-    ) -> Map<String, Json> {
+    ) -> Result<Map<String, Json>> {
         let mut data = Map::new();
         let log_display = self.log_path().into_os_string().into_string().unwrap();
         let lp = LaunchdParams {
             label: service_name,
-            env: self.environment(con_index),
+            env: self.environment(con_index)?,
             command_args: self.command_args(pe),
             stdout_path: &format!("{}/{}.log", &log_display, &service_name),
             stderr_path: &format!("{}/{}.error.log", &log_display, &service_name),
@@ -88,7 +87,7 @@ impl<'a> Exporter<'a> {
             work_dir: &self.root_path().into_os_string().into_string().unwrap(),
         };
         data.insert("launchd".to_string(), to_json(&lp));
-        data
+        Ok(data)
     }
 
     fn command_args(&self, pe: &'a ProcfileEntry) -> Vec<&'a str> {
@@ -100,13 +99,13 @@ impl<'a> Exporter<'a> {
         result
     }
 
-    fn environment(&self, con_index: usize) -> Vec<EnvParameter> {
+    fn environment(&self, con_index: usize) -> Result<Vec<EnvParameter>> {
         let port = port_for(
             &self.opts.env_path.clone().unwrap(),
             self.opts.port.clone(),
             con_index,
-        );
-        let mut env = read_env(self.opts.env_path.clone().unwrap()).expect("failed read .env");
+        )?;
+        let mut env = read_env(self.opts.env_path.clone().unwrap())?;
         env.insert("PORT".to_string(), port.to_string());
 
         let mut result = vec![];
@@ -117,13 +116,13 @@ impl<'a> Exporter<'a> {
             });
         }
 
-        result
+        Ok(result)
     }
 }
 
 impl<'a> Exportable for Exporter<'a> {
-    fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.base_export().expect("failed execute base_export");
+    fn export(&self) -> Result<()> {
+        self.base_export()?;
 
         let mut clean_paths: Vec<PathBuf> = vec![];
         let mut tmpl_data: Vec<Template> = vec![];
@@ -137,18 +136,18 @@ impl<'a> Exportable for Exporter<'a> {
                 clean_paths.push(output_path.clone());
                 tmpl_data.push(Template {
                     template_path: self.launchd_tmpl_path(),
-                    data: self.make_launchd_data(pe, &service_name, n),
+                    data: self.make_launchd_data(pe, &service_name, n)?,
                     output_path,
                 });
             }
         }
 
         for path in clean_paths {
-            self.clean(&path);
+            self.clean(&path)?;
         }
 
         for tmpl in tmpl_data {
-            self.write_template(tmpl);
+            self.write_template(tmpl)?;
         }
 
         Ok(())
@@ -1,9 +1,11 @@
-use crate::cmd::export::base::Exportable;
-use crate::config::{read_config, Config};
+use crate::config::{Config, RawConfig};
+use crate::error::{Result, UltramanError};
 use crate::procfile::read_procfile;
 use std::path::PathBuf;
 use structopt::{clap, StructOpt};
 
+pub use base::Exportable;
+
 pub mod base;
 pub mod daemon;
 pub mod launchd;
@@ -27,7 +29,7 @@ pub struct ExportOpts {
     #[structopt(name = "APP", short = "a", long = "app")]
     pub app: Option<String>,
 
-    /// Specify the number of each process type to run. The value passed in should be in the format process=num,process=num
+    /// Specify the number of each process type to run. The value passed in should be in the format process=num,process=num, where all=num sets the number for processes not listed
     #[structopt(name = "APP=NUMBER", short = "m", long = "formation")]
     pub formation: Option<String>,
 
@@ -66,9 +68,13 @@ pub struct ExportOpts {
     /// Specify the amount of time (in seconds) processes have to shutdown gracefully before receiving a SIGTERM
     #[structopt(name = "TIMEOUT (sec)", short = "t", long = "timeout")]
     pub timeout: Option<u64>,
+
+    /// Use the named profile from .ultraman (also ULTRAMAN_PROFILE)
+    #[structopt(name = "PROFILE", long = "profile")]
+    pub profile: Option<String>,
 }
 
-enum ExportFormat {
+pub enum ExportFormat {
     Upstart,
     Systemd,
     Supervisord,
@@ -77,154 +83,126 @@ enum ExportFormat {
     Daemon,
 }
 
-pub fn run(input_opts: ExportOpts) -> Result<(), Box<dyn std::error::Error>> {
-    let dotconfig = read_config(PathBuf::from(".ultraman")).unwrap();
-    let opts = merged_opts(&input_opts, dotconfig);
-    let exporter = new(&opts);
-    exporter.export().expect("failed ultraman export");
-
-    Ok(())
-}
-
-fn new(opts: &ExportOpts) -> Box<dyn Exportable> {
-    let procfile_path = opts.procfile_path.clone().unwrap();
-    let display = procfile_path
-        .clone()
-        .into_os_string()
-        .into_string()
-        .unwrap();
-    let procfile =
-        read_procfile(procfile_path).expect(&format!("Could not read Procfile: {}", display));
-    let format = opts.format.as_str();
+// Build the exporter for `opts.format` with the Procfile and formation already applied
+pub fn new(opts: &ExportOpts) -> Result<Box<dyn Exportable>> {
+    let format = export_format(opts.format.as_str())?;
+    let procfile = read_procfile(opts.procfile_path.clone().unwrap())?;
+    // Read the formation from the command line option and always call it before process_len for the convenience of setting concurrency
+    procfile.set_concurrency(&opts.formation.clone().unwrap().parse()?)?;
 
-    match export_format(format) {
+    let exporter: Box<dyn Exportable> = match format {
         ExportFormat::Upstart => {
             let mut expo = upstart::Exporter::boxed_new();
-            // Read the formation from the command line option and always call it before process_len for the convenience of setting concurrency
-            procfile.set_concurrency(&opts.formation.clone().unwrap());
             expo.procfile = procfile;
             expo.opts = opts.clone();
             expo
         }
         ExportFormat::Systemd => {
             let mut expo = systemd::Exporter::boxed_new();
-            procfile.set_concurrency(&opts.formation.clone().unwrap());
             expo.procfile = procfile;
             expo.opts = opts.clone();
             expo
         }
         ExportFormat::Supervisord => {
             let mut expo = supervisord::Exporter::boxed_new();
-            procfile.set_concurrency(&opts.formation.clone().unwrap());
             expo.procfile = procfile;
             expo.opts = opts.clone();
             expo
         }
         ExportFormat::Runit => {
             let mut expo = runit::Exporter::boxed_new();
-            procfile.set_concurrency(&opts.formation.clone().unwrap());
             expo.procfile = procfile;
             expo.opts = opts.clone();
             expo
         }
         ExportFormat::Launchd => {
             let mut expo = launchd::Exporter::boxed_new();
-            procfile.set_concurrency(&opts.formation.clone().unwrap());
             expo.procfile = procfile;
             expo.opts = opts.clone();
             expo
         }
         ExportFormat::Daemon => {
             let mut expo = daemon::Exporter::boxed_new();
-            procfile.set_concurrency(&opts.formation.clone().unwrap());
             expo.procfile = procfile;
             expo.opts = opts.clone();
             expo
         }
-    }
+    };
+
+    Ok(exporter)
 }
 
-fn export_format(format: &str) -> ExportFormat {
+pub fn export_format(format: &str) -> Result<ExportFormat> {
     if format == "upstart" {
-        ExportFormat::Upstart
+        Ok(ExportFormat::Upstart)
     } else if format == "systemd" {
-        ExportFormat::Systemd
+        Ok(ExportFormat::Systemd)
     } else if format == "supervisord" {
-        ExportFormat::Supervisord
+        Ok(ExportFormat::Supervisord)
     } else if format == "runit" {
-        ExportFormat::Runit
+        Ok(ExportFormat::Runit)
     } else if format == "launchd" {
-        ExportFormat::Launchd
+        Ok(ExportFormat::Launchd)
     } else if format == "daemon" {
-        ExportFormat::Daemon
+        Ok(ExportFormat::Daemon)
     } else {
-        panic!("Do not support format {}", format)
+        Err(UltramanError::UnsupportedFormat(format.to_string()))
     }
 }
 
-fn merged_opts(input_opts: &ExportOpts, dotconfig: Config) -> ExportOpts {
+impl From<&ExportOpts> for RawConfig {
+    fn from(opts: &ExportOpts) -> Self {
+        RawConfig {
+            procfile: opts.procfile_path.clone(),
+            env: opts.env_path.clone(),
+            formation: opts.formation.clone(),
+            timeout: opts.timeout,
+            port: opts.port,
+            app: opts.app.clone(),
+            log: opts.log_path.clone(),
+            run: opts.run_path.clone(),
+            template: opts.template_path.clone(),
+            user: opts.user.clone(),
+            root: opts.root_path.clone(),
+            profile: opts.profile.clone(),
+            ..RawConfig::default()
+        }
+    }
+}
+
+// Exporters read their settings from ExportOpts, so fill it with the effective values
+pub fn resolved_opts(input_opts: &ExportOpts, config: Config) -> ExportOpts {
     ExportOpts {
         format: input_opts.format.to_string(),
-        location: input_opts.clone().location,
-        formation: match &input_opts.formation {
-            Some(r) => Some(r.to_string()),
-            None => Some(dotconfig.formation),
-        },
-        env_path: match &input_opts.env_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => Some(dotconfig.env_path),
-        },
-        procfile_path: match &input_opts.procfile_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => Some(dotconfig.procfile_path),
-        },
-        timeout: match &input_opts.timeout {
-            Some(r) => Some(*r),
-            None => Some(dotconfig.timeout),
-        },
-        port: match &input_opts.port {
-            Some(r) => Some(*r),
-            None => dotconfig.port.map(|r| r as u32),
-        },
-        app: match &input_opts.app {
-            Some(r) => Some(r.to_string()),
-            None => dotconfig.app,
-        },
-        log_path: match &input_opts.log_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => dotconfig.log_path,
-        },
-        root_path: match &input_opts.root_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => dotconfig.root_path,
-        },
-        run_path: match &input_opts.run_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => dotconfig.run_path,
-        },
-        template_path: match &input_opts.template_path {
-            Some(r) => Some(PathBuf::from(r)),
-            None => dotconfig.template_path,
-        },
-        user: match &input_opts.user {
-            Some(r) => Some(r.to_string()),
-            None => dotconfig.user,
-        },
+        location: input_opts.location.clone(),
+        formation: Some(config.formation),
+        env_path: Some(config.env_path),
+        procfile_path: Some(config.procfile_path),
+        timeout: Some(config.timeout),
+        port: config.port,
+        app: config.app,
+        log_path: config.log_path,
+        root_path: config.root_path,
+        run_path: config.run_path,
+        template_path: config.template_path,
+        user: config.user,
+        profile: config.profile,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ConfigLoader;
+    use std::collections::HashMap;
     use std::fs::File;
     use std::io::Write;
     use tempfile::tempdir;
 
-    fn prepare_dotconfig() -> Config {
+    fn load_with_dotconfig(input_opts: &ExportOpts) -> Config {
         let dir = tempdir().ok().unwrap();
         let file_path = dir.path().join(".ultraman");
         let mut file = File::create(file_path.clone()).ok().unwrap();
-        // Writing a comment causes a parse error
         writeln!(
             file,
             r#"
@@ -242,7 +220,7 @@ no-timestamp: true
 app: app-for-runit
 log: /var/app/log/ultraman.log
 run: /tmp/pids/ultraman.pid
-template: ../../src/cmd/export/templates/supervisord
+template: ../../src/export/templates/supervisord
 user: root
 root: /home/app
 
@@ -251,12 +229,13 @@ hoge: hogehoge
         )
         .unwrap();
 
-        let dotconfig = read_config(file_path).expect("failed read .ultraman");
-        dotconfig
+        ConfigLoader::new(dir.path().to_path_buf(), HashMap::new())
+            .load(RawConfig::from(input_opts))
+            .expect("failed load .ultraman")
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_dotconfig() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_dotconfig() -> anyhow::Result<()> {
         let input_opts = ExportOpts {
             format: String::from("upstart"),
             location: PathBuf::from("./test/location"),
@@ -271,21 +250,18 @@ hoge: hogehoge
             run_path: None,
             template_path: None,
             user: None,
+            profile: None,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
 
-        assert_eq!(result.format, "upstart");
-        assert_eq!(result.location, PathBuf::from("./test/location"));
-        assert_eq!(result.formation.unwrap(), "app=1,web=2");
-        assert_eq!(result.env_path.unwrap(), PathBuf::from("./tmp/.env"));
-        assert_eq!(
-            result.procfile_path.unwrap(),
-            PathBuf::from("./tmp/Procfile")
-        );
+        assert_eq!(input_opts.format, "upstart");
+        assert_eq!(input_opts.location, PathBuf::from("./test/location"));
+        assert_eq!(result.formation, "app=1,web=2");
+        assert_eq!(result.env_path, PathBuf::from("./tmp/.env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./tmp/Procfile"));
         assert_eq!(result.port.unwrap(), 6000);
-        assert_eq!(result.timeout.unwrap(), 5000);
+        assert_eq!(result.timeout, 5000);
         assert_eq!(result.app.unwrap(), "app-for-runit");
         assert_eq!(
             result.log_path.unwrap(),
@@ -298,7 +274,7 @@ hoge: hogehoge
         );
         assert_eq!(
             result.template_path.unwrap(),
-            PathBuf::from("../../src/cmd/export/templates/supervisord")
+            PathBuf::from("../../src/export/templates/supervisord")
         );
         assert_eq!(result.user.unwrap(), "root");
 
@@ -306,7 +282,7 @@ hoge: hogehoge
     }
 
     #[test]
-    fn test_merged_opts_when_prefer_input_opts() -> anyhow::Result<()> {
+    fn test_load_config_when_prefer_input_opts() -> anyhow::Result<()> {
         let input_opts = ExportOpts {
             format: String::from("upstart"),
             location: PathBuf::from("./test/location"),
@@ -321,21 +297,18 @@ hoge: hogehoge
             run_path: Some(PathBuf::from("./test/run")),
             template_path: Some(PathBuf::from("./test/template")),
             user: Some("user".to_string()),
+            profile: None,
         };
 
-        let dotconfig = prepare_dotconfig();
-        let result = merged_opts(&input_opts, dotconfig);
+        let result = load_with_dotconfig(&input_opts);
 
-        assert_eq!(result.format, "upstart");
-        assert_eq!(result.location, PathBuf::from("./test/location"));
-        assert_eq!(result.formation.unwrap(), "app=2,web=2,server=2");
-        assert_eq!(result.env_path.unwrap(), PathBuf::from("./test/.env"));
-        assert_eq!(
-            result.procfile_path.unwrap(),
-            PathBuf::from("./test/Procfile")
-        );
+        assert_eq!(input_opts.format, "upstart");
+        assert_eq!(input_opts.location, PathBuf::from("./test/location"));
+        assert_eq!(result.formation, "app=2,web=2,server=2");
+        assert_eq!(result.env_path, PathBuf::from("./test/.env"));
+        assert_eq!(result.procfile_path, PathBuf::from("./test/Procfile"));
         assert_eq!(result.port.unwrap(), 9999);
-        assert_eq!(result.timeout.unwrap(), 9999);
+        assert_eq!(result.timeout, 9999);
         assert_eq!(result.app.unwrap(), "app");
         assert_eq!(result.log_path.unwrap(), PathBuf::from("./test/log"));
         assert_eq!(result.root_path.unwrap(), PathBuf::from("./test/root"));
@@ -1,12 +1,12 @@
-use super::base::{Exportable, Template};
-use crate::cmd::export::ExportOpts;
+use super::base::{export_error, Exportable, Template};
 use crate::env::read_env;
+use crate::error::Result;
+use crate::export::ExportOpts;
 use crate::process::port_for;
 use crate::procfile::{Procfile, ProcfileEntry};
 use handlebars::to_json;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
-use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::io::Write;
@@ -36,9 +36,7 @@ struct LogRunParams<'a> {
 impl<'a> Default for Exporter<'a> {
     fn default() -> Self {
         Exporter {
-            procfile: Procfile {
-                data: HashMap::new(),
-            },
+            procfile: Procfile::default(),
             opts: ExportOpts {
                 format: String::from(""),
                 location: PathBuf::from("location"),
@@ -53,6 +51,7 @@ impl<'a> Default for Exporter<'a> {
                 procfile_path: Some(PathBuf::from("Procfile")),
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: Some(5),
+                profile: None,
             },
             _marker: PhantomData,
         }
@@ -70,14 +69,14 @@ impl<'a> Exporter<'a> {
 
     fn run_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/runit/run.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/runit/run.hbs");
         path.push(tmpl_path);
         path
     }
 
     fn log_run_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/runit/log/run.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/runit/log/run.hbs");
         path.push(tmpl_path);
         path
     }
@@ -109,24 +108,26 @@ impl<'a> Exporter<'a> {
         data
     }
 
-    fn write_env(&self, output_dir_path: &PathBuf, con_index: usize) {
-        let mut env = read_env(self.opts.env_path.clone().unwrap()).expect("failed read .env");
+    fn write_env(&self, output_dir_path: &PathBuf, con_index: usize) -> Result<()> {
+        let mut env = read_env(self.opts.env_path.clone().unwrap())?;
         let port = port_for(
             &self.opts.env_path.clone().unwrap(),
             self.opts.port.clone(),
             con_index,
-        );
+        )?;
         env.insert("PORT".to_string(), port.to_string());
 
         for (key, val) in env.iter() {
-            let path = output_dir_path.join(&key);
-            let display = path.clone().into_os_string().into_string().unwrap();
-            self.clean(&path);
+            let path = output_dir_path.join(key);
+            self.clean(&path)?;
             let mut file =
-                File::create(path.clone()).expect(&format!("Could not create file: {}", &display));
-            self.say(&format!("writing: {}", &display));
-            writeln!(&mut file, "{}", &val).expect(&format!("Could not write file: {}", &display));
+                File::create(&path).map_err(|e| export_error(&path, "could not create", e))?;
+            self.say(&format!("writing: {}", path.display()));
+            writeln!(&mut file, "{}", &val)
+                .map_err(|e| export_error(&path, "could not write", e))?;
         }
+
+        Ok(())
     }
 }
 
@@ -137,8 +138,8 @@ struct EnvTemplate<'a> {
 }
 
 impl<'a> Exportable for Exporter<'a> {
-    fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.base_export().expect("failed execute base_export");
+    fn export(&self) -> Result<()> {
+        self.base_export()?;
 
         let mut clean_paths: Vec<PathBuf> = vec![];
         let mut create_recursive_dir_paths: Vec<PathBuf> = vec![];
@@ -192,19 +193,19 @@ impl<'a> Exportable for Exporter<'a> {
         }
 
         for path in clean_paths {
-            self.clean(&path);
+            self.clean(&path)?;
         }
 
         for dir_path in create_recursive_dir_paths {
-            self.create_dir_recursive(&dir_path);
+            self.create_dir_recursive(&dir_path)?;
         }
 
         for tmpl in tmpl_data {
-            self.write_template(tmpl);
+            self.write_template(tmpl)?;
         }
 
         for e in env_data {
-            self.write_env(&e.template_path, e.con_index);
+            self.write_env(&e.template_path, e.con_index)?;
         }
 
         Ok(())
@@ -1,6 +1,7 @@
 use super::base::{Exportable, Template};
-use crate::cmd::export::ExportOpts;
 use crate::env::read_env;
+use crate::error::Result;
+use crate::export::ExportOpts;
 use crate::process::port_for;
 use crate::procfile::Procfile;
 use handlebars::to_json;
@@ -8,7 +9,6 @@ use regex::Regex;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
 use shellwords::escape;
-use std::collections::HashMap;
 use std::env;
 use std::marker::PhantomData;
 use std::path::PathBuf;
@@ -42,9 +42,7 @@ struct AppConfParams<'a> {
 impl<'a> Default for Exporter<'a> {
     fn default() -> Self {
         Exporter {
-            procfile: Procfile {
-                data: HashMap::new(),
-            },
+            procfile: Procfile::default(),
             opts: ExportOpts {
                 format: String::from(""),
                 location: PathBuf::from("location"),
@@ -59,6 +57,7 @@ impl<'a> Default for Exporter<'a> {
                 procfile_path: Some(PathBuf::from("Procfile")),
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: Some(5),
+                profile: None,
             },
             _marker: PhantomData,
         }
@@ -76,7 +75,7 @@ impl<'a> Exporter<'a> {
 
     fn app_conf_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/supervisord/app.conf.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/supervisord/app.conf.hbs");
         path.push(tmpl_path);
         path
     }
@@ -100,13 +99,13 @@ impl<'a> Exporter<'a> {
         tmpldata
     }
 
-    fn environment(&self, con_index: usize) -> String {
+    fn environment(&self, con_index: usize) -> Result<String> {
         let port = port_for(
             &self.opts.env_path.clone().unwrap(),
             self.opts.port.clone(),
             con_index,
-        );
-        let mut env = read_env(self.opts.env_path.clone().unwrap()).expect("failed read .env");
+        )?;
+        let mut env = read_env(self.opts.env_path.clone().unwrap())?;
         env.insert("PORT".to_string(), port.to_string());
 
         let mut result = vec![];
@@ -114,7 +113,7 @@ impl<'a> Exporter<'a> {
             result.push(format!("{}=\"{}\"", &key, escape(&val)))
         }
 
-        result.join(",")
+        Ok(result.join(","))
     }
 
     // http://supervisord.org/configuration.html?highlight=environment#environment-variables
@@ -126,8 +125,8 @@ impl<'a> Exporter<'a> {
 }
 
 impl<'a> Exportable for Exporter<'a> {
-    fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.base_export().expect("failed execute base_export");
+    fn export(&self) -> Result<()> {
+        self.base_export()?;
 
         let mut service_names = vec![];
         let mut data: Vec<AppConfDataParams> = vec![];
@@ -136,7 +135,7 @@ impl<'a> Exportable for Exporter<'a> {
             for n in 0..con {
                 let program = format!("{}-{}-{}", self.app(), &name, n + 1);
                 let process_command = self.replace_env_for_supervisord(&pe.command);
-                let environment = self.environment(n);
+                let environment = self.environment(n)?;
                 let display_log = self.log_path().into_os_string().into_string().unwrap();
                 let stdout_logfile = format!("{}/{}-{}.log", &display_log, &name, n + 1);
                 let stderr_logfile = format!("{}/{}-{}.error.log", &display_log, &name, n + 1);
@@ -154,14 +153,12 @@ impl<'a> Exportable for Exporter<'a> {
         }
 
         let output_path = self.output_path("app.conf");
-        self.clean(&output_path);
+        self.clean(&output_path)?;
         self.write_template(Template {
             template_path: self.app_conf_tmpl_path(),
             data: self.make_app_conf_data(service_names, data),
             output_path,
-        });
-
-        Ok(())
+        })
     }
 
     fn ref_opts(&self) -> &ExportOpts {
@@ -1,11 +1,11 @@
 use super::base::{EnvParameter, Exportable, Template};
-use crate::cmd::export::ExportOpts;
+use crate::error::Result;
+use crate::export::ExportOpts;
 use crate::process::port_for;
 use crate::procfile::{Procfile, ProcfileEntry};
 use handlebars::to_json;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
-use std::collections::HashMap;
 use std::env;
 use std::marker::PhantomData;
 use std::path::PathBuf;
@@ -36,9 +36,7 @@ struct ProcessServiceParams<'a> {
 impl<'a> Default for Exporter<'a> {
     fn default() -> Self {
         Exporter {
-            procfile: Procfile {
-                data: HashMap::new(),
-            },
+            procfile: Procfile::default(),
             opts: ExportOpts {
                 format: String::from(""),
                 location: PathBuf::from("location"),
@@ -53,6 +51,7 @@ impl<'a> Default for Exporter<'a> {
                 procfile_path: Some(PathBuf::from("Procfile")),
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: Some(5),
+                profile: None,
             },
             _marker: PhantomData,
         }
@@ -70,14 +69,14 @@ impl<'a> Exporter<'a> {
 
     fn master_target_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/systemd/master.target.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/systemd/master.target.hbs");
         path.push(tmpl_path);
         path
     }
 
     fn process_service_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/systemd/process.service.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/systemd/process.service.hbs");
         path.push(tmpl_path);
         path
     }
@@ -100,7 +99,7 @@ impl<'a> Exporter<'a> {
         pe: &ProcfileEntry,
         process_name: &str,
         con_index: usize,
-    ) -> Map<String, Json> {
+    ) -> Result<Map<String, Json>> {
         let mut data = Map::new();
         let ps = ProcessServiceParams {
             app: self.app(),
@@ -110,20 +109,20 @@ impl<'a> Exporter<'a> {
                 &self.opts.env_path.clone().unwrap(),
                 self.opts.port,
                 con_index,
-            ),
+            )?,
             process_name,
             process_command: &pe.command,
-            env_without_port: self.env_without_port(),
+            env_without_port: self.env_without_port()?,
             timeout: self.opts.timeout.as_ref().unwrap(),
         };
         data.insert("process_service".to_string(), to_json(&ps));
-        data
+        Ok(data)
     }
 }
 
 impl<'a> Exportable for Exporter<'a> {
-    fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.base_export().expect("failed execute base_export");
+    fn export(&self) -> Result<()> {
+        self.base_export()?;
 
         let mut service_names = vec![];
         let mut clean_paths: Vec<PathBuf> = vec![];
@@ -135,7 +134,7 @@ impl<'a> Exportable for Exporter<'a> {
                 let process_name = format!("{}.{}", &name, n);
                 let service_filename = format!("{}-{}.service", &name, &process_name);
                 let output_path = self.output_path(&service_filename);
-                let data = self.make_process_service_data(pe, &process_name, n);
+                let data = self.make_process_service_data(pe, &process_name, n)?;
 
                 clean_paths.push(output_path.clone());
                 tmpl_data.push(Template {
@@ -158,11 +157,11 @@ impl<'a> Exportable for Exporter<'a> {
         });
 
         for path in clean_paths {
-            self.clean(&path);
+            self.clean(&path)?;
         }
 
         for tmpl in tmpl_data {
-            self.write_template(tmpl);
+            self.write_template(tmpl)?;
         }
 
         Ok(())
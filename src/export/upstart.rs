@@ -1,12 +1,12 @@
 use super::base::{EnvParameter, Exportable, Template};
-use crate::cmd::export::ExportOpts;
+use crate::error::Result;
+use crate::export::ExportOpts;
 use crate::process::port_for;
 use crate::procfile::{Procfile, ProcfileEntry};
 
 use handlebars::to_json;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
-use std::collections::HashMap;
 use std::env;
 use std::marker::PhantomData;
 use std::path::PathBuf;
@@ -45,21 +45,21 @@ impl<'a> Exporter<'a> {
 
     fn master_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/upstart/master.conf.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/upstart/master.conf.hbs");
         path.push(tmpl_path);
         path
     }
 
     fn process_master_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/upstart/process_master.conf.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/upstart/process_master.conf.hbs");
         path.push(tmpl_path);
         path
     }
 
     fn process_tmpl_path(&self) -> PathBuf {
         let mut path = self.project_root_path();
-        let tmpl_path = PathBuf::from("src/cmd/export/templates/upstart/process.conf.hbs");
+        let tmpl_path = PathBuf::from("src/export/templates/upstart/process.conf.hbs");
         path.push(tmpl_path);
         path
     }
@@ -76,7 +76,7 @@ impl<'a> Exporter<'a> {
         pe: &ProcfileEntry,
         app_name: &str,
         con_index: usize,
-    ) -> Map<String, Json> {
+    ) -> Result<Map<String, Json>> {
         let mut data = Map::new();
 
         let p = ProcessParams {
@@ -86,23 +86,21 @@ impl<'a> Exporter<'a> {
                 &self.opts.env_path.clone().unwrap(),
                 self.opts.port.clone(),
                 con_index,
-            ),
-            env_without_port: self.env_without_port(),
+            )?,
+            env_without_port: self.env_without_port()?,
             setuid: self.username(),
             chdir: &self.root_path().into_os_string().into_string().unwrap(),
             exec: &pe.command,
         };
         data.insert("process".to_string(), to_json(&p));
-        data
+        Ok(data)
     }
 }
 
 impl<'a> Default for Exporter<'a> {
     fn default() -> Self {
         Exporter {
-            procfile: Procfile {
-                data: HashMap::new(),
-            },
+            procfile: Procfile::default(),
             opts: ExportOpts {
                 format: String::from(""),
                 location: PathBuf::from("location"),
@@ -117,6 +115,7 @@ impl<'a> Default for Exporter<'a> {
                 procfile_path: Some(PathBuf::from("Procfile")),
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: Some(5),
+                profile: None,
             },
             _marker: PhantomData,
         }
@@ -124,8 +123,8 @@ impl<'a> Default for Exporter<'a> {
 }
 
 impl<'a> Exportable for Exporter<'a> {
-    fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
-        self.base_export().expect("failed execute base_export");
+    fn export(&self) -> Result<()> {
+        self.base_export()?;
 
         let mut clean_paths: Vec<PathBuf> = vec![];
         let mut tmpl_data: Vec<Template> = vec![];
@@ -159,18 +158,18 @@ impl<'a> Exportable for Exporter<'a> {
                 clean_paths.push(output_path.clone());
                 tmpl_data.push(Template {
                     template_path: self.process_tmpl_path(),
-                    data: self.make_process_data(pe, &name, n),
+                    data: self.make_process_data(pe, &name, n)?,
                     output_path,
                 });
             }
         }
 
         for path in clean_paths {
-            self.clean(&path);
+            self.clean(&path)?;
         }
 
         for tmpl in tmpl_data {
-            self.write_template(tmpl);
+            self.write_template(tmpl)?;
         }
 
         Ok(())
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+// e.g.) all=2, web=1, worker=0
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Formation {
+    // Concurrency for processes without their own entry
+    pub all: Option<usize>,
+    pub processes: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FormationError {
+    Empty,
+    MissingCount { token: String },
+    MissingName { token: String },
+    InvalidName { token: String },
+    InvalidCount { token: String },
+    Duplicate { name: String },
+    UnknownProcess { name: String, defined: String },
+}
+
+impl fmt::Display for FormationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormationError::Empty => write!(f, "no process=num pairs given"),
+            FormationError::MissingCount { token } => {
+                write!(f, "`{}` has no number (expected `{}=num`)", token, token)
+            }
+            FormationError::MissingName { token } => {
+                write!(f, "`{}` has no process name", token)
+            }
+            FormationError::InvalidName { token } => write!(
+                f,
+                "`{}` is not a valid process name (letters, numbers, `_` and `-` only)",
+                token
+            ),
+            FormationError::InvalidCount { token } => {
+                write!(f, "`{}` is not a non-negative number", token)
+            }
+            FormationError::Duplicate { name } => write!(f, "`{}` is given more than once", name),
+            FormationError::UnknownProcess { name, defined } => write!(
+                f,
+                "no process called `{}` in the Procfile (defined: {})",
+                name, defined
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormationError {}
+
+impl FromStr for Formation {
+    type Err = FormationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut formation = Formation::default();
+
+        // a trailing comma is harmless, so empty tokens are skipped
+        for token in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let (name, count) = match token.split_once('=') {
+                Some((name, count)) => (name.trim(), count.trim()),
+                None => {
+                    return Err(FormationError::MissingCount {
+                        token: token.to_string(),
+                    })
+                }
+            };
+
+            if name.is_empty() {
+                return Err(FormationError::MissingName {
+                    token: token.to_string(),
+                });
+            }
+            if !is_valid_name(name) {
+                return Err(FormationError::InvalidName {
+                    token: name.to_string(),
+                });
+            }
+            if count.is_empty() {
+                return Err(FormationError::MissingCount {
+                    token: name.to_string(),
+                });
+            }
+            let count = count
+                .parse::<usize>()
+                .map_err(|_| FormationError::InvalidCount {
+                    token: count.to_string(),
+                })?;
+
+            let is_duplicate = if name == "all" {
+                formation.all.replace(count).is_some()
+            } else {
+                formation
+                    .processes
+                    .insert(name.to_string(), count)
+                    .is_some()
+            };
+            if is_duplicate {
+                return Err(FormationError::Duplicate {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        if formation.all.is_none() && formation.processes.is_empty() {
+            return Err(FormationError::Empty);
+        }
+
+        Ok(formation)
+    }
+}
+
+impl Formation {
+    pub fn concurrency_for(&self, name: &str) -> usize {
+        // Processes that are not mentioned don't run unless `all` is given
+        self.processes
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| self.all.unwrap_or(0))
+    }
+
+    // Every named process must exist in the Procfile
+    pub fn validate<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), FormationError> {
+        let mut names = names.into_iter().collect::<Vec<_>>();
+        names.sort_unstable();
+
+        match self
+            .processes
+            .keys()
+            .find(|name| names.binary_search(&name.as_str()).is_err())
+        {
+            Some(name) => Err(FormationError::UnknownProcess {
+                name: name.to_string(),
+                defined: names.join(", "),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let result: Formation = "all=2, web = 1 ,worker=0,".parse()?;
+        assert_eq!(result.all, Some(2));
+        assert_eq!(result.concurrency_for("web"), 1);
+        assert_eq!(result.concurrency_for("worker"), 0);
+        assert_eq!(result.concurrency_for("clock"), 2);
+
+        let result: Formation = "web=3".parse()?;
+        assert_eq!(result.all, None);
+        assert_eq!(result.concurrency_for("web"), 3);
+        assert_eq!(result.concurrency_for("worker"), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_when_error() -> anyhow::Result<()> {
+        let cases = vec![
+            ("", FormationError::Empty),
+            (" , ", FormationError::Empty),
+            (
+                "web",
+                FormationError::MissingCount {
+                    token: String::from("web"),
+                },
+            ),
+            (
+                "web=",
+                FormationError::MissingCount {
+                    token: String::from("web"),
+                },
+            ),
+            (
+                "=2",
+                FormationError::MissingName {
+                    token: String::from("=2"),
+                },
+            ),
+            (
+                "web.1=2",
+                FormationError::InvalidName {
+                    token: String::from("web.1"),
+                },
+            ),
+            (
+                "all=2,web=x",
+                FormationError::InvalidCount {
+                    token: String::from("x"),
+                },
+            ),
+            (
+                "web=-1",
+                FormationError::InvalidCount {
+                    token: String::from("-1"),
+                },
+            ),
+            (
+                "web=1,web=2",
+                FormationError::Duplicate {
+                    name: String::from("web"),
+                },
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                input.parse::<Formation>().unwrap_err(),
+                expected,
+                "{}",
+                input
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> anyhow::Result<()> {
+        let formation: Formation = "all=1,web=2,wrker=1".parse()?;
+        assert_eq!(
+            formation.validate(vec!["worker", "web"]).unwrap_err(),
+            FormationError::UnknownProcess {
+                name: String::from("wrker"),
+                defined: String::from("web, worker"),
+            }
+        );
+        assert!(formation.validate(vec!["web", "wrker"]).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+// Ultraman is a manager for Procfile-based applications. The `ultraman` command is a thin
+// layer over this crate, so a Procfile can be parsed, scaled, exported or started in-process
+// the same way.
+//
+// e.g.)
+// let procfile = Procfile::parse("web: ./web.sh\nworker: ./worker.sh");
+// Supervisor::new(procfile)
+//     .formation("all=1,web=2".parse()?)
+//     .run()?;
+
+pub mod config;
+pub mod control;
+pub mod env;
+pub mod error;
+pub mod export;
+pub mod formation;
+pub mod procfile;
+
+mod log;
+mod output;
+mod process;
+mod pty;
+mod signal;
+mod stream_read;
+mod supervisor;
+mod tui;
+
+pub use error::{Result, UltramanError};
+pub use export::Exportable as Exporter;
+pub use formation::{Formation, FormationError};
+pub use log::file::{LogFiles, Rotation};
+pub use log::{ColorChoice, LogFormat, Stream, TimestampFormat};
+pub use output::{Decoding, Filter, LinePattern};
+pub use process::{env_for, port_for, Runner};
+pub use procfile::Procfile;
+pub use supervisor::{Event, Handle, ShutdownReason, Supervisor};
@@ -1,5 +1,5 @@
-use crate::log::{now, Printable};
-use crate::opt::DisplayOpts;
+use crate::log::writer::Writer;
+use crate::log::{LogOpt, Printable, Source, Stream};
 use colored::*;
 
 const COLORS: [&str; 12] = [
@@ -16,11 +16,19 @@ const COLORS: [&str; 12] = [
     "bright_red",
     "bright_green",
 ];
+// Not one of COLORS, so that system lines don't look like the first process
+pub const SYSTEM_COLOR: &str = "bright black";
+
+// Color of the process at `index` in the Procfile
+pub fn color_for(index: usize) -> &'static str {
+    COLORS[index % COLORS.len()]
+}
 
 #[derive(Default)]
 pub struct Log {
     pub index: usize,
-    pub opts: DisplayOpts,
+    pub opts: LogOpt,
+    pub writer: Writer,
 }
 
 unsafe impl Sync for Log {}
@@ -35,29 +43,55 @@ impl Log {
     pub fn boxed_new() -> Box<Self> {
         Self::default().boxed()
     }
-}
 
-impl Printable for Log {
-    fn output(&self, proc_name: &str, content: &str) {
-        let color = COLORS[self.index % COLORS.len()];
+    fn color(&self, proc_name: &str) -> &'static str {
+        if proc_name == "system" {
+            SYSTEM_COLOR
+        } else {
+            color_for(self.index)
+        }
+    }
 
+    fn prefix(&self, source: &Source) -> String {
+        let color = self.color(source.name);
         if self.opts.is_timestamp {
-            println!(
-                "{3} {0:1$} | {2}",
-                proc_name.color(color),
+            format!(
+                "{2} {0:1$} {3} ",
+                source.name.color(color),
                 self.opts.padding,
-                content.color(color),
-                now().color(color)
+                self.opts.timestamp_format.format(&source.at).color(color),
+                source.separator()
             )
         } else {
-            println!(
-                "{0:1$} | {2}",
-                proc_name.color(color),
+            format!(
+                "{0:1$} {2} ",
+                source.name.color(color),
                 self.opts.padding,
-                content.color(color),
+                source.separator()
             )
         }
     }
+}
+
+impl Printable for Log {
+    fn output_at(&self, source: &Source, content: &str) {
+        let mut line = self.prefix(source);
+        let content = content.color(self.color(source.name));
+        // stderr stands out a little more
+        let content = match source.stream {
+            Some(Stream::Stderr) => content.bold(),
+            _ => content,
+        };
+        line.push_str(&content.to_string());
+        self.writer.write_line(line.into_bytes());
+    }
+
+    // Only the prefix is colored, the content is not text
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        let mut line = self.prefix(source).into_bytes();
+        line.extend_from_slice(content);
+        self.writer.write_line(line);
+    }
 
     fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
         let content = &format!("error: {:?}", err);
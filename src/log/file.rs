@@ -0,0 +1,440 @@
+use crate::error::{Result, UltramanError};
+use crate::log::writer::Writer;
+use crate::log::{self, Log as Console, LogOpt, Printable, Source};
+
+use chrono::{DateTime, Local};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+
+pub const DEFAULT_KEEP: usize = 5;
+
+// Where each instance writes its log file (e.g. web.1 -> DIR/web.1.log) and how it is rotated
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFiles {
+    pub dir: PathBuf,
+    pub rotation: Rotation,
+    // Number of rotated files kept next to the current one
+    pub keep: usize,
+    // Rotated files are compressed in the background and kept uncompressed if that fails
+    pub is_gzip: bool,
+}
+
+impl LogFiles {
+    pub fn new(dir: PathBuf) -> Self {
+        LogFiles {
+            dir,
+            rotation: Rotation::default(),
+            keep: DEFAULT_KEEP,
+            is_gzip: false,
+        }
+    }
+}
+
+// When a log file is rotated. Files are only rotated when a line is written to them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Rotation {
+    #[default]
+    Never,
+    // Before a line would make the file larger than this (in bytes)
+    Size(u64),
+    Daily,
+    Hourly,
+}
+
+const UNITS: [(&str, u64); 3] = [("G", 1 << 30), ("M", 1 << 20), ("K", 1 << 10)];
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "unknown rotation `{}` (expected never, daily, hourly or a size such as 10M)",
+                s
+            )
+        };
+        match s {
+            "never" => return Ok(Rotation::Never),
+            "daily" => return Ok(Rotation::Daily),
+            "hourly" => return Ok(Rotation::Hourly),
+            _ => (),
+        }
+
+        let (number, unit) = UNITS
+            .iter()
+            .find_map(|(suffix, unit)| s.strip_suffix(suffix).map(|number| (number, *unit)))
+            .unwrap_or((s, 1));
+        match number.parse::<u64>() {
+            Ok(number) if number > 0 => number
+                .checked_mul(unit)
+                .map(Rotation::Size)
+                .ok_or_else(error),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl TryFrom<String> for Rotation {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rotation::Never => write!(f, "never"),
+            Rotation::Daily => write!(f, "daily"),
+            Rotation::Hourly => write!(f, "hourly"),
+            Rotation::Size(size) => match UNITS.iter().find(|(_, unit)| size % unit == 0) {
+                Some((suffix, unit)) => write!(f, "{}{}", size / unit, suffix),
+                None => write!(f, "{}", size),
+            },
+        }
+    }
+}
+
+impl Rotation {
+    // Lines written in different periods go to different files
+    fn period(&self, at: &DateTime<Local>) -> Option<String> {
+        match self {
+            Rotation::Daily => Some(at.format("%Y-%m-%d").to_string()),
+            Rotation::Hourly => Some(at.format("%Y-%m-%d %H").to_string()),
+            Rotation::Never | Rotation::Size(_) => None,
+        }
+    }
+}
+
+// A file that is rotated to `path.1`, `path.2`... (or `path.1.gz`...) before it grows too large or old
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    period: Option<String>,
+    rotation: Rotation,
+    keep: usize,
+    is_gzip: bool,
+    // The compression of the last rotated file, which the next rotation waits for
+    gzip: Option<JoinHandle<()>>,
+    // How compression errors are reported
+    log_opt: LogOpt,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, files: &LogFiles, opt: &LogOpt) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let modified: DateTime<Local> = metadata.modified()?.into();
+
+        Ok(RotatingFile {
+            path,
+            file,
+            size: metadata.len(),
+            // A file left from an earlier day is rotated before the first line
+            period: files.rotation.period(&modified),
+            rotation: files.rotation,
+            keep: files.keep,
+            is_gzip: files.is_gzip,
+            gzip: None,
+            log_opt: opt.clone(),
+        })
+    }
+
+    // Whether a line of `len` bytes goes to a new file, after `pending` bytes that are not written yet
+    fn is_due(&self, pending: usize, len: usize) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Size(max) => {
+                let size = self.size + pending as u64;
+                size > 0 && size + len as u64 > max
+            }
+            Rotation::Daily | Rotation::Hourly => {
+                self.rotation.period(&Local::now()) != self.period
+            }
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // Otherwise the file being compressed could be renamed under it
+        if let Some(gzip) = self.gzip.take() {
+            let _ = gzip.join();
+        }
+        for n in (1..=self.keep).rev() {
+            for from in [self.rotated(n, false), self.rotated(n, true)] {
+                if !from.exists() {
+                    continue;
+                }
+                if n == self.keep {
+                    fs::remove_file(from)?;
+                } else {
+                    let is_gzip = from.extension().is_some_and(|ext| ext == "gz");
+                    fs::rename(from, self.rotated(n + 1, is_gzip))?;
+                }
+            }
+        }
+        if self.keep > 0 {
+            let rotated = self.rotated(1, false);
+            fs::rename(&self.path, &rotated)?;
+            if self.is_gzip {
+                self.gzip = Some(gzip(rotated, self.rotated(1, true), self.log_opt.clone())?);
+            }
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.period = self.rotation.period(&Local::now());
+        Ok(())
+    }
+
+    // e.g.) web.1.log -> web.1.log.2.gz
+    fn rotated(&self, n: usize, is_gzip: bool) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{}", n));
+        if is_gzip {
+            path.push(".gz");
+        }
+        PathBuf::from(path)
+    }
+}
+
+// Compresses `from` to `to` on a thread of its own, so lines are not held up meanwhile.
+// `from` stays as it is when that fails
+fn gzip(from: PathBuf, to: PathBuf, opt: LogOpt) -> io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name(String::from("gzip log"))
+        .spawn(move || {
+            if let Err(source) = compress(&from, &to) {
+                let _ = fs::remove_file(&to);
+                let e = UltramanError::LogFile { path: to, source };
+                log::error("system", &e, true, &opt);
+            }
+        })
+}
+
+fn compress(from: &Path, to: &Path) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(from)
+}
+
+impl Write for RotatingFile {
+    // `buf` holds whole lines (usually several), and each of them goes to the file it is due in
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut start = 0;
+        let mut pending = 0;
+        for line in buf.split_inclusive(|b| *b == b'\n') {
+            if self.is_due(pending, line.len()) {
+                self.file.write_all(&buf[start..start + pending])?;
+                self.size += pending as u64;
+                start += pending;
+                pending = 0;
+                if self.is_due(0, line.len()) {
+                    self.rotate()?;
+                }
+            }
+            pending += line.len();
+        }
+        self.file.write_all(&buf[start..])?;
+        self.size += pending as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Writes the lines of each instance of a process to its own file
+pub struct Log {
+    logs: HashMap<String, Box<dyn Printable + Sync + Send>>,
+    writers: Vec<Writer>,
+}
+
+impl Log {
+    // Files are opened up front, so a directory that can't be written to is reported before starting
+    pub fn open(index: usize, names: &[String], opt: &LogOpt, files: &LogFiles) -> Result<Self> {
+        let error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| UltramanError::LogFile { path, source }
+        };
+        fs::create_dir_all(&files.dir).map_err(error(&files.dir))?;
+
+        // Colors are for terminals and the name is in the file name
+        let file_opt = LogOpt {
+            is_color: false,
+            padding: 0,
+            ..opt.clone()
+        };
+        let mut logs = HashMap::new();
+        let mut writers = vec![];
+        for name in names {
+            let path = files.dir.join(format!("{}.log", name));
+            let file = RotatingFile::open(path.clone(), files, opt).map_err(error(&path))?;
+            let writer = Writer::new(file).map_err(error(&path))?;
+            logs.insert(
+                name.clone(),
+                Console::with_writer(index, &file_opt, writer.clone()),
+            );
+            writers.push(writer);
+        }
+
+        Ok(Log { logs, writers })
+    }
+}
+
+impl Printable for Log {
+    fn output_at(&self, source: &Source, content: &str) {
+        if let Some(log) = self.logs.get(source.name) {
+            log.output_at(source, content);
+        }
+    }
+
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        if let Some(log) = self.logs.get(source.name) {
+            log.output_raw(source, content);
+        }
+    }
+
+    fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
+        if let Some(log) = self.logs.get(proc_name) {
+            log.error(proc_name, err);
+        }
+    }
+
+    fn flush(&self) {
+        for writer in self.writers.iter() {
+            writer.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use io::Read;
+    use tempfile::tempdir;
+
+    fn log_opt() -> LogOpt {
+        LogOpt {
+            format: Default::default(),
+            is_color: true,
+            padding: 10,
+            is_timestamp: false,
+            timestamp_format: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_rotation_from_str() -> anyhow::Result<()> {
+        assert_eq!("daily".parse::<Rotation>(), Ok(Rotation::Daily));
+        assert_eq!("512".parse::<Rotation>(), Ok(Rotation::Size(512)));
+        assert_eq!("10M".parse::<Rotation>(), Ok(Rotation::Size(10 << 20)));
+        assert_eq!(Rotation::Size(10 << 20).to_string(), "10M");
+        assert_eq!(Rotation::Size(1000).to_string(), "1000");
+        assert!("0".parse::<Rotation>().is_err());
+        assert!("weekly".parse::<Rotation>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotating_file() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("web.1.log");
+        let files = LogFiles {
+            dir: dir.path().to_path_buf(),
+            rotation: Rotation::Size(10),
+            keep: 2,
+            is_gzip: false,
+        };
+
+        let mut file = RotatingFile::open(path.clone(), &files, &log_opt())?;
+        file.write_all(b"line 1\nline 2\n")?;
+        file.write_all(b"line 3\nline 4\n")?;
+        file.flush()?;
+
+        // line 1 went out of retention
+        assert_eq!(fs::read_to_string(&path)?, "line 4\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("web.1.log.1"))?,
+            "line 3\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("web.1.log.2"))?,
+            "line 2\n"
+        );
+        assert!(!dir.path().join("web.1.log.3").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotating_file_when_gzip() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("web.1.log");
+        let files = LogFiles {
+            dir: dir.path().to_path_buf(),
+            rotation: Rotation::Size(10),
+            keep: 2,
+            is_gzip: true,
+        };
+
+        let mut file = RotatingFile::open(path.clone(), &files, &log_opt())?;
+        file.write_all(b"line 1\nline 2\n")?;
+        file.write_all(b"line 3\n")?;
+        file.flush()?;
+        file.gzip.take().expect("no compression").join().unwrap();
+
+        assert_eq!(fs::read_to_string(&path)?, "line 3\n");
+        // Each rotation waits for the compression of the file before
+        let mut rotated = String::new();
+        GzDecoder::new(File::open(dir.path().join("web.1.log.2.gz"))?)
+            .read_to_string(&mut rotated)?;
+        assert_eq!(rotated, "line 1\n");
+        rotated.clear();
+        GzDecoder::new(File::open(dir.path().join("web.1.log.1.gz"))?)
+            .read_to_string(&mut rotated)?;
+        assert_eq!(rotated, "line 2\n");
+        assert!(!dir.path().join("web.1.log.1").exists());
+        assert!(!dir.path().join("web.1.log.2").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let files = LogFiles::new(dir.path().join("log"));
+
+        let log = Log::open(0, &[String::from("web.1")], &log_opt(), &files)?;
+        log.output("web.1", "hello");
+        log.output("web.2", "not mine");
+        log.flush();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("log/web.1.log"))?,
+            "web.1 | hello\n"
+        );
+
+        Ok(())
+    }
+}
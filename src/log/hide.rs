@@ -0,0 +1,41 @@
+use crate::log::{Printable, Source, Stream};
+
+// Drops the lines a process wrote to `streams` and passes everything else on to `inner`
+pub struct Log {
+    pub inner: Box<dyn Printable + Sync + Send>,
+    pub streams: Vec<Stream>,
+}
+
+impl Log {
+    pub fn boxed_new(inner: Box<dyn Printable + Sync + Send>, streams: Vec<Stream>) -> Box<Self> {
+        Box::new(Log { inner, streams })
+    }
+
+    fn is_hidden(&self, source: &Source) -> bool {
+        source
+            .stream
+            .is_some_and(|stream| self.streams.contains(&stream))
+    }
+}
+
+impl Printable for Log {
+    fn output_at(&self, source: &Source, content: &str) {
+        if !self.is_hidden(source) {
+            self.inner.output_at(source, content);
+        }
+    }
+
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        if !self.is_hidden(source) {
+            self.inner.output_raw(source, content);
+        }
+    }
+
+    fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
+        self.inner.error(proc_name, err);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
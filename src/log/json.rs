@@ -0,0 +1,99 @@
+use crate::log::writer::Writer;
+use crate::log::{Printable, Source, Stream};
+use chrono::SecondsFormat;
+use serde_derive::Serialize;
+
+// e.g.) {"ts":"2022-01-01T12:00:00.000+09:00","process":"web","instance":"web.1","stream":"stdout","pid":1234,"msg":"listening"}
+#[derive(Default)]
+pub struct Log {
+    pub writer: Writer,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    ts: String,
+    process: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<Stream>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    msg: &'a str,
+}
+
+impl Log {
+    pub fn boxed_new() -> Box<Self> {
+        Box::new(Self::default())
+    }
+
+    fn line(&self, source: &Source, content: &str) -> Vec<u8> {
+        let record = Record {
+            ts: source.at.to_rfc3339_opts(SecondsFormat::Millis, false),
+            process: source.process.unwrap_or(source.name),
+            instance: source.process.map(|_| source.name),
+            stream: source.stream,
+            pid: source.pid,
+            msg: content,
+        };
+        // Serializing strings and numbers can't fail
+        serde_json::to_vec(&record).unwrap_or_default()
+    }
+}
+
+impl Printable for Log {
+    fn output_at(&self, source: &Source, content: &str) {
+        self.writer.write_line(self.line(source, content));
+    }
+
+    // JSON strings are text, so invalid UTF-8 is replaced
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        self.output_at(source, &String::from_utf8_lossy(content));
+    }
+
+    fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
+        let content = &format!("error: {:?}", err);
+        self.output(proc_name, content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn test_line() -> anyhow::Result<()> {
+        let at = Local.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+        let ts = at.to_rfc3339_opts(SecondsFormat::Millis, false);
+        let source = Source {
+            name: "web.1",
+            process: Some("web"),
+            pid: Some(1234),
+            stream: Some(Stream::Stderr),
+            at,
+        };
+
+        let line = String::from_utf8(Log::default().line(&source, "say \"hi\"\tthere"))?;
+        assert_eq!(
+            line,
+            format!(
+                r#"{{"ts":"{}","process":"web","instance":"web.1","stream":"stderr","pid":1234,"msg":"say \"hi\"\tthere"}}"#,
+                ts
+            )
+        );
+        assert!(ts.starts_with("2022-01-01T12:00:00.000"));
+
+        let system = Source {
+            at,
+            ..Source::new("system")
+        };
+        let line = String::from_utf8(Log::default().line(&system, "exit 0"))?;
+        assert_eq!(
+            line,
+            format!(r#"{{"ts":"{}","process":"system","msg":"exit 0"}}"#, ts)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::log::writer::Writer;
+use crate::log::{Printable, Source};
+use chrono::SecondsFormat;
+use std::borrow::Cow;
+
+// e.g.) ts=2022-01-01T12:00:00.000+09:00 process=web instance=web.1 stream=stdout pid=1234 msg="listening on 5000"
+#[derive(Default)]
+pub struct Log {
+    pub writer: Writer,
+}
+
+impl Log {
+    pub fn boxed_new() -> Box<Self> {
+        Box::new(Self::default())
+    }
+
+    fn line(&self, source: &Source, content: &str) -> String {
+        let mut pairs = vec![
+            (
+                "ts",
+                source.at.to_rfc3339_opts(SecondsFormat::Millis, false),
+            ),
+            (
+                "process",
+                String::from(source.process.unwrap_or(source.name)),
+            ),
+        ];
+        if source.process.is_some() {
+            pairs.push(("instance", String::from(source.name)));
+        }
+        if let Some(stream) = source.stream {
+            pairs.push(("stream", String::from(stream.as_str())));
+        }
+        if let Some(pid) = source.pid {
+            pairs.push(("pid", pid.to_string()));
+        }
+        pairs.push(("msg", String::from(content)));
+
+        pairs
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, quote(value)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// Values with spaces, quotes, `=` or control characters are quoted and escaped
+fn quote(value: &str) -> Cow<'_, str> {
+    let is_bare = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c == ' ' || c == '"' || c == '=' || c == '\\' || c.is_control());
+    if is_bare {
+        return Cow::Borrowed(value);
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:04x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+impl Printable for Log {
+    fn output_at(&self, source: &Source, content: &str) {
+        self.writer
+            .write_line(self.line(source, content).into_bytes());
+    }
+
+    // Values are text, so invalid UTF-8 is replaced
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        self.output_at(source, &String::from_utf8_lossy(content));
+    }
+
+    fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
+        let content = &format!("error: {:?}", err);
+        self.output(proc_name, content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::Stream;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn test_line() -> anyhow::Result<()> {
+        let at = Local.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+        let ts = at.to_rfc3339_opts(SecondsFormat::Millis, false);
+        let source = Source {
+            name: "web.1",
+            process: Some("web"),
+            pid: Some(1234),
+            stream: Some(Stream::Stdout),
+            at,
+        };
+
+        assert_eq!(
+            Log::default().line(&source, "listening"),
+            format!(
+                "ts={} process=web instance=web.1 stream=stdout pid=1234 msg=listening",
+                ts
+            )
+        );
+        assert_eq!(
+            Log::default().line(
+                &Source {
+                    at,
+                    ..Source::new("system")
+                },
+                "exit 0"
+            ),
+            format!("ts={} process=system msg=\"exit 0\"", ts)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quote() -> anyhow::Result<()> {
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote(""), r#""""#);
+        assert_eq!(quote("a=b"), r#""a=b""#);
+        assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote("tab\there\\"), r#""tab\there\\""#);
+        assert_eq!(quote("bell\u{7}"), r#""bell\u{0007}""#);
+
+        Ok(())
+    }
+}
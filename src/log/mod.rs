@@ -1,44 +1,371 @@
-use crate::opt::DisplayOpts;
-use chrono::Local;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use writer::Writer;
 
 pub mod color;
+pub mod file;
+pub mod hide;
+pub mod json;
+pub mod logfmt;
 pub mod plain;
+pub mod tee;
+pub mod writer;
 
+// Lines are handed to writer::stdout() rather than printed
 pub trait Printable {
-    fn output(&self, proc_name: &str, content: &str);
+    fn output_at(&self, source: &Source, content: &str);
+    // Content that may not be UTF-8, written as it is where the format allows
+    fn output_raw(&self, source: &Source, content: &[u8]);
     fn error(&self, proc_name: &str, err: &dyn std::error::Error);
+
+    fn output(&self, proc_name: &str, content: &str) {
+        self.output_at(&Source::new(proc_name), content)
+    }
+
+    // Blocks until every line is written. Lines on stdout are flushed by log::flush
+    fn flush(&self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+// Who a line is about and when it was captured
+pub struct Source<'a> {
+    // e.g.) web.1 or system
+    pub name: &'a str,
+    // e.g.) web, when `name` is an instance of it
+    pub process: Option<&'a str>,
+    pub pid: Option<u32>,
+    // Only lines a process wrote have a stream
+    pub stream: Option<Stream>,
+    pub at: DateTime<Local>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(name: &'a str) -> Self {
+        Source {
+            name,
+            process: None,
+            pid: None,
+            stream: None,
+            at: Local::now(),
+        }
+    }
+
+    // Goes between the name and the content in text, e.g.) web.1  ! warning
+    pub fn separator(&self) -> char {
+        match self.stream {
+            Some(Stream::Stderr) => '!',
+            Some(Stream::Stdout) | None => '|',
+        }
+    }
+}
+
+// How lines are written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // e.g.) 12:00:00 web.1  | listening
+    #[default]
+    Text,
+    // One JSON object per line
+    Json,
+    // key=value pairs, e.g.) ts=... process=web instance=web.1 msg=listening
+    Logfmt,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            _ => Err(format!(
+                "unknown log format `{}` (expected text, json or logfmt)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+            LogFormat::Logfmt => write!(f, "logfmt"),
+        }
+    }
 }
 
 pub struct Log;
 
 #[derive(Clone)]
 pub struct LogOpt {
+    pub format: LogFormat,
     pub is_color: bool,
     pub padding: usize,
     pub is_timestamp: bool,
+    pub timestamp_format: TimestampFormat,
+}
+
+impl Default for LogOpt {
+    fn default() -> Self {
+        LogOpt {
+            format: LogFormat::default(),
+            is_color: false,
+            padding: 0,
+            is_timestamp: true,
+            timestamp_format: TimestampFormat::default(),
+        }
+    }
+}
+
+// How the timestamp of a text line is written
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TimestampFormat {
+    // 12:00:00
+    #[default]
+    Time,
+    // 12:00:00.123
+    TimeMs,
+    // 2022-01-01T12:00:00.123+09:00
+    Iso8601,
+    // 2022-01-01T03:00:00.123Z
+    Utc,
+    // +12.345s since ultraman started
+    Relative,
+    // strftime(3), e.g.) %Y-%m-%d %H:%M:%S%.3f or utc:%H:%M:%S
+    Custom {
+        format: String,
+        is_utc: bool,
+    },
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "time" => return Ok(TimestampFormat::Time),
+            "time-ms" => return Ok(TimestampFormat::TimeMs),
+            "iso8601" => return Ok(TimestampFormat::Iso8601),
+            "utc" => return Ok(TimestampFormat::Utc),
+            "relative" => return Ok(TimestampFormat::Relative),
+            _ => (),
+        }
+
+        let (format, is_utc) = match s.strip_prefix("utc:") {
+            Some(format) => (format, true),
+            None => (s, false),
+        };
+        // A typo in a preset would otherwise be printed as it is
+        let is_strftime = format.contains('%')
+            && !StrftimeItems::new(format).any(|item| matches!(item, Item::Error));
+        if is_strftime {
+            Ok(TimestampFormat::Custom {
+                format: String::from(format),
+                is_utc,
+            })
+        } else {
+            Err(format!(
+                "unknown timestamp format `{}` (expected time, time-ms, iso8601, utc, relative or a strftime format)",
+                s
+            ))
+        }
+    }
+}
+
+impl TryFrom<String> for TimestampFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimestampFormat::Time => write!(f, "time"),
+            TimestampFormat::TimeMs => write!(f, "time-ms"),
+            TimestampFormat::Iso8601 => write!(f, "iso8601"),
+            TimestampFormat::Utc => write!(f, "utc"),
+            TimestampFormat::Relative => write!(f, "relative"),
+            TimestampFormat::Custom { format, is_utc } if *is_utc => write!(f, "utc:{}", format),
+            TimestampFormat::Custom { format, .. } => write!(f, "{}", format),
+        }
+    }
+}
+
+impl TimestampFormat {
+    pub fn format(&self, at: &DateTime<Local>) -> String {
+        match self {
+            TimestampFormat::Time => at.format("%H:%M:%S").to_string(),
+            TimestampFormat::TimeMs => at.format("%H:%M:%S%.3f").to_string(),
+            TimestampFormat::Iso8601 => at.to_rfc3339_opts(SecondsFormat::Millis, false),
+            TimestampFormat::Utc => at
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            TimestampFormat::Relative => relative(at, started_at()),
+            TimestampFormat::Custom { format, is_utc } if *is_utc => {
+                at.with_timezone(&Utc).format(format).to_string()
+            }
+            TimestampFormat::Custom { format, .. } => at.format(format).to_string(),
+        }
+    }
+}
+
+static STARTED_AT: OnceLock<DateTime<Local>> = OnceLock::new();
+
+// Relative timestamps count from the first call
+pub fn started_at() -> &'static DateTime<Local> {
+    STARTED_AT.get_or_init(Local::now)
+}
+
+// e.g.) when lines of an ultraman that started earlier are printed
+pub fn set_started_at(at: DateTime<Local>) {
+    let _ = STARTED_AT.set(at);
+}
+
+fn relative(at: &DateTime<Local>, started_at: &DateTime<Local>) -> String {
+    let elapsed = at
+        .signed_duration_since(*started_at)
+        .num_milliseconds()
+        .max(0);
+    format!("+{}.{:03}s", elapsed / 1000, elapsed % 1000)
+}
+
+// Whether lines are colored
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    // Colored when stdout is a terminal, unless NO_COLOR or CLICOLOR_FORCE say otherwise
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color `{}` (expected auto, always or never)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorChoice::Auto => write!(f, "auto"),
+            ColorChoice::Always => write!(f, "always"),
+            ColorChoice::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn is_color(&self) -> bool {
+        self.resolve(
+            env::var("NO_COLOR").ok(),
+            env::var("CLICOLOR_FORCE").ok(),
+            io::stdout().is_terminal(),
+        )
+    }
+
+    // https://no-color.org and https://bixense.com/clicolors
+    fn resolve(
+        &self,
+        no_color: Option<String>,
+        clicolor_force: Option<String>,
+        is_terminal: bool,
+    ) -> bool {
+        let is_set = |var: &Option<String>| var.as_deref().is_some_and(|v| !v.is_empty());
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto
+                if is_set(&clicolor_force) && clicolor_force.as_deref() != Some("0") =>
+            {
+                true
+            }
+            ColorChoice::Auto if is_set(&no_color) => false,
+            ColorChoice::Auto => is_terminal,
+        }
+    }
 }
 
 impl Log {
     pub fn new(index: usize, opt: &LogOpt) -> Box<dyn Printable + Sync + Send> {
+        Self::with_writer(index, opt, writer::stdout().clone())
+    }
+
+    pub fn with_writer(
+        index: usize,
+        opt: &LogOpt,
+        writer: Writer,
+    ) -> Box<dyn Printable + Sync + Send> {
+        match opt.format {
+            LogFormat::Json => {
+                let mut json = json::Log::boxed_new();
+                json.writer = writer;
+                return json;
+            }
+            LogFormat::Logfmt => {
+                let mut logfmt = logfmt::Log::boxed_new();
+                logfmt.writer = writer;
+                return logfmt;
+            }
+            LogFormat::Text => (),
+        }
+
         if opt.is_color {
             let mut color = color::Log::boxed_new();
             color.index = index;
-            color.opts = Self::display_opts(opt);
+            color.opts = opt.clone();
+            color.writer = writer;
             color
         } else {
             let mut plain = plain::Log::boxed_new();
             plain.index = index;
-            plain.opts = Self::display_opts(opt);
+            plain.opts = opt.clone();
+            plain.writer = writer;
             plain
         }
     }
-
-    fn display_opts(opt: &LogOpt) -> DisplayOpts {
-        DisplayOpts {
-            padding: opt.padding,
-            is_timestamp: opt.is_timestamp,
-        }
-    }
 }
 
 pub fn output(proc_name: &str, content: &str, index: Option<usize>, opt: &LogOpt) {
@@ -53,22 +380,23 @@ pub fn error(proc_name: &str, err: &dyn std::error::Error, is_padding: bool, opt
         output(proc_name, content, None, &opt);
     } else {
         let remake_opt = LogOpt {
-            is_color: opt.is_color,
             padding: proc_name.len() + 1,
-            is_timestamp: opt.is_timestamp,
+            ..opt.clone()
         };
         output(proc_name, content, None, &remake_opt);
     }
 }
 
-pub fn now() -> String {
-    Local::now().format("%H:%M:%S").to_string()
+// Waits until every line logged so far is on stdout
+pub fn flush() {
+    writer::stdout().flush();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use anyhow;
+    use chrono::TimeZone;
     use std::error::Error;
     use std::fmt;
 
@@ -77,9 +405,11 @@ mod tests {
         let log = Log::new(
             0,
             &LogOpt {
+                format: LogFormat::Text,
                 is_color: true,
                 padding: 10,
                 is_timestamp: true,
+                timestamp_format: TimestampFormat::Time,
             },
         );
         log.output("output 1", "coloring");
@@ -92,9 +422,11 @@ mod tests {
         let log = Log::new(
             0,
             &LogOpt {
+                format: LogFormat::Text,
                 is_color: false,
                 padding: 10,
                 is_timestamp: true,
+                timestamp_format: TimestampFormat::Time,
             },
         );
         log.output("output 1", "not coloring");
@@ -112,15 +444,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_color_choice_resolve() -> anyhow::Result<()> {
+        let set = |v: &str| Some(String::from(v));
+
+        assert!(ColorChoice::Auto.resolve(None, None, true));
+        assert!(!ColorChoice::Auto.resolve(None, None, false));
+        assert!(!ColorChoice::Auto.resolve(set("1"), None, true));
+        assert!(ColorChoice::Auto.resolve(set(""), None, true));
+        assert!(ColorChoice::Auto.resolve(None, set("1"), false));
+        assert!(ColorChoice::Auto.resolve(set("1"), set("1"), false));
+        assert!(!ColorChoice::Auto.resolve(None, set("0"), false));
+        assert!(ColorChoice::Always.resolve(set("1"), None, false));
+        assert!(!ColorChoice::Never.resolve(None, set("1"), true));
+        assert_eq!("never".parse::<ColorChoice>(), Ok(ColorChoice::Never));
+        assert!("yes".parse::<ColorChoice>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp_format() -> anyhow::Result<()> {
+        let at = Utc
+            .with_ymd_and_hms(2022, 1, 1, 3, 4, 5)
+            .unwrap()
+            .with_timezone(&Local)
+            + chrono::Duration::milliseconds(67);
+        let format = |s: &str| s.parse::<TimestampFormat>().map(|f| f.format(&at));
+
+        assert_eq!(format("utc"), Ok(String::from("2022-01-01T03:04:05.067Z")));
+        assert_eq!(format("utc:%H:%M:%S%.3f"), Ok(String::from("03:04:05.067")));
+        assert_eq!(format("time-ms"), Ok(at.format("%H:%M:%S%.3f").to_string()));
+        assert_eq!(
+            format("iso8601"),
+            Ok(at.to_rfc3339_opts(SecondsFormat::Millis, false))
+        );
+        assert_eq!(
+            relative(&(at + chrono::Duration::milliseconds(12345)), &at),
+            "+12.345s"
+        );
+        assert!("hh:mm".parse::<TimestampFormat>().is_err());
+        assert!("%Q".parse::<TimestampFormat>().is_err());
+        assert_eq!(
+            "utc:%s".parse::<TimestampFormat>().map(|f| f.to_string()),
+            Ok(String::from("utc:%s"))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_error() -> anyhow::Result<()> {
         let error = TestError("test error");
         let log = Log::new(
             0,
             &LogOpt {
+                format: LogFormat::Text,
                 is_color: true,
                 padding: 10,
                 is_timestamp: true,
+                timestamp_format: TimestampFormat::Time,
             },
         );
         log.error("test_app", &error);
@@ -1,10 +1,11 @@
-use crate::log::{now, Printable};
-use crate::opt::DisplayOpts;
+use crate::log::writer::Writer;
+use crate::log::{LogOpt, Printable, Source};
 
 #[derive(Default)]
 pub struct Log {
     pub index: usize,
-    pub opts: DisplayOpts,
+    pub opts: LogOpt,
+    pub writer: Writer,
 }
 
 unsafe impl Sync for Log {}
@@ -19,22 +20,39 @@ impl Log {
     pub fn boxed_new() -> Box<Self> {
         Self::default().boxed()
     }
-}
 
-impl Printable for Log {
-    fn output(&self, proc_name: &str, content: &str) {
+    fn prefix(&self, source: &Source) -> String {
         if self.opts.is_timestamp {
-            println!(
-                "{3} {0:1$} | {2}",
-                proc_name,
+            format!(
+                "{2} {0:1$} {3} ",
+                source.name,
                 self.opts.padding,
-                content,
-                now()
+                self.opts.timestamp_format.format(&source.at),
+                source.separator()
             )
         } else {
-            println!("{0:1$} | {2}", proc_name, self.opts.padding, content)
+            format!(
+                "{0:1$} {2} ",
+                source.name,
+                self.opts.padding,
+                source.separator()
+            )
         }
     }
+}
+
+impl Printable for Log {
+    fn output_at(&self, source: &Source, content: &str) {
+        let mut line = self.prefix(source);
+        line.push_str(content);
+        self.writer.write_line(line.into_bytes());
+    }
+
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        let mut line = self.prefix(source).into_bytes();
+        line.extend_from_slice(content);
+        self.writer.write_line(line);
+    }
 
     fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
         let content = &format!("error: {:?}", err);
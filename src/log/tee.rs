@@ -0,0 +1,38 @@
+use crate::log::{Printable, Source};
+
+// Hands every line to each of `sinks`, e.g.) the console and log files
+pub struct Log {
+    pub sinks: Vec<Box<dyn Printable + Sync + Send>>,
+}
+
+impl Log {
+    pub fn boxed_new(sinks: Vec<Box<dyn Printable + Sync + Send>>) -> Box<Self> {
+        Box::new(Log { sinks })
+    }
+}
+
+impl Printable for Log {
+    fn output_at(&self, source: &Source, content: &str) {
+        for sink in self.sinks.iter() {
+            sink.output_at(source, content);
+        }
+    }
+
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        for sink in self.sinks.iter() {
+            sink.output_raw(source, content);
+        }
+    }
+
+    fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
+        for sink in self.sinks.iter() {
+            sink.error(proc_name, err);
+        }
+    }
+
+    fn flush(&self) {
+        for sink in self.sinks.iter() {
+            sink.flush();
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use crossbeam::channel::{bounded, Receiver, Sender};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::thread;
+
+// Lines queued for the output before the processes printing them wait, e.g.) while stdout is
+// piped to a pager that is not reading
+const LINE_BUFFER: usize = 1024;
+
+enum Job {
+    Line(Vec<u8>),
+    Flush(Sender<()>),
+}
+
+// Owns an output and writes every line it is sent in one piece, in the order they were sent,
+// so lines from different processes and threads never tear or interleave
+#[derive(Clone)]
+pub struct Writer {
+    inner: Inner,
+}
+
+#[derive(Clone)]
+enum Inner {
+    // a thread of its own writes the lines
+    Thread(Sender<Job>),
+    // the thread that sends a line writes it, see stdout
+    Direct(Arc<Mutex<BufWriter<Box<dyn Write + Send>>>>),
+}
+
+impl Writer {
+    pub fn new<W>(out: W) -> io::Result<Self>
+    where
+        W: Write + Send + 'static,
+    {
+        let (tx, rx) = bounded(LINE_BUFFER);
+        thread::Builder::new()
+            .name(String::from("write output"))
+            .spawn(move || write_lines(out, rx))?;
+
+        Ok(Writer {
+            inner: Inner::Thread(tx),
+        })
+    }
+
+    fn direct<W>(out: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        let out: Box<dyn Write + Send> = Box::new(out);
+        Writer {
+            inner: Inner::Direct(Arc::new(Mutex::new(BufWriter::new(out)))),
+        }
+    }
+
+    // `line` is a complete line without the newline
+    pub fn write_line(&self, mut line: Vec<u8>) {
+        line.push(b'\n');
+        match &self.inner {
+            Inner::Thread(tx) => {
+                let _ = tx.send(Job::Line(line));
+            }
+            Inner::Direct(out) => {
+                let mut out = out.lock().unwrap_or_else(PoisonError::into_inner);
+                let _ = out.write_all(&line).and_then(|_| out.flush());
+            }
+        }
+    }
+
+    // Blocks until every line sent so far has been written
+    pub fn flush(&self) {
+        match &self.inner {
+            Inner::Thread(tx) => {
+                let (done, rx) = bounded(1);
+                if tx.send(Job::Flush(done)).is_ok() {
+                    let _ = rx.recv();
+                }
+            }
+            Inner::Direct(out) => {
+                let _ = out.lock().unwrap_or_else(PoisonError::into_inner).flush();
+            }
+        }
+    }
+}
+
+fn write_lines<W: Write>(out: W, rx: Receiver<Job>) {
+    let mut out = BufWriter::new(out);
+
+    for job in rx.iter() {
+        // A closed stdout (e.g. piped to head) must not take ultraman down, so errors are ignored
+        match job {
+            Job::Line(line) => {
+                let _ = out.write_all(&line);
+                // Lines that are queued already go out in the same write
+                if rx.is_empty() {
+                    let _ = out.flush();
+                }
+            }
+            Job::Flush(done) => {
+                let _ = out.flush();
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+// Writes to stdout
+impl Default for Writer {
+    fn default() -> Self {
+        stdout().clone()
+    }
+}
+
+static STDOUT: OnceLock<Writer> = OnceLock::new();
+
+// The writer every log line goes through
+pub fn stdout() -> &'static Writer {
+    STDOUT.get_or_init(|| {
+        // Unlike println!, the writer thread is not captured by the test harness
+        #[cfg(not(test))]
+        let out = io::stdout;
+        #[cfg(test)]
+        let out = io::sink;
+        // Lines are still printed without a thread to write them, just not in the background
+        Writer::new(out()).unwrap_or_else(|_| Writer::direct(out()))
+    })
+}
+
+// Lines go to `out` instead of stdout, e.g.) while the terminal is drawn on.
+// Returns false when something was written to stdout already
+pub fn redirect_stdout<W>(out: W) -> io::Result<bool>
+where
+    W: Write + Send + 'static,
+{
+    Ok(STDOUT.set(Writer::new(out)?).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        // Short writes, so that a line written in pieces would be torn
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(7);
+            self.0.lock().unwrap().extend_from_slice(&buf[..len]);
+            thread::yield_now();
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_line_under_load() -> anyhow::Result<()> {
+        let buffer = Buffer::default();
+        let writer = Writer::new(buffer.clone())?;
+        let threads = 8;
+        let lines = 2000;
+
+        let handles = (0..threads)
+            .map(|t| {
+                let writer = writer.clone();
+                thread::spawn(move || {
+                    for n in 0..lines {
+                        let line = format!("process-{} | line {} {}", t, n, "x".repeat(n % 300));
+                        writer.write_line(line.into_bytes());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().expect("failed join");
+        }
+        writer.flush();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        let expected = (0..threads)
+            .flat_map(|t| {
+                (0..lines)
+                    .map(move |n| format!("process-{} | line {} {}", t, n, "x".repeat(n % 300)))
+            })
+            .collect::<HashSet<_>>();
+        let written = output.lines().collect::<Vec<_>>();
+        assert_eq!(written.len(), threads * lines);
+        for line in written.iter() {
+            assert!(expected.contains(*line), "torn line: {}", line);
+        }
+
+        // lines of one thread keep their order
+        let process_0 = written
+            .iter()
+            .filter(|line| line.starts_with("process-0 |"))
+            .map(|line| line.split(' ').nth(3).unwrap().parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(process_0, (0..lines).collect::<Vec<_>>());
+
+        Ok(())
+    }
+}
@@ -1,29 +1,28 @@
 use opt::{Opt, Ultraman};
+use std::process::exit;
 use structopt::StructOpt;
 
 mod cmd;
-mod config;
-mod env;
-mod log;
 mod opt;
-mod output;
-mod process;
-mod procfile;
-mod signal;
-mod stream_read;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() {
     let opt = Opt::from_args();
 
     if let Some(subcommand) = opt.subcommands {
-        match subcommand {
+        let result = match subcommand {
             Ultraman::Check(opts) => cmd::check::run(opts),
-            Ultraman::Completion(opts) => cmd::completion::run(opts).expect("failed ultraman completion"),
-            Ultraman::Start(opts) => cmd::start::run(opts).expect("failed ultraman start"),
+            Ultraman::Completion(opts) => cmd::completion::run(opts),
+            Ultraman::Config(opts) => cmd::config::run(opts),
+            Ultraman::Env(opts) => cmd::env::run(opts),
+            Ultraman::Start(opts) => cmd::start::run(opts),
+            Ultraman::Logs(opts) => cmd::logs::run(opts),
             Ultraman::Run(opts) => cmd::run::run(opts),
-            Ultraman::Export(opts) => cmd::export::run(opts).expect("failed ultraman export"),
+            Ultraman::Export(opts) => cmd::export::run(opts),
+        };
+
+        if let Err(e) = result {
+            e.report();
+            exit(e.exit_code());
         }
     }
-
-    Ok(())
 }
@@ -1,9 +1,12 @@
 use crate::cmd::check::CheckOpts;
 use crate::cmd::completion::CompletionOpts;
-use crate::cmd::export::ExportOpts;
+use crate::cmd::config::ConfigOpts;
+use crate::cmd::env::EnvOpts;
+use crate::cmd::logs::LogsOpts;
 use crate::cmd::run::RunOpts;
 use crate::cmd::start::StartOpts;
 use structopt::{clap, StructOpt};
+use ultraman::export::ExportOpts;
 
 #[derive(StructOpt, Debug)]
 #[structopt(long_version(option_env!("LONG_VERSION").unwrap_or(env!("CARGO_PKG_VERSION"))))]
@@ -25,9 +28,21 @@ pub enum Ultraman {
     #[structopt(name = "completion", about = "Generate shell completion scripts")]
     Completion(CompletionOpts),
 
+    #[structopt(name = "config", about = "Show the effective configuration")]
+    Config(ConfigOpts),
+
+    #[structopt(name = "env", about = "Print the environment a process would receive")]
+    Env(EnvOpts),
+
     #[structopt(name = "start", about = "Start the application")]
     Start(StartOpts),
 
+    #[structopt(
+        name = "logs",
+        about = "Show recent and live output of a running `ultraman start`"
+    )]
+    Logs(LogsOpts),
+
     #[structopt(
         name = "run",
         about = "Run a command using your application's environment"
@@ -40,20 +55,3 @@ pub enum Ultraman {
     )]
     Export(ExportOpts),
 }
-
-///// Options not related to commands /////
-
-#[derive(Clone)]
-pub struct DisplayOpts {
-    pub padding: usize,
-    pub is_timestamp: bool,
-}
-
-impl Default for DisplayOpts {
-    fn default() -> Self {
-        DisplayOpts {
-            padding: 0,
-            is_timestamp: true,
-        }
-    }
-}
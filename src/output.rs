@@ -1,134 +1,348 @@
-use crate::log::{Log, LogOpt, Printable};
-use crate::opt::DisplayOpts;
-use crate::process::Process;
-use crate::stream_read::{PipeError, PipeStreamReader, PipedLine};
+use crate::error;
+use crate::log::file::{self, LogFiles};
+use crate::log::{hide, tee};
+use crate::log::{Log, LogOpt, Printable, Source, Stream};
+use crate::stream_read::{self, PipeError, PipedLine};
 
-use crossbeam::channel::Select;
-use std::sync::{Arc, Mutex};
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::str::{self, FromStr};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread::JoinHandle;
+
+// How output that is not valid UTF-8 is shown
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decoding {
+    // Invalid bytes become �
+    #[default]
+    Lossy,
+    // Invalid bytes become \xNN
+    Hex,
+    // Bytes are written as they are (e.g. Latin-1 for a Latin-1 terminal)
+    Raw,
+}
+
+impl FromStr for Decoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(Decoding::Lossy),
+            "hex" => Ok(Decoding::Hex),
+            "raw" => Ok(Decoding::Raw),
+            _ => Err(format!(
+                "unknown decoding `{}` (expected lossy, hex or raw)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Decoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoding::Lossy => write!(f, "lossy"),
+            Decoding::Hex => write!(f, "hex"),
+            Decoding::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+impl Decoding {
+    // Text for a line of output. Raw output can't be text, so it is decoded lossily
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        match self {
+            Decoding::Lossy | Decoding::Raw => String::from_utf8_lossy(bytes),
+            Decoding::Hex => hex_escape(bytes),
+        }
+    }
+}
+
+fn hex_escape(bytes: &[u8]) -> Cow<'_, str> {
+    let mut rest = match str::from_utf8(bytes) {
+        Ok(text) => return Cow::Borrowed(text),
+        Err(_) => bytes,
+    };
+
+    let mut text = String::with_capacity(bytes.len());
+    while !rest.is_empty() {
+        match str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                break;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                // the bytes were just checked
+                text.push_str(str::from_utf8(valid).unwrap_or_default());
+                // a truncated character at the end has no error_len
+                let invalid_len = e.error_len().unwrap_or(invalid.len());
+                for byte in &invalid[..invalid_len] {
+                    text.push_str(&format!("\\x{:02x}", byte));
+                }
+                rest = &invalid[invalid_len..];
+            }
+        }
+    }
+    Cow::Owned(text)
+}
+
+// Which lines of a process are printed. Log files get every line either way
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub is_quiet: bool,
+    // A line is printed if it matches any of them (or there are none)
+    pub include: Vec<Regex>,
+    // and none of these
+    pub exclude: Vec<Regex>,
+}
+
+impl Filter {
+    pub fn is_match(&self, line: &str) -> bool {
+        !self.is_quiet
+            && (self.include.is_empty() || self.include.iter().any(|re| re.is_match(line)))
+            && !self.exclude.iter().any(|re| re.is_match(line))
+    }
+}
+
+// A regex for the lines of a process, e.g.) web=ERROR|WARN
+#[derive(Debug, Clone)]
+pub struct LinePattern {
+    pub process: String,
+    pub regex: Regex,
+}
+
+impl FromStr for LinePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((process, pattern)) if !process.trim().is_empty() => Ok(LinePattern {
+                process: process.trim().to_string(),
+                regex: Regex::new(pattern).map_err(|e| e.to_string())?,
+            }),
+            _ => Err(format!("invalid pattern `{}` (expected process=regex)", s)),
+        }
+    }
+}
 
 pub struct Output {
     pub log: Box<dyn Printable + Sync + Send>,
+    filter: Arc<RwLock<Filter>>,
 }
 
 impl Output {
-    pub fn new(index: usize, opts: DisplayOpts) -> Self {
+    pub fn new(index: usize, opts: LogOpt) -> Self {
+        let filter = Arc::new(RwLock::new(Filter::default()));
         Output {
-            log: Log::new(
-                index,
-                &LogOpt {
-                    is_color: true,
-                    padding: opts.padding,
-                    is_timestamp: opts.is_timestamp,
-                },
-            ),
+            log: Box::new(Filtered {
+                inner: Log::new(index, &opts),
+                filter: Arc::clone(&filter),
+            }),
+            filter,
         }
     }
 
-    pub fn handle_output(&self, proc: &Arc<Mutex<Process>>) {
-        let mut channels: Vec<PipeStreamReader> = Vec::new();
-        channels.push(PipeStreamReader::new(Box::new(
-            proc.lock()
-                .unwrap()
-                .child
-                .stdout
-                .take()
-                .expect("failed take stdout"),
-        )));
-        channels.push(PipeStreamReader::new(Box::new(
-            proc.lock()
-                .unwrap()
-                .child
-                .stderr
-                .take()
-                .expect("failed take stderr"),
-        )));
-
-        let mut select = Select::new();
-        for channel in channels.iter() {
-            select.recv(&channel.lines);
+    // Lines written to `streams` are not printed
+    pub fn hide(self, streams: Vec<Stream>) -> Self {
+        if streams.is_empty() {
+            return self;
         }
+        Output {
+            log: hide::Log::boxed_new(self.log, streams),
+            filter: self.filter,
+        }
+    }
 
-        let mut stream_eof = false;
-
-        while !stream_eof {
-            let operation = select.select();
-            let index = operation.index();
-            let received = operation.recv(
-                &channels
-                    .get(index)
-                    .expect("failed get channel at index")
-                    .lines,
-            );
-            let log = &self.log;
-
-            match received {
-                Ok(remote_result) => match remote_result {
-                    Ok(piped_line) => match piped_line {
-                        PipedLine::Line(line) => {
-                            log.output(&proc.lock().unwrap().name, &line);
-                        }
-                        PipedLine::EOF => {
-                            stream_eof = true;
-                            select.remove(index);
-                        }
-                    },
-                    Err(error) => match error {
-                        PipeError::IO(err) => log.error(&proc.lock().unwrap().name, &err),
-                        PipeError::NotUtf8(err) => log.error(&proc.lock().unwrap().name, &err),
-                    },
-                },
-                Err(_) => {
-                    stream_eof = true;
-                    select.remove(index);
-                }
-            }
+    // Takes effect from the next line
+    pub fn set_filter(&self, filter: Filter) {
+        *self.filter.write().unwrap_or_else(PoisonError::into_inner) = filter;
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // Also writes each of `names` (instances of the process) to its own file, hidden lines included
+    pub fn with_files(
+        self,
+        index: usize,
+        opts: &LogOpt,
+        names: &[String],
+        files: &LogFiles,
+    ) -> error::Result<Self> {
+        let file = file::Log::open(index, names, opts, files)?;
+        Ok(Output {
+            log: tee::Log::boxed_new(vec![self.log, Box::new(file)]),
+            filter: self.filter,
+        })
+    }
+}
+
+// Passes on the lines of a process that the filter matches. Messages of ultraman itself
+// (e.g. exited with code 0) are always passed on
+struct Filtered {
+    inner: Box<dyn Printable + Sync + Send>,
+    filter: Arc<RwLock<Filter>>,
+}
+
+impl Filtered {
+    fn is_match(&self, source: &Source, content: &str) -> bool {
+        source.stream.is_none()
+            || self
+                .filter
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_match(content)
+    }
+}
+
+impl Printable for Filtered {
+    fn output_at(&self, source: &Source, content: &str) {
+        if self.is_match(source, content) {
+            self.inner.output_at(source, content);
+        }
+    }
+
+    fn output_raw(&self, source: &Source, content: &[u8]) {
+        if self.is_match(source, &String::from_utf8_lossy(content)) {
+            self.inner.output_raw(source, content);
         }
+    }
 
-        // MEMO
-        //
-        // An error occurs in a child process that was terminated by sending a SIGTERM
-        // It is necessary to be able to send a signal after successfully executing the termination process.
-        //
+    fn error(&self, proc_name: &str, err: &dyn std::error::Error) {
+        self.inner.error(proc_name, err);
+    }
 
-        // blocking
-        // let status = proc.lock().unwrap().child.wait().expect("!wait");
-        // let proc_name = &proc.lock().unwrap().name;
-        // if status.success() {
-        //     log::output(proc_name, "onsucceed handler");
-        // } else {
-        //     log::output(proc_name, "onfailed handler");
-        // }
+    fn flush(&self) {
+        self.inner.flush();
     }
 }
 
+// Reads stdout and stderr of a process line by line and hands each line to `yielder`
+// in the order it was read, then one EOF per stream
+pub fn build_output_threads<F>(
+    stdout: Box<dyn io::Read + Send>,
+    stderr: Box<dyn io::Read + Send>,
+    max_line_length: usize,
+    yielder: F,
+) -> io::Result<[JoinHandle<()>; 2]>
+where
+    F: Fn(Stream, Result<PipedLine, PipeError>) + Clone + Send + 'static,
+{
+    let yielder2 = yielder.clone();
+    Ok([
+        stream_read::build_read_thread(stdout, max_line_length, move |line| {
+            yielder(Stream::Stdout, line);
+            true
+        })?,
+        stream_read::build_read_thread(stderr, max_line_length, move |line| {
+            yielder2(Stream::Stderr, line);
+            true
+        })?,
+    ])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use anyhow;
+    use crate::stream_read::DEFAULT_MAX_LINE_LENGTH;
     use std::process::{Command, Stdio};
+    use std::sync::{Arc, Mutex};
 
     #[test]
-    fn test_handle_output() -> anyhow::Result<()> {
-        let proc = Arc::new(Mutex::new(Process {
-            index: 0,
-            name: String::from("handle_output"),
-            child: Command::new("./test/fixtures/for.sh")
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .expect("failed execute handle_output command"),
-            opts: None,
-        }));
-
-        let proc2 = Arc::clone(&proc);
-        let output = Output::new(
-            0,
-            DisplayOpts {
-                padding: 10,
-                is_timestamp: true,
+    fn test_build_output_threads() -> anyhow::Result<()> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("for i in 1 2; do echo out $i; sleep 0.1; echo err $i >&2; sleep 0.1; done")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed execute handle_output command");
+
+        let lines = Arc::new(Mutex::new(vec![]));
+        let lines2 = Arc::clone(&lines);
+        let threads = build_output_threads(
+            Box::new(child.stdout.take().unwrap()),
+            Box::new(child.stderr.take().unwrap()),
+            DEFAULT_MAX_LINE_LENGTH,
+            move |stream, line| {
+                let line = match line.expect("failed read line") {
+                    PipedLine::Line(line, _) => {
+                        format!("{}: {}", stream.as_str(), String::from_utf8(line).unwrap())
+                    }
+                    PipedLine::EOF => String::from("<EOF>"),
+                };
+                lines2.lock().unwrap().push(line);
             },
+        )
+        .expect("failed build output threads");
+        for thread in threads {
+            thread.join().expect("failed join");
+        }
+        child.wait()?;
+
+        // stdout and stderr stay in the order they were written
+        assert_eq!(
+            *lines.lock().unwrap(),
+            vec![
+                "stdout: out 1",
+                "stderr: err 1",
+                "stdout: out 2",
+                "stderr: err 2",
+                "<EOF>",
+                "<EOF>"
+            ]
         );
-        output.handle_output(&proc2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode() -> anyhow::Result<()> {
+        let bytes = b"caf\xe9 \xe3\x81\x82 ok\xe3\x81";
+
+        assert_eq!(Decoding::Lossy.decode(bytes), "caf\u{fffd} あ ok\u{fffd}");
+        assert_eq!(Decoding::Hex.decode(bytes), "caf\\xe9 あ ok\\xe3\\x81");
+        assert_eq!(Decoding::Raw.decode(b"plain"), "plain");
+        assert_eq!("hex".parse::<Decoding>(), Ok(Decoding::Hex));
+        assert!("latin1".parse::<Decoding>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() -> anyhow::Result<()> {
+        let mut filter = Filter::default();
+        assert!(filter.is_match("GET /"));
+
+        filter.include = vec![Regex::new("ERROR|WARN")?];
+        filter.exclude = vec![Regex::new("retrying")?];
+        assert!(filter.is_match("ERROR boom"));
+        assert!(!filter.is_match("INFO ok"));
+        assert!(!filter.is_match("WARN retrying"));
+
+        filter.is_quiet = true;
+        assert!(!filter.is_match("ERROR boom"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_pattern() -> anyhow::Result<()> {
+        let pattern = "web=^GET (/|/health)$".parse::<LinePattern>();
+        assert!(pattern.as_ref().is_ok_and(|p| p.process == "web"));
+        assert!(pattern.is_ok_and(|p| p.regex.is_match("GET /health")));
+        assert!("web".parse::<LinePattern>().is_err());
+        assert!("=ERROR".parse::<LinePattern>().is_err());
+        assert!("web=(".parse::<LinePattern>().is_err());
 
         Ok(())
     }
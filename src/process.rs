@@ -1,174 +1,253 @@
-use crate::env::read_env;
-use crate::log::{self, LogOpt};
-use crate::opt::DisplayOpts;
-use crate::signal;
-use nix::sys::signal::Signal;
-use nix::sys::wait::WaitStatus;
-use nix::{self, unistd::Pid};
+use crate::env::{read_env, Env};
+use crate::error::{Result, UltramanError};
+use crate::log::LogOpt;
+use crate::pty::Pty;
 use std::env::{self as os_env};
-use std::path::PathBuf;
+use std::fs::File;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::thread::{self, JoinHandle};
 
-#[cfg(not(test))]
-use std::process::exit;
+// How a Procfile command is run
+#[derive(Debug, Clone, PartialEq)]
+pub enum Runner {
+    // `shell -c command`, so the command may use pipes, && and $VARIABLES
+    Shell(PathBuf),
+    // The command is split into words as a shell would and run directly, without a shell.
+    // $VARIABLES and ${VARIABLES} are expanded from the environment of the process first
+    Exec,
+}
+
+impl Runner {
+    // `env` is the one the process gets on top of that of ultraman, see env_for
+    pub fn command(&self, cmd: &str, env: &Env) -> Result<Command> {
+        match self {
+            Runner::Shell(shell) => {
+                let mut command = Command::new(shell);
+                command.arg("-c").arg(cmd);
+                Ok(command)
+            }
+            Runner::Exec => {
+                let invalid = |message: &str| UltramanError::InvalidCommand {
+                    command: cmd.to_string(),
+                    message: message.to_string(),
+                };
+                let cmd = expand(cmd, env).map_err(invalid)?;
+                let words = shellwords::split(&cmd).map_err(|e| invalid(&e.to_string()))?;
+                let (program, args) = words.split_first().ok_or_else(|| invalid("it is empty"))?;
+                let mut command = Command::new(program);
+                command.args(args);
+                Ok(command)
+            }
+        }
+    }
+
+    // A shell that can't be started is not the fault of the command
+    pub fn spawn_error(&self, cmd: &str, source: io::Error) -> UltramanError {
+        match self {
+            Runner::Shell(shell) => UltramanError::Shell {
+                path: shell.clone(),
+                source,
+            },
+            Runner::Exec => UltramanError::Spawn {
+                command: cmd.to_string(),
+                source,
+            },
+        }
+    }
+}
+
+// Substitutes variables as a shell would before the command is split into words: not in
+// single quotes, as one word in double quotes, and split on whitespace otherwise.
+// Unset variables are empty
+fn expand(cmd: &str, env: &Env) -> std::result::Result<String, &'static str> {
+    let lookup = |name: &str| {
+        env.get(name)
+            .cloned()
+            .or_else(|| os_env::var(name).ok())
+            .unwrap_or_default()
+    };
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut expanded = String::new();
+    let mut is_double_quoted = false;
+    let mut chars = cmd.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                expanded.push(c);
+                expanded.extend(chars.next());
+            }
+            '\'' if !is_double_quoted => {
+                expanded.push(c);
+                for c in chars.by_ref() {
+                    expanded.push(c);
+                    if c == '\'' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                is_double_quoted = !is_double_quoted;
+                expanded.push(c);
+            }
+            '$' => {
+                let mut name = String::new();
+                if chars.next_if_eq(&'{').is_some() {
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err("${ is not closed"),
+                        }
+                    }
+                    if name.is_empty() || !name.chars().all(is_name) {
+                        return Err("${} does not hold a variable name");
+                    }
+                } else {
+                    while let Some(c) = chars.next_if(|c| is_name(*c)) {
+                        name.push(c);
+                    }
+                    if name.is_empty() {
+                        expanded.push(c);
+                        continue;
+                    }
+                }
+
+                let value = lookup(&name);
+                if is_double_quoted {
+                    for c in value.chars() {
+                        if matches!(c, '\\' | '"' | '$' | '`') {
+                            expanded.push('\\');
+                        }
+                        expanded.push(c);
+                    }
+                } else {
+                    let words = value.split_whitespace().collect::<Vec<_>>();
+                    expanded.push_str(&shellwords::join(&words));
+                }
+            }
+            _ => expanded.push(c),
+        }
+    }
+    Ok(expanded)
+}
+
+// What a process reads from and writes to. Either way it leads a process group of its own,
+// so a signal reaches what it started too (e.g. the command a `sh -c` didn't exec)
+pub enum Io {
+    // stdout and stderr are piped apart. stdin must not be the terminal, which would stop
+    // a process reading it from outside the foreground process group
+    Pipes { stdin: Stdio },
+    // a terminal of its own, see Pty. It doesn't echo input when `is_echo` is false
+    Pty { is_echo: bool },
+}
 
 pub struct Process {
-    pub index: usize,
     pub name: String,
     pub child: Child,
+    // the master side of its terminal, if it was spawned on one
+    pub pty: Option<File>,
     #[allow(dead_code)]
-    pub opts: Option<DisplayOpts>,
+    pub opts: Option<LogOpt>,
 }
 
 impl Process {
+    // `env` is the one of the instance, see env_for
     pub fn new(
         process_name: &str,
-        cmd: &str,
-        env_path: PathBuf,
-        port: Option<u32>,
         concurrency_index: usize,
-        app_index: usize,
-        opts: Option<DisplayOpts>,
-    ) -> Self {
-        let mut read_env = read_env(env_path.clone()).expect("failed read .env");
-        read_env.insert(
-            String::from("PORT"),
-            port_for(&env_path, port, concurrency_index).to_string(),
-        );
-        read_env.insert(
-            String::from("PS"),
-            ps_for(process_name, concurrency_index + 1),
-        );
-        let shell = os_env::var("SHELL").expect("$SHELL is not set");
-
-        Process {
-            index: app_index,
-            name: ps_for(process_name, concurrency_index + 1),
-            child: Command::new(shell)
-                .arg("-c")
-                .arg(cmd)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .envs(read_env)
-                .spawn()
-                .expect("failed execute command"),
-            opts,
-        }
-    }
-}
-
-// https://stackoverflow.com/questions/34439977/lifetime-of-variables-passed-to-a-new-thread
-pub fn build_exec_and_output_thread<F>(yielder: F) -> JoinHandle<()>
-where
-    F: FnOnce() + Sync + Send + 'static,
-{
-    thread::Builder::new()
-        .name(String::from("handle exec and output"))
-        .spawn(move || {
-            yielder();
-        })
-        .expect("failed exec and output")
-}
+        cmd: &str,
+        runner: &Runner,
+        env: Env,
+        io: Io,
+        opts: Option<LogOpt>,
+    ) -> Result<Self> {
+        let spawn_error = |source| UltramanError::Spawn {
+            command: cmd.to_string(),
+            source,
+        };
 
-pub fn build_check_for_child_termination_thread(
-    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
-    opts: DisplayOpts,
-) -> JoinHandle<()> {
-    thread::Builder::new()
-        .name(String::from(format!("check child terminated")))
-        .spawn(move || {
-            loop {
-                // Waiting for the end of any one child process
-                let procs2 = Arc::clone(&procs);
-                let procs3 = Arc::clone(&procs);
-                if let Some((_, code)) = check_for_child_termination(procs2, opts.clone()) {
-                    signal::kill_children(procs3, Signal::SIGTERM, code, opts.clone())
+        let mut command = runner.command(cmd, &env)?;
+        command.envs(env);
+        let pty = match io {
+            Io::Pipes { stdin } => {
+                command
+                    .stdin(stdin)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .process_group(0);
+                None
+            }
+            Io::Pty { is_echo } => {
+                let pty = Pty::open().map_err(spawn_error)?;
+                if !is_echo {
+                    pty.disable_echo().map_err(spawn_error)?;
                 }
-                // check_for_child_termination returns immediately, so let's sleep
-                // a little to avoid pegging CPU.
-                std::thread::sleep(std::time::Duration::from_millis(10));
+                pty.attach(&mut command).map_err(spawn_error)?;
+                Some(pty)
             }
+        };
+        let child = command
+            .spawn()
+            .map_err(|source| runner.spawn_error(cmd, source))?;
+
+        Ok(Process {
+            name: ps_for(process_name, concurrency_index + 1),
+            child,
+            // The process has the only other end of the terminal
+            pty: pty.map(|pty| pty.master),
+            opts,
         })
-        .expect("failed check child terminated")
+    }
 }
 
-pub fn check_for_child_termination(
-    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
-    opts: DisplayOpts,
-) -> Option<(Pid, i32)> {
-    let child_termination_fn = Box::new(move |pid: Pid, message: &str| {
-        procs.lock().unwrap().retain(|p| {
-            let child_id = p.lock().unwrap().child.id() as i32;
-            if Pid::from_raw(child_id) == pid {
-                let proc = p.lock().unwrap();
-                let proc_name = &proc.name;
-                let proc_index = proc.index;
-                log::output(
-                    &proc_name,
-                    &message,
-                    Some(proc_index),
-                    &LogOpt {
-                        is_color: true,
-                        padding: opts.padding,
-                        is_timestamp: opts.is_timestamp,
-                    },
-                );
-            }
-            Pid::from_raw(child_id) != pid
-        });
-    });
-
-    // Waiting for the end of any one child process
-    match nix::sys::wait::waitpid(
-        Pid::from_raw(-1),
-        Some(nix::sys::wait::WaitPidFlag::WNOHANG),
-    ) {
-        Ok(exit_status) => match exit_status {
-            WaitStatus::Exited(pid, code) => {
-                child_termination_fn(pid, &format!("exited with code {}", code));
-                return Some((pid, code));
-            }
-            WaitStatus::Signaled(pid, signal, _) => {
-                child_termination_fn(pid, &format!("terminated by {}", signal.as_str()));
-                return None;
-            }
-            _ => return None,
-        },
-        Err(e) => {
-            if let nix::errno::Errno::ECHILD = e {
-                // close loop (thread finished)
-                #[cfg(not(test))]
-                exit(0);
-                #[cfg(test)]
-                panic!("exit 0");
-            }
-            return None;
-        }
-    };
+// The environment a process instance receives: the env file plus PORT and PS
+pub fn env_for(
+    process_name: &str,
+    env_path: PathBuf,
+    port: Option<u32>,
+    concurrency_index: usize,
+) -> Result<Env> {
+    let mut env = read_env(env_path.clone())?;
+    env.insert(
+        String::from("PORT"),
+        port_for(&env_path, port, concurrency_index)?.to_string(),
+    );
+    env.insert(
+        String::from("PS"),
+        ps_for(process_name, concurrency_index + 1),
+    );
+    Ok(env)
 }
 
-fn ps_for(process_name: &str, concurrency_index: usize) -> String {
+pub fn ps_for(process_name: &str, concurrency_index: usize) -> String {
     format!("{}.{}", process_name, concurrency_index)
 }
 
-pub fn port_for(env_path: &PathBuf, port: Option<u32>, concurrency_index: usize) -> u32 {
-    base_port(env_path, port) + concurrency_index as u32
+pub fn port_for(env_path: &Path, port: Option<u32>, concurrency_index: usize) -> Result<u32> {
+    Ok(base_port(env_path, port)? + concurrency_index as u32)
 }
 
-fn base_port(env_path: &PathBuf, port: Option<u32>) -> u32 {
-    let env = read_env(env_path.clone()).unwrap();
+fn base_port(env_path: &Path, port: Option<u32>) -> Result<u32> {
+    let env = read_env(env_path.to_path_buf())?;
     let default_port = 5000;
+    let parse_port = |p: &str| {
+        p.parse::<u32>().map_err(|_| UltramanError::Env {
+            path: env_path.to_path_buf(),
+            message: format!("PORT must be a number, got `{}`", p),
+        })
+    };
 
     if let Some(p) = port {
-        p
+        Ok(p)
     } else if let Some(p) = env.get("PORT") {
-        p.parse::<u32>().unwrap()
+        parse_port(p)
     } else if let Ok(p) = os_env::var("PORT") {
-        p.parse::<u32>().unwrap()
+        parse_port(&p)
     } else {
-        default_port
+        Ok(default_port)
     }
 }
 
@@ -177,47 +256,67 @@ mod tests {
     use super::*;
 
     #[test]
-    #[should_panic(expected = "exit 0: Any")]
-    fn test_build_check_for_child_termination_thread() {
-        let procs = Arc::new(Mutex::new(vec![
-            Arc::new(Mutex::new(Process {
-                index: 0,
-                name: String::from("check_for_child_termination_thread-1"),
-                child: Command::new("./test/fixtures/exit_0.sh")
-                    .spawn()
-                    .expect("failed execute check_for_child_termination_thread-1"),
-                opts: None,
-            })),
-            Arc::new(Mutex::new(Process {
-                index: 1,
-                name: String::from("check_for_child_termination_thread-2"),
-                child: Command::new("./test/fixtures/exit_1.sh")
-                    .spawn()
-                    .expect("failed execute check_for_child_termination_thread-2"),
-                opts: None,
-            })),
-        ]));
-        let procs2 = Arc::clone(&procs);
-        let padding = 10;
-
-        build_check_for_child_termination_thread(
-            procs2,
-            DisplayOpts {
-                padding,
-                is_timestamp: true,
-            },
-        )
-        .join()
-        .expect("exit 0");
+    fn test_port_for() -> anyhow::Result<()> {
+        let env_path = PathBuf::from("./test/fixtures/.env");
+        let port = Some(6000);
+
+        assert_eq!(port_for(&env_path, port, 0 /* concurrency_index */)?, 6000);
+        assert_eq!(port_for(&env_path, port, 1 /* concurrency_index */)?, 6001);
+        assert_eq!(port_for(&env_path, port, 2 /* concurrency_index */)?, 6002);
+
+        Ok(())
     }
 
     #[test]
-    fn test_port_for() {
-        let env_path = PathBuf::from("./test/fixtures/.env");
-        let port = Some(6000);
+    fn test_runner_command() -> anyhow::Result<()> {
+        let env = Env::from([
+            (String::from("PORT"), String::from("5000")),
+            (String::from("OPTS"), String::from("-a  \"b c\"")),
+        ]);
+
+        // The shell expands the variables itself
+        let command = Runner::Shell(PathBuf::from("/bin/sh")).command("echo $PORT", &env)?;
+        assert_eq!(command.get_program(), "/bin/sh");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-c", "echo $PORT"]);
+
+        let command = Runner::Exec.command(r#"ruby -e "puts 'hi'" --port=$PORT"#, &env)?;
+        assert_eq!(command.get_program(), "ruby");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-e", "puts 'hi'", "--port=5000"]
+        );
+
+        let command = Runner::Exec.command(
+            r#"echo ${PORT}0 $OPTS "$OPTS" '$PORT' \$PORT $ULTRAMAN_UNSET_VARIABLE $"#,
+            &env,
+        )?;
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "50000",
+                "-a",
+                "\"b",
+                "c\"",
+                "-a  \"b c\"",
+                "$PORT",
+                "$PORT",
+                "$"
+            ]
+        );
+
+        assert!(matches!(
+            Runner::Exec.command("echo 'unclosed", &env),
+            Err(UltramanError::InvalidCommand { .. })
+        ));
+        assert!(matches!(
+            Runner::Exec.command("  ", &env),
+            Err(UltramanError::InvalidCommand { .. })
+        ));
+        assert!(matches!(
+            Runner::Exec.command("echo ${PORT", &env),
+            Err(UltramanError::InvalidCommand { .. })
+        ));
 
-        assert_eq!(port_for(&env_path, port, 0 /* concurrency_index */), 6000);
-        assert_eq!(port_for(&env_path, port, 1 /* concurrency_index */), 6001);
-        assert_eq!(port_for(&env_path, port, 2 /* concurrency_index */), 6002);
+        Ok(())
     }
 }
@@ -1,13 +1,16 @@
 use regex::Regex;
 use std::cell::Cell;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fmt;
+use std::fs;
+use std::io;
 use std::path::PathBuf;
 
-use crate::config::DEFAULT_FORMATION;
+use crate::error::{Result, UltramanError};
+use crate::formation::Formation;
 
-const PROCFILE_REGEXP: &'static str = r"\A([A-Za-z0-9_-]+):\s*(.+)$";
+const PROCFILE_REGEXP: &str = r"\A([A-Za-z0-9_-]+):\s*(.+)$";
+const NAME_REGEXP: &str = r"\A[A-Za-z0-9_-]+\z";
 
 pub struct ProcfileEntry {
     pub command: String,
@@ -19,17 +22,93 @@ type ProcfileData = HashMap<String, ProcfileEntry>;
 #[derive(Default)]
 pub struct Procfile {
     pub data: ProcfileData,
+    // Lines that were skipped or overridden while parsing
+    pub issues: Vec<ProcfileIssue>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IssueKind {
+    Unparseable { hint: &'static str },
+    Duplicate { name: String, first_line: usize },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ProcfileIssue {
+    // 1-indexed
+    pub line: usize,
+    pub content: String,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for ProcfileIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            IssueKind::Unparseable { hint } => write!(
+                f,
+                "line {}: cannot parse `{}` ({})",
+                self.line, self.content, hint
+            ),
+            IssueKind::Duplicate { name, first_line } => write!(
+                f,
+                "line {}: process `{}` is already defined on line {}, this definition wins",
+                self.line, name, first_line
+            ),
+        }
+    }
 }
 
 impl Procfile {
+    // Lines that can't be used are recorded in `issues` instead of failing
+    pub fn parse(content: &str) -> Self {
+        let procfile_re = Regex::new(PROCFILE_REGEXP).unwrap();
+        let mut pf = Procfile::default();
+        let mut defined_at = HashMap::<String, usize>::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            match procfile_re.captures(trimmed) {
+                Some(cap) => {
+                    let name = cap[1].to_string();
+                    let first_line = *defined_at.entry(name.clone()).or_insert(line_number);
+                    if first_line != line_number {
+                        pf.issues.push(ProcfileIssue {
+                            line: line_number,
+                            content: trimmed.to_string(),
+                            kind: IssueKind::Duplicate {
+                                name: name.clone(),
+                                first_line,
+                            },
+                        });
+                    }
+                    pf.data.insert(
+                        name,
+                        ProcfileEntry {
+                            command: cap[2].to_string(),
+                            concurrency: Cell::new(1),
+                        },
+                    );
+                }
+                None => pf.issues.push(ProcfileIssue {
+                    line: line_number,
+                    content: trimmed.to_string(),
+                    kind: IssueKind::Unparseable {
+                        hint: unparseable_hint(trimmed),
+                    },
+                }),
+            }
+        }
+
+        pf
+    }
+
     pub fn padding(&self) -> usize {
         // e.g) <name>.<concurrency> |
-        self.data
-            .keys()
-            .map(|name| name.len())
-            .max()
-            .expect("failed calculate padding")
-            + 3
+        self.data.keys().map(|name| name.len()).max().unwrap_or(0) + 3
     }
 
     pub fn process_len(&self) -> usize {
@@ -39,51 +118,23 @@ impl Procfile {
             .fold(0, |sum, a| sum + a)
     }
 
-    pub fn find_by(&self, name: &str) -> &ProcfileEntry {
-        let pe = self
-            .data
+    pub fn find_by(&self, name: &str) -> Result<&ProcfileEntry> {
+        self.data
             .get(name)
-            .expect(&format!("Can't find process called: {}", name));
-        pe
+            .ok_or_else(|| UltramanError::UnknownProcess {
+                name: name.to_string(),
+                defined: self.process_names(),
+            })
     }
 
-    pub fn set_concurrency(&self, formation: &str) {
-        // e.g.) all=1
-        if formation == DEFAULT_FORMATION {
-            return ();
-        }
-
-        // e.g.) all=2
-        let data: Vec<&str> = formation.split("=").collect();
-        let name = data[0];
-        if name == "all" {
-            let concurrency = data[1].parse::<usize>().unwrap();
-            for (_, pe) in self.data.iter() {
-                pe.concurrency.set(concurrency);
-            }
-            return ();
-        }
-
-        let formation_data = self.parse_formation(formation);
-
-        // https://doc.rust-lang.org/std/collections/hash_map/struct.HashMap.html#examples-14
-        let formation_apps = formation_data.keys().clone().collect::<Vec<_>>();
-        let valid_formation = formation_apps
-            .iter()
-            .all(|key| self.data.contains_key(*key));
-
-        if valid_formation == false {
-            panic!("Do not support formation: {}", formation);
-        }
+    pub fn set_concurrency(&self, formation: &Formation) -> Result<()> {
+        formation.validate(self.data.keys().map(|name| name.as_str()))?;
 
         for (name, pe) in self.data.iter() {
-            let pe_name = name;
-            let concurrency = formation_data
-                .get(&pe_name.to_string())
-                .unwrap_or_else(|| &0)
-                .clone();
-            pe.concurrency.set(concurrency);
+            pe.concurrency.set(formation.concurrency_for(name));
         }
+
+        Ok(())
     }
 
     pub fn check(&self) -> bool {
@@ -95,66 +146,42 @@ impl Procfile {
         names.sort();
         names.join(", ")
     }
+}
 
-    fn parse_formation(&self, formation: &str) -> HashMap<String, usize> {
-        let mut fm = formation.to_string();
-        self.remove_whitespace(&mut fm);
-
-        let pairs: Vec<&str> = fm.split(",").collect();
-        let mut result = HashMap::<String, usize>::new();
-
-        for pair in pairs {
-            let data: Vec<&str> = pair.split("=").collect();
-            let name = data[0];
-            let concurrency = data[1];
-            result.insert(String::from(name), concurrency.parse::<usize>().unwrap());
+pub fn read_procfile(filepath: PathBuf) -> Result<Procfile> {
+    match fs::read_to_string(&filepath) {
+        Ok(content) => Ok(Procfile::parse(&content)),
+        Err(why) if why.kind() == io::ErrorKind::NotFound => {
+            Err(UltramanError::ProcfileNotFound(filepath))
         }
-
-        result
+        Err(why) => Err(UltramanError::ProcfileRead {
+            path: filepath,
+            source: why,
+        }),
     }
-
-    fn remove_whitespace(&self, s: &mut String) {
-        s.retain(|c| !c.is_whitespace());
-    }
-}
-
-pub fn read_procfile(filepath: PathBuf) -> Result<Procfile, Box<dyn std::error::Error>> {
-    let display = filepath.clone().into_os_string().into_string().unwrap();
-
-    let file = match File::open(filepath) {
-        Ok(f) => f,
-        Err(why) => panic!("cloud't open {}: {}", display, why),
-    };
-
-    parse_procfile(&file)
 }
 
-fn parse_procfile(file: &File) -> Result<Procfile, Box<dyn std::error::Error>> {
-    let procfile_re = Regex::new(PROCFILE_REGEXP).unwrap();
-    let mut pf = Procfile {
-        data: HashMap::<String, ProcfileEntry>::new(),
-    };
-    let buf_reader = BufReader::new(file);
-
-    for line in buf_reader.lines() {
-        for cap in procfile_re.captures_iter(&line.unwrap()) {
-            let name = (&cap[1]).to_string();
-            pf.data.insert(
-                name,
-                ProcfileEntry {
-                    command: (&cap[2]).to_string(),
-                    concurrency: Cell::new(1),
-                },
-            );
+fn unparseable_hint(line: &str) -> &'static str {
+    let name_re = Regex::new(NAME_REGEXP).unwrap();
+    match line.split_once(':') {
+        Some((name, command)) => {
+            if !name_re.is_match(name) {
+                "process names may only contain letters, numbers, `_` and `-`"
+            } else if command.trim().is_empty() {
+                "missing command"
+            } else {
+                "expected `<name>: <command>`"
+            }
         }
+        None if line.contains('=') => "use `:` to separate the process name from the command",
+        None => "expected `<name>: <command>`",
     }
-
-    Ok(pf)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
     use std::io::Write;
     use tempfile::tempdir;
 
@@ -179,6 +206,7 @@ mod tests {
                 concurrency: Cell::new(1),
               }
             ],
+            issues: vec![],
         }
     }
 
@@ -194,10 +222,13 @@ mod tests {
     #[test]
     fn test_find_by() -> anyhow::Result<()> {
         let pf = create_procfile();
-        let result = pf.find_by("web");
+        let result = pf.find_by("web")?;
         assert_eq!(result.command, String::from("./app.sh"));
         assert_eq!(result.concurrency.get(), 1);
 
+        let result = pf.find_by("worker").err().unwrap();
+        assert_eq!(result.hint().unwrap(), "defined processes: app, web");
+
         Ok(())
     }
 
@@ -212,10 +243,10 @@ mod tests {
 
     #[test]
     fn test_set_concurrency() -> anyhow::Result<()> {
-        let formation = "app=2, web=3";
+        let formation = "app=2, web=3".parse()?;
         let pf = create_procfile();
 
-        pf.set_concurrency(formation);
+        pf.set_concurrency(&formation)?;
         assert_eq!(pf.data.get("app").unwrap().concurrency.get(), 2);
         assert_eq!(pf.data.get("web").unwrap().concurrency.get(), 3);
 
@@ -227,19 +258,37 @@ mod tests {
         let formation = "all=10";
         let pf = create_procfile();
 
-        pf.set_concurrency(formation);
+        pf.set_concurrency(&formation.parse()?)?;
         assert_eq!(pf.data.get("app").unwrap().concurrency.get(), 10);
         assert_eq!(pf.data.get("web").unwrap().concurrency.get(), 10);
 
+        pf.set_concurrency(&"all=2, web=0".parse()?)?;
+        assert_eq!(pf.data.get("app").unwrap().concurrency.get(), 2);
+        assert_eq!(pf.data.get("web").unwrap().concurrency.get(), 0);
+
         Ok(())
     }
 
     #[test]
-    #[should_panic(expected = "Do not support formation: hoge=1,fuga=2")]
-    fn test_set_concurrency_when_panic() {
-        let formation = "hoge=1,fuga=2";
+    fn test_set_concurrency_when_error() -> anyhow::Result<()> {
         let pf = create_procfile();
-        pf.set_concurrency(formation);
+
+        let result = pf.set_concurrency(&"hoge=1,fuga=2".parse()?).unwrap_err();
+        assert_eq!(
+            result.to_string(),
+            "invalid formation: no process called `fuga` in the Procfile (defined: app, web)"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_procfile_when_not_found() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let result = read_procfile(dir.path().join("Procfile")).err().unwrap();
+        assert_eq!(result.exit_code(), 66);
+
+        Ok(())
     }
 
     #[test]
@@ -252,9 +301,7 @@ mod tests {
 
     #[test]
     fn test_check_when_falsy() -> anyhow::Result<()> {
-        let pf = Procfile {
-            data: HashMap::new(),
-        };
+        let pf = Procfile::default();
         assert_eq!(pf.check(), false);
 
         Ok(())
@@ -269,7 +316,7 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_procfile() -> anyhow::Result<()> {
+    fn test_read_procfile() -> anyhow::Result<()> {
         let dir = tempdir()?;
         let procfile_path = dir.path().join("Procfile");
         let mut file = File::create(procfile_path.clone())?;
@@ -282,8 +329,7 @@ web: ./web.sh
         )
         .expect("failed write temp Procfile");
 
-        let read_file = File::open(procfile_path)?;
-        let result = parse_procfile(&read_file).expect("failed parse_procfile");
+        let result = read_procfile(procfile_path)?;
 
         assert!(result.data.contains_key("app"));
         assert!(result.data.contains_key("web"));
@@ -294,4 +340,54 @@ web: ./web.sh
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_with_issues() -> anyhow::Result<()> {
+        let result = Procfile::parse(
+            "# web server\n\nweb: ./web.sh\nworker = ./worker.sh\napi.v2: ./api.sh\njob:\n  # indented comment\nweb: ./web2.sh\n",
+        );
+
+        assert_eq!(result.data.len(), 1);
+        assert_eq!(result.data.get("web").unwrap().command, "./web2.sh");
+        assert_eq!(
+            result.issues,
+            vec![
+                ProcfileIssue {
+                    line: 4,
+                    content: String::from("worker = ./worker.sh"),
+                    kind: IssueKind::Unparseable {
+                        hint: "use `:` to separate the process name from the command"
+                    },
+                },
+                ProcfileIssue {
+                    line: 5,
+                    content: String::from("api.v2: ./api.sh"),
+                    kind: IssueKind::Unparseable {
+                        hint: "process names may only contain letters, numbers, `_` and `-`"
+                    },
+                },
+                ProcfileIssue {
+                    line: 6,
+                    content: String::from("job:"),
+                    kind: IssueKind::Unparseable {
+                        hint: "missing command"
+                    },
+                },
+                ProcfileIssue {
+                    line: 8,
+                    content: String::from("web: ./web2.sh"),
+                    kind: IssueKind::Duplicate {
+                        name: String::from("web"),
+                        first_line: 3
+                    },
+                },
+            ]
+        );
+        assert_eq!(
+            result.issues[3].to_string(),
+            "line 8: process `web` is already defined on line 3, this definition wins"
+        );
+
+        Ok(())
+    }
 }
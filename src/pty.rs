@@ -0,0 +1,137 @@
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::libc;
+use nix::pty::{openpty, Winsize};
+use nix::sys::termios::{self, LocalFlags, SetArg};
+use nix::unistd;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+// ^D, which a terminal in canonical mode reads as the end of input
+pub const EOF: u8 = 0x04;
+
+// A pseudo-terminal of its own for a process, so it colors and line-buffers its output
+// as if it were run in a terminal. Its stdout and stderr are one stream on the master side
+pub struct Pty {
+    pub master: File,
+    slave: File,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        let pty = openpty(&window_size(), None).map_err(io::Error::from)?;
+        // Other processes must not keep the terminal open, or it never sees EOF
+        for fd in [pty.master, pty.slave] {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(io::Error::from)?;
+        }
+        // SAFETY: openpty has just opened both, and nothing else owns them
+        unsafe {
+            Ok(Pty {
+                master: File::from_raw_fd(pty.master),
+                slave: File::from_raw_fd(pty.slave),
+            })
+        }
+    }
+
+    // Lines written to the master are not written back to it as a terminal echoes what is
+    // typed, since they are typed (and shown) in the terminal of ultraman already
+    pub fn disable_echo(&self) -> io::Result<()> {
+        let fd = self.slave.as_raw_fd();
+        let mut attrs = termios::tcgetattr(fd).map_err(io::Error::from)?;
+        attrs.local_flags.remove(LocalFlags::ECHO);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &attrs).map_err(io::Error::from)
+    }
+
+    // stdin, stdout and stderr of `command` become the terminal, which is also its
+    // controlling terminal in a session (and so a process group) of its own
+    pub fn attach(&self, command: &mut Command) -> io::Result<()> {
+        command
+            .stdin(Stdio::from(self.slave.try_clone()?))
+            .stdout(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave.try_clone()?));
+        // SAFETY: setsid and ioctl are async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                unistd::setsid().map_err(io::Error::from)?;
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+// Reads the output of the process until the terminal is closed
+pub fn reader(master: &File) -> io::Result<Reader> {
+    Ok(Reader(master.try_clone()?))
+}
+
+// Sends the process a SIGWINCH too
+pub fn resize(master: &File) -> io::Result<()> {
+    let size = window_size();
+    // SAFETY: TIOCSWINSZ only reads `size`
+    match unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+// The size of the terminal ultraman runs in, 80x24 if there is none
+fn window_size() -> Winsize {
+    let mut size = Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let stdout = io::stdout();
+    // SAFETY: TIOCGWINSZ only writes to `size`
+    let is_sized = stdout.is_terminal()
+        && unsafe { libc::ioctl(stdout.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } == 0;
+    if !is_sized || size.ws_col == 0 || size.ws_row == 0 {
+        size.ws_col = 80;
+        size.ws_row = 24;
+    }
+    size
+}
+
+// Linux fails reads with EIO once every process has closed the terminal, which is its EOF
+pub struct Reader(File);
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pty() -> anyhow::Result<()> {
+        let pty = Pty::open()?;
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("test -t 1 && test -t 2 && echo tty");
+        pty.attach(&mut command)?;
+        let mut child = command.spawn()?;
+        let mut reader = reader(&pty.master)?;
+        // Only the process keeps the terminal open now
+        drop(command);
+        drop(pty);
+
+        let mut output = String::new();
+        reader.read_to_string(&mut output)?;
+        child.wait()?;
+        assert_eq!(output, "tty\r\n");
+
+        Ok(())
+    }
+}
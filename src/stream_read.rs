@@ -1,69 +1,144 @@
-use crossbeam::channel::{unbounded, Receiver};
-use std::io;
-use std::string::FromUtf8Error;
-use std::thread::spawn;
+use chrono::{DateTime, Local};
+use std::io::{self, BufRead, BufReader};
+use std::mem;
+use std::thread::{self, JoinHandle};
+
+// Longer lines are split, so a process that never prints a newline cannot exhaust memory
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 16 * 1024;
+const BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Debug)]
 pub enum PipeError {
     IO(io::Error),
-    NotUtf8(FromUtf8Error),
 }
 
 #[derive(Debug)]
 pub enum PipedLine {
-    Line(String),
+    // Raw bytes (decoding is up to the reader, see output::Decoding) and when they were read
+    Line(Vec<u8>, DateTime<Local>),
     EOF,
 }
 
-#[derive(Debug)]
-pub struct PipeStreamReader {
-    pub lines: Receiver<Result<PipedLine, PipeError>>,
+// Hands each line of the stream to `yielder` as soon as it is read, then EOF
+pub fn build_read_thread<F>(
+    stream: Box<dyn io::Read + Send>,
+    max_line_length: usize,
+    yielder: F,
+) -> io::Result<JoinHandle<()>>
+where
+    F: FnMut(Result<PipedLine, PipeError>) -> bool + Send + 'static,
+{
+    thread::Builder::new()
+        .name(String::from("read output"))
+        .spawn(move || {
+            let reader = BufReader::with_capacity(BUFFER_SIZE, stream);
+            read_lines(reader, max_line_length, yielder);
+        })
 }
 
-impl PipeStreamReader {
-    pub fn new(mut stream: Box<dyn io::Read + Send>) -> PipeStreamReader {
-        PipeStreamReader {
-            lines: {
-                let (tx, rx) = unbounded();
-
-                spawn(move || {
-                    let mut buf = Vec::new();
-                    let mut byte = [0u8];
-                    loop {
-                        match stream.read(&mut byte) {
-                            Ok(0) => {
-                                let _ = tx.send(Ok(PipedLine::EOF));
-                                break;
-                            }
-                            Ok(_) => {
-                                if byte[0] == 0x0A {
-                                    match tx.send(match String::from_utf8(buf.clone()) {
-                                        Ok(line) => Ok(PipedLine::Line(line)),
-                                        Err(err) => Err(PipeError::NotUtf8(err)),
-                                    }) {
-                                        Ok(_) => {}
-                                        Err(_) => {
-                                            break;
-                                        }
-                                    }
-
-                                    buf.clear()
-                                } else {
-                                    buf.push(byte[0])
-                                }
-                            }
-                            Err(error) => {
-                                if let Err(_) = tx.send(Err(PipeError::IO(error))) {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                });
-
-                rx
-            },
+// Splits the stream into lines ended by `\n`, `\r\n` or a lone `\r`, with which progress
+// bars redraw their line. Stops at EOF, after a read error or once `yielder` returns false.
+fn read_lines<R, F>(mut reader: R, max_line_length: usize, yielder: F)
+where
+    R: BufRead,
+    F: FnMut(Result<PipedLine, PipeError>) -> bool,
+{
+    let mut lines = Lines {
+        line: Vec::new(),
+        max_line_length: max_line_length.max(1),
+        yielder,
+    };
+    // the last byte was a `\r`, so a `\n` right after it ends nothing
+    let mut is_after_cr = false;
+
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                if (lines.yielder)(Err(PipeError::IO(e))) {
+                    lines.finish();
+                }
+                return;
+            }
+        };
+        if buf.is_empty() {
+            lines.finish();
+            return;
+        }
+
+        let len = buf.len();
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if is_after_cr && rest[0] == b'\n' {
+                rest = &rest[1..];
+            }
+            is_after_cr = false;
+
+            let is_continued = match rest.iter().position(|&b| b == b'\n' || b == b'\r') {
+                Some(pos) => {
+                    is_after_cr = rest[pos] == b'\r';
+                    let is_continued = lines.push(&rest[..pos])
+                        // an empty redraw is not worth a line
+                        && (is_after_cr && lines.line.is_empty() || lines.end_line());
+                    rest = &rest[pos + 1..];
+                    is_continued
+                }
+                None => {
+                    let is_continued = lines.push(rest);
+                    rest = &[];
+                    is_continued
+                }
+            };
+            if !is_continued {
+                return;
+            }
+        }
+        reader.consume(len);
+    }
+}
+
+struct Lines<F> {
+    line: Vec<u8>,
+    max_line_length: usize,
+    yielder: F,
+}
+
+impl<F> Lines<F>
+where
+    F: FnMut(Result<PipedLine, PipeError>) -> bool,
+{
+    // Returns false once nobody wants more lines
+    fn push(&mut self, mut bytes: &[u8]) -> bool {
+        while self.line.len() + bytes.len() > self.max_line_length {
+            let mut at = self.max_line_length - self.line.len();
+            // don't split a UTF-8 character in two
+            let boundary = (at.saturating_sub(3)..=at)
+                .rev()
+                .find(|&i| i == bytes.len() || bytes[i] & 0xC0 != 0x80);
+            if let Some(boundary) = boundary.filter(|&b| b > 0 || !self.line.is_empty()) {
+                at = boundary;
+            }
+
+            self.line.extend_from_slice(&bytes[..at]);
+            bytes = &bytes[at..];
+            if !self.end_line() {
+                return false;
+            }
         }
+
+        self.line.extend_from_slice(bytes);
+        true
+    }
+
+    fn end_line(&mut self) -> bool {
+        let line = mem::take(&mut self.line);
+        (self.yielder)(Ok(PipedLine::Line(line, Local::now())))
+    }
+
+    // A last line without a newline is still a line
+    fn finish(&mut self) -> bool {
+        (self.line.is_empty() || self.end_line()) && (self.yielder)(Ok(PipedLine::EOF))
     }
 }
 
@@ -71,26 +146,37 @@ impl PipeStreamReader {
 mod tests {
     use super::*;
     use anyhow;
+    use crossbeam::channel::{unbounded, Receiver};
     use std::process::{Command, Stdio};
 
+    // Lines of the stream on a channel of their own
+    fn lines_of(
+        stream: Box<dyn io::Read + Send>,
+    ) -> io::Result<Receiver<Result<PipedLine, PipeError>>> {
+        let (tx, rx) = unbounded();
+        build_read_thread(stream, DEFAULT_MAX_LINE_LENGTH, move |line| {
+            tx.send(line).is_ok()
+        })?;
+        Ok(rx)
+    }
+
     #[test]
-    fn test_new() -> anyhow::Result<()> {
+    fn test_build_read_thread() -> anyhow::Result<()> {
         let mut child = Command::new("echo")
             .arg("Test")
             .stdout(Stdio::piped())
             .spawn()
             .expect("failed execute command");
         let stream = Box::new(child.stdout.take().unwrap());
-        let result = PipeStreamReader::new(stream);
+        let lines = lines_of(stream)?;
 
-        match result.lines.recv().unwrap() {
+        match lines.recv().unwrap() {
             Ok(piped_line) => match piped_line {
-                PipedLine::Line(line) => assert_eq!(line, "Test"),
+                PipedLine::Line(line, _) => assert_eq!(line, b"Test"),
                 PipedLine::EOF => println!("EOF"),
             },
             Err(error) => match error {
                 PipeError::IO(err) => println!("{}", err),
-                PipeError::NotUtf8(err) => println!("{}", err),
             },
         }
 
@@ -105,12 +191,12 @@ mod tests {
             .spawn()
             .expect("failed execute command");
         let stream = Box::new(child.stdout.take().unwrap());
-        let reader = PipeStreamReader::new(stream);
+        let lines = lines_of(stream)?;
 
         let mut lines_received = 0;
         loop {
-            match reader.lines.recv() {
-                Ok(Ok(PipedLine::Line(_))) => {
+            match lines.recv() {
+                Ok(Ok(PipedLine::Line(..))) => {
                     lines_received += 1;
                 }
                 Ok(Ok(PipedLine::EOF)) => {
@@ -131,7 +217,6 @@ mod tests {
 
     #[test]
     fn test_graceful_channel_disconnection() -> anyhow::Result<()> {
-        use std::sync::Arc;
         use std::thread;
         use std::time::Duration;
 
@@ -140,14 +225,13 @@ mod tests {
             .spawn()
             .expect("failed execute command");
         let stream = Box::new(child.stdout.take().unwrap());
-        let reader = Arc::new(PipeStreamReader::new(stream));
+        let lines = lines_of(stream)?;
 
-        let reader_clone = Arc::clone(&reader);
         let handle = thread::spawn(move || {
             let mut count = 0;
             while count < 5 {
-                match reader_clone.lines.recv() {
-                    Ok(Ok(PipedLine::Line(_))) => count += 1,
+                match lines.recv() {
+                    Ok(Ok(PipedLine::Line(..))) => count += 1,
                     Ok(Ok(PipedLine::EOF)) => break,
                     Ok(Err(_)) => break,
                     Err(_) => break,
@@ -157,8 +241,91 @@ mod tests {
 
         thread::sleep(Duration::from_millis(100));
         child.kill().expect("failed to kill child");
-        
+
         handle.join().expect("thread panicked");
         Ok(())
     }
+
+    fn collect_lines(input: &str, capacity: usize, max_line_length: usize) -> Vec<String> {
+        let reader = BufReader::with_capacity(capacity, input.as_bytes());
+        let mut lines = vec![];
+        read_lines(reader, max_line_length, |line| {
+            lines.push(match line {
+                Ok(PipedLine::Line(line, _)) => {
+                    String::from_utf8(line).expect("failed decode line")
+                }
+                Ok(PipedLine::EOF) => String::from("<EOF>"),
+                Err(e) => format!("{:?}", e),
+            });
+            true
+        });
+        lines
+    }
+
+    #[test]
+    fn test_read_lines() -> anyhow::Result<()> {
+        let input = "a\n\nb\r\n\rprogress 1\rprogress 2\r\nlast";
+        let expected = vec!["a", "", "b", "progress 1", "progress 2", "last", "<EOF>"];
+
+        // a small buffer splits `\r\n` and lines across reads
+        for capacity in 1..=input.len() {
+            assert_eq!(
+                collect_lines(input, capacity, 100),
+                expected,
+                "{}",
+                capacity
+            );
+        }
+        assert_eq!(collect_lines("", 8, 100), vec!["<EOF>"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_lines_with_max_line_length() -> anyhow::Result<()> {
+        assert_eq!(
+            collect_lines("abcdefg\nhi\n", 4, 3),
+            vec!["abc", "def", "g", "hi", "<EOF>"]
+        );
+        // あ is 3 bytes long
+        assert_eq!(collect_lines("ああ\n", 8, 4), vec!["あ", "あ", "<EOF>"]);
+
+        Ok(())
+    }
+
+    // Benchmark: cargo test --release -- --ignored --nocapture bench_throughput
+    #[test]
+    #[ignore]
+    fn bench_throughput() -> anyhow::Result<()> {
+        use std::time::Instant;
+
+        let line = "I, [2021-01-01T00:00:00] INFO -- : Completed 200 OK in 12ms (Views: 3.4ms)";
+        let count = 1_000_000;
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(format!("yes '{}' | head -n {}", line, count))
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let start = Instant::now();
+        let lines = lines_of(Box::new(child.stdout.take().unwrap()))?;
+        let mut received = 0;
+        while let Ok(Ok(PipedLine::Line(..))) = lines.recv() {
+            received += 1;
+        }
+        let elapsed = start.elapsed();
+        child.wait()?;
+
+        let mb = (line.len() + 1) as f64 * count as f64 / 1024.0 / 1024.0;
+        println!(
+            "{} lines ({:.0}MB) in {:?}: {:.0}MB/s",
+            received,
+            mb,
+            elapsed,
+            mb / elapsed.as_secs_f64()
+        );
+        assert_eq!(received, count);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,1422 @@
+use crate::config::{DEFAULT_ENV, DEFAULT_SHELL, DEFAULT_TIMEOUT};
+use crate::control::{History, Listener, Record, Reply, Request, DEFAULT_HISTORY};
+use crate::error::{self, Result};
+use crate::formation::Formation;
+use crate::log::file::LogFiles;
+use crate::log::{self, ColorChoice, LogFormat, LogOpt, Source, Stream, TimestampFormat};
+use crate::output::{self, Decoding, Filter, Output};
+use crate::process::{self, Io, Process, Runner};
+use crate::procfile::Procfile;
+use crate::pty;
+use crate::signal;
+use crate::stream_read::{self, PipeError, PipedLine, DEFAULT_MAX_LINE_LENGTH};
+use crate::tui;
+
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use nix::sys::signal::Signal;
+use regex::Regex;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::mem;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Ctrl-], the escape character of telnet. A line starting with it switches the attached process
+const ATTACH_CHORD: u8 = 0x1d;
+
+// What happened to the processes, in the order it happened.
+// `name` is the name of the instance (e.g. web.1)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Spawned {
+        name: String,
+        pid: u32,
+    },
+    // Decoded as configured, raw output is decoded lossily
+    Output {
+        name: String,
+        line: String,
+    },
+    Exited {
+        name: String,
+        pid: u32,
+        status: ExitStatus,
+    },
+    // The instance is stopped and will be spawned again once it has exited
+    Restarting {
+        name: String,
+    },
+    ShutdownStarted {
+        reason: ShutdownReason,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShutdownReason {
+    Signal(Signal),
+    // An instance exited without being asked to
+    Exited(String),
+    // Handle::shutdown was called
+    Requested,
+}
+
+// Controls a running Supervisor from another thread
+#[derive(Clone)]
+pub struct Handle {
+    tx: Sender<Message>,
+}
+
+impl Handle {
+    pub fn start(&self, name: &str) {
+        self.send(Command::Start(name.to_string()));
+    }
+
+    pub fn stop(&self, name: &str) {
+        self.send(Command::Stop(name.to_string()));
+    }
+
+    pub fn restart(&self, name: &str) {
+        self.send(Command::Restart(name.to_string()));
+    }
+
+    pub fn shutdown(&self) {
+        self.send(Command::Shutdown);
+    }
+
+    // Lines of the process (e.g. web, not web.1) are not printed until unquiet is called
+    pub fn quiet(&self, process_name: &str) {
+        self.send(Command::Quiet(process_name.to_string(), true));
+    }
+
+    pub fn unquiet(&self, process_name: &str) {
+        self.send(Command::Quiet(process_name.to_string(), false));
+    }
+
+    // Only lines of these processes are printed, and every process again if it is empty
+    pub fn only(&self, process_names: &[&str]) {
+        let names = process_names.iter().map(|name| name.to_string()).collect();
+        self.send(Command::Only(names));
+    }
+
+    // Only lines matching `regex` are printed, and every line again if it is None
+    pub fn include(&self, process_name: &str, regex: Option<Regex>) {
+        self.send(Command::Include(process_name.to_string(), regex));
+    }
+
+    // Lines matching `regex` are not printed, and every line again if it is None
+    pub fn exclude(&self, process_name: &str, regex: Option<Regex>) {
+        self.send(Command::Exclude(process_name.to_string(), regex));
+    }
+
+    // Lines read from stdin are written to the instance (e.g. web.2, or web for web.1),
+    // and to no instance if it is None. Only works if the supervisor was started with `attach`
+    pub fn attach(&self, name: Option<&str>) {
+        self.send(Command::Attach(name.map(|name| name.to_string())));
+    }
+
+    fn send(&self, command: Command) {
+        // The supervisor may have returned already
+        let _ = self.tx.send(Message::Command(command));
+    }
+}
+
+enum Command {
+    Start(String),
+    Stop(String),
+    Restart(String),
+    Shutdown,
+    Quiet(String, bool),
+    Only(Vec<String>),
+    Include(String, Option<Regex>),
+    Exclude(String, Option<Regex>),
+    Attach(Option<String>),
+}
+
+enum Message {
+    Output {
+        slot: usize,
+        pid: u32,
+        stream: Stream,
+        line: std::result::Result<PipedLine, PipeError>,
+    },
+    Signal(i32),
+    Command(Command),
+    // `ultraman logs` connected to the control socket
+    Logs(Request, Sender<Reply>),
+    // A line read from stdin for the attached instance, None once stdin is at EOF
+    Input(Option<Vec<u8>>),
+}
+
+// Starts every process of a formation and supervises them until they are all gone
+//
+// e.g.)
+// Supervisor::new(procfile)
+//     .formation("all=1,web=2".parse()?)
+//     .env_path(PathBuf::from(".env"))
+//     .run()?;
+pub struct Supervisor {
+    procfile: Procfile,
+    formation: Option<Formation>,
+    env_path: PathBuf,
+    port: Option<u32>,
+    timeout: u64,
+    is_timestamp: bool,
+    timestamp_format: TimestampFormat,
+    color: ColorChoice,
+    log_format: LogFormat,
+    log_files: Option<LogFiles>,
+    // streams of each process that are not printed
+    hidden: HashMap<String, Vec<Stream>>,
+    // lines of each process that are printed
+    filters: HashMap<String, Filter>,
+    // processes that are printed, every process if it is empty
+    only: Vec<String>,
+    max_line_length: usize,
+    decoding: Decoding,
+    events: Option<Sender<Event>>,
+    // lines of each instance kept for `ultraman logs`
+    history: usize,
+    socket: Option<PathBuf>,
+    // the shell commands are run with, except for processes that are run directly
+    shell: PathBuf,
+    exec: Vec<String>,
+    // processes spawned on a terminal of their own
+    pty: Vec<String>,
+    // the process stdin is written to first
+    attach: Option<String>,
+    stdin: Option<Box<dyn Read + Send>>,
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+}
+
+impl Supervisor {
+    pub fn new(procfile: Procfile) -> Self {
+        let (tx, rx) = unbounded();
+        Supervisor {
+            procfile,
+            formation: None,
+            env_path: PathBuf::from(DEFAULT_ENV),
+            port: None,
+            timeout: DEFAULT_TIMEOUT,
+            is_timestamp: true,
+            timestamp_format: TimestampFormat::default(),
+            color: ColorChoice::default(),
+            log_format: LogFormat::default(),
+            log_files: None,
+            hidden: HashMap::new(),
+            filters: HashMap::new(),
+            only: vec![],
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            decoding: Decoding::default(),
+            events: None,
+            history: DEFAULT_HISTORY,
+            socket: None,
+            shell: PathBuf::from(DEFAULT_SHELL),
+            exec: vec![],
+            pty: vec![],
+            attach: None,
+            stdin: None,
+            tx,
+            rx,
+        }
+    }
+
+    pub fn formation(mut self, formation: Formation) -> Self {
+        self.formation = Some(formation);
+        self
+    }
+
+    pub fn env_path(mut self, env_path: PathBuf) -> Self {
+        self.env_path = env_path;
+        self
+    }
+
+    pub fn port(mut self, port: Option<u32>) -> Self {
+        self.port = port;
+        self
+    }
+
+    // Seconds processes have to shut down gracefully before they are killed
+    pub fn timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timestamp(mut self, is_timestamp: bool) -> Self {
+        self.is_timestamp = is_timestamp;
+        self
+    }
+
+    // How the timestamps of system and process lines are written
+    pub fn timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    // Whether system and process lines are colored
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        self
+    }
+
+    // How system and process lines are written
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    // Output of each instance is also written to its own file
+    pub fn log_files(mut self, log_files: LogFiles) -> Self {
+        self.log_files = Some(log_files);
+        self
+    }
+
+    // Lines a process writes to `stream` are not printed (they still go to log files and events)
+    pub fn hide(mut self, process_name: &str, stream: Stream) -> Self {
+        let streams = self.hidden.entry(process_name.to_string()).or_default();
+        if !streams.contains(&stream) {
+            streams.push(stream);
+        }
+        self
+    }
+
+    // The process keeps running, but its lines are not printed (they still go to log files and events)
+    pub fn quiet(mut self, process_name: &str) -> Self {
+        self.filters
+            .entry(process_name.to_string())
+            .or_default()
+            .is_quiet = true;
+        self
+    }
+
+    // Only lines of the processes passed to `only` are printed
+    pub fn only(mut self, process_name: &str) -> Self {
+        self.only.push(process_name.to_string());
+        self
+    }
+
+    // Only lines of the process matching one of the included regexes are printed
+    pub fn include(mut self, process_name: &str, regex: Regex) -> Self {
+        self.filters
+            .entry(process_name.to_string())
+            .or_default()
+            .include
+            .push(regex);
+        self
+    }
+
+    // Lines of the process matching one of the excluded regexes are not printed
+    pub fn exclude(mut self, process_name: &str, regex: Regex) -> Self {
+        self.filters
+            .entry(process_name.to_string())
+            .or_default()
+            .exclude
+            .push(regex);
+        self
+    }
+
+    // Longer output lines are split into several lines (in bytes)
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    // How output that is not valid UTF-8 is printed
+    pub fn decoding(mut self, decoding: Decoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
+
+    // Every event is also sent to `events`, next to what is printed
+    pub fn events(mut self, events: Sender<Event>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    // Number of lines of each instance kept for `ultraman logs`
+    pub fn history(mut self, history: usize) -> Self {
+        self.history = history;
+        self
+    }
+
+    // `ultraman logs` reads recent and live lines from a socket at `path`
+    pub fn control_socket(mut self, path: PathBuf) -> Self {
+        self.socket = Some(path);
+        self
+    }
+
+    // Commands are run with `shell -c command`
+    pub fn shell(mut self, shell: PathBuf) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    // The command of the process is split into words and run without a shell, so it
+    // can't use shell syntax (pipes, &&, $VARIABLES) but needs no shell either
+    pub fn exec(mut self, process_name: &str) -> Self {
+        self.exec.push(process_name.to_string());
+        self
+    }
+
+    // The process runs on a pseudo-terminal of its own, so it colors and line-buffers its
+    // output as in a terminal. What it writes to stderr is then printed as stdout
+    pub fn pty(mut self, process_name: &str) -> Self {
+        self.pty.push(process_name.to_string());
+        self
+    }
+
+    // stdin is written to the process (e.g. web, or web.2 for an instance) instead of being
+    // inherited by every process. Handle::attach or Ctrl-] and a name switches it
+    pub fn attach(mut self, name: &str) -> Self {
+        self.attach = Some(name.to_string());
+        self
+    }
+
+    // Where attached input is read from, the terminal by default
+    pub fn stdin<R: Read + Send + 'static>(mut self, stdin: R) -> Self {
+        self.stdin = Some(Box::new(stdin));
+        self
+    }
+
+    pub fn handle(&self) -> Handle {
+        Handle {
+            tx: self.tx.clone(),
+        }
+    }
+
+    // Returns once every process has exited
+    pub fn run(self) -> Result<()> {
+        let result = self.start();
+        // The caller may print or exit right away
+        log::flush();
+        result
+    }
+
+    // Shows the processes in a dashboard instead of printing their output (--tui), until q is
+    // pressed after every process has exited. Fails unless stdin and stdout are a terminal
+    pub fn run_tui(self) -> Result<()> {
+        let (env_path, port) = (self.env_path.clone(), self.port);
+        tui::run(self, env_path, port)
+    }
+
+    fn start(self) -> Result<()> {
+        let procfile = self.procfile;
+        if let Some(formation) = &self.formation {
+            procfile.set_concurrency(formation)?;
+        }
+        for name in self
+            .hidden
+            .keys()
+            .chain(self.filters.keys())
+            .chain(self.only.iter())
+            .chain(self.pty.iter())
+            .chain(self.exec.iter())
+        {
+            procfile.find_by(name)?;
+        }
+
+        // Relative timestamps count from here
+        log::started_at();
+        let is_color = self.color.is_color();
+        // colored makes its own guess from the environment otherwise
+        colored::control::set_override(is_color);
+        let opts = LogOpt {
+            format: self.log_format,
+            is_color,
+            // Names are only aligned in text
+            padding: match self.log_format {
+                LogFormat::Text => procfile.padding(),
+                LogFormat::Json | LogFormat::Logfmt => 0,
+            },
+            is_timestamp: self.is_timestamp,
+            timestamp_format: self.timestamp_format,
+        };
+        let mut children = Children {
+            instances: vec![],
+            outputs: vec![],
+            env_path: self.env_path,
+            port: self.port,
+            timeout: Duration::from_secs(self.timeout),
+            max_line_length: self.max_line_length,
+            decoding: self.decoding,
+            opts: opts.clone(),
+            events: self.events,
+            history: History::new(self.history, procfile.padding()),
+            is_attach: self.attach.is_some(),
+            attached: None,
+            tx: self.tx.clone(),
+            shutdown: None,
+        };
+
+        for (app_index, (name, pe)) in procfile.data.iter().enumerate() {
+            let names = (1..=pe.concurrency.get())
+                .map(|n| process::ps_for(name, n))
+                .collect::<Vec<_>>();
+            let hidden = self.hidden.get(name).cloned().unwrap_or_default();
+            let output = Output::new(app_index, opts.clone()).hide(hidden);
+            let mut filter = self.filters.get(name).cloned().unwrap_or_default();
+            if !self.only.is_empty() && !self.only.contains(name) {
+                filter.is_quiet = true;
+            }
+            output.set_filter(filter);
+            children.outputs.push(match &self.log_files {
+                Some(files) => output.with_files(app_index, &opts, &names, files)?,
+                None => output,
+            });
+            for (n, instance_name) in names.into_iter().enumerate() {
+                children.instances.push(Instance {
+                    index: app_index,
+                    process_name: name.clone(),
+                    command: pe.command.clone(),
+                    concurrency_index: n,
+                    name: instance_name,
+                    runner: if self.exec.contains(name) {
+                        Runner::Exec
+                    } else {
+                        Runner::Shell(self.shell.clone())
+                    },
+                    is_pty: self.pty.contains(name),
+                    proc: None,
+                    open_outputs: 0,
+                    on_exit: OnExit::Shutdown,
+                    deadline: None,
+                });
+            }
+        }
+
+        if let Some(name) = &self.attach {
+            match children.find(name) {
+                Some(slot) => children.attached = Some(slot),
+                None => {
+                    procfile.find_by(name)?;
+                }
+            }
+        }
+
+        // Removes the socket once every process is gone
+        let _listener = match &self.socket {
+            Some(path) => {
+                let tx = self.tx.clone();
+                Some(Listener::bind(path, move |request, reply| {
+                    tx.send(Message::Logs(request, reply)).is_ok()
+                })?)
+            }
+            None => None,
+        };
+
+        let tx = self.tx.clone();
+        let (signals, signal_thread) = signal::handle_signal_thread(move |sig| {
+            let _ = tx.send(Message::Signal(sig));
+        })?;
+
+        // Spawn every process before supervising, so a failure can still be returned
+        for slot in 0..children.instances.len() {
+            if let Err(e) = children.spawn(slot) {
+                children.kill_spawned();
+                signals.close();
+                error::join(signal_thread)?;
+                return Err(e);
+            }
+        }
+
+        if let Some(slot) = children.attached {
+            children.system(&format!(
+                "stdin is attached to {} (Ctrl-] and a process name, then Enter, switches it)",
+                children.instances[slot].name
+            ));
+            let is_terminal = self.stdin.is_none() && io::stdin().is_terminal();
+            let stdin = self.stdin.unwrap_or_else(|| Box::new(io::stdin()));
+            read_input_thread(stdin, is_terminal, self.tx.clone())?;
+        }
+
+        children.supervise(&self.rx);
+
+        signals.close();
+        error::join(signal_thread)
+    }
+}
+
+// What to do when an instance exits
+enum OnExit {
+    Shutdown,
+    Stop,
+    Restart,
+}
+
+struct Instance {
+    // index of the process in the Procfile, which picks the color
+    index: usize,
+    process_name: String,
+    command: String,
+    concurrency_index: usize,
+    name: String,
+    runner: Runner,
+    is_pty: bool,
+    proc: Option<Process>,
+    // stdout and stderr that have not seen EOF yet (a restarted instance can have more)
+    open_outputs: usize,
+    on_exit: OnExit,
+    // when a signaled instance gets a SIGKILL
+    deadline: Option<Instant>,
+}
+
+struct Children {
+    instances: Vec<Instance>,
+    outputs: Vec<Output>,
+    env_path: PathBuf,
+    port: Option<u32>,
+    timeout: Duration,
+    max_line_length: usize,
+    decoding: Decoding,
+    opts: LogOpt,
+    events: Option<Sender<Event>>,
+    history: History,
+    // whether processes get a pipe as stdin, and the instance it is written to
+    is_attach: bool,
+    attached: Option<usize>,
+    tx: Sender<Message>,
+    shutdown: Option<ShutdownReason>,
+}
+
+impl Children {
+    // Sleeps until something happens: children are reaped on SIGCHLD, and the only
+    // timer is the deadline of a signaled instance
+    fn supervise(&mut self, rx: &Receiver<Message>) {
+        // A child may have exited before its SIGCHLD was handled
+        self.reap();
+
+        // Output still in the pipes is printed before returning
+        while self
+            .instances
+            .iter()
+            .any(|i| i.proc.is_some() || i.open_outputs > 0)
+        {
+            let message = match self.next_deadline() {
+                Some(deadline) => rx.recv_deadline(deadline),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match message {
+                Ok(Message::Output {
+                    slot,
+                    pid,
+                    stream,
+                    line,
+                }) => self.output(slot, pid, stream, line),
+                Ok(Message::Signal(sig)) => match Signal::try_from(sig) {
+                    Ok(Signal::SIGCHLD) => self.reap(),
+                    // Ctrl-C, `kill`, `systemctl stop` or `docker stop`, and the terminal closing
+                    Ok(sig @ Signal::SIGINT)
+                    | Ok(sig @ Signal::SIGTERM)
+                    | Ok(sig @ Signal::SIGHUP) => self.shutdown(ShutdownReason::Signal(sig)),
+                    Ok(Signal::SIGWINCH) => self.resize(),
+                    _ => (),
+                },
+                Ok(Message::Command(command)) => self.command(command),
+                Ok(Message::Logs(request, reply)) => self.logs(request, reply),
+                Ok(Message::Input(line)) => self.input(line),
+                // Children keeps a sender, so the channel is never disconnected
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => (),
+            }
+
+            self.kill_expired();
+        }
+
+        if let Some(ShutdownReason::Signal(_)) = self.shutdown {
+            self.system("exit 0");
+        }
+        for output in self.outputs.iter() {
+            output.log.flush();
+        }
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<()> {
+        let instance = &mut self.instances[slot];
+        let env = process::env_for(
+            &instance.process_name,
+            self.env_path.clone(),
+            self.port,
+            instance.concurrency_index,
+        )?;
+        let mut proc = Process::new(
+            &instance.process_name,
+            instance.concurrency_index,
+            &instance.command,
+            &instance.runner,
+            env,
+            match (instance.is_pty, self.is_attach) {
+                // Any instance can be attached later on
+                (true, is_attach) => Io::Pty {
+                    is_echo: !is_attach,
+                },
+                (false, true) => Io::Pipes {
+                    stdin: Stdio::piped(),
+                },
+                (false, false) => Io::Pipes {
+                    stdin: Stdio::null(),
+                },
+            },
+            Some(self.opts.clone()),
+        )?;
+        let pid = proc.child.id();
+
+        let tx = self.tx.clone();
+        let max_line_length = self.max_line_length;
+        let outputs = match &proc.pty {
+            // A terminal has a single stream
+            Some(master) => pty::reader(master).and_then(|reader| {
+                stream_read::build_read_thread(Box::new(reader), max_line_length, move |line| {
+                    let _ = tx.send(Message::Output {
+                        slot,
+                        pid,
+                        stream: Stream::Stdout,
+                        line,
+                    });
+                    true
+                })
+                .map(|_| 1)
+            }),
+            None => output::build_output_threads(
+                Box::new(proc.child.stdout.take().expect("failed take stdout")),
+                Box::new(proc.child.stderr.take().expect("failed take stderr")),
+                max_line_length,
+                move |stream, line| {
+                    let _ = tx.send(Message::Output {
+                        slot,
+                        pid,
+                        stream,
+                        line,
+                    });
+                },
+            )
+            .map(|_| 2),
+        };
+        match outputs {
+            Ok(outputs) => instance.open_outputs += outputs,
+            Err(e) => {
+                // Nothing would read its output, so the process is not left running
+                let _ = proc.child.kill();
+                let _ = proc.child.wait();
+                return Err(e.into());
+            }
+        }
+
+        instance.proc = Some(proc);
+        let name = instance.name.clone();
+        self.system(&format!(
+            "{0:1$} start at pid: {2}",
+            &name, self.opts.padding, pid
+        ));
+        self.emit(Event::Spawned { name, pid });
+
+        Ok(())
+    }
+
+    fn output(
+        &mut self,
+        slot: usize,
+        pid: u32,
+        stream: Stream,
+        line: std::result::Result<PipedLine, PipeError>,
+    ) {
+        let instance = &self.instances[slot];
+        let log = &self.outputs[instance.index].log;
+
+        match line {
+            // saturating, as a stdout thread can outlive a stderr thread that failed to start
+            Ok(PipedLine::EOF) => {
+                let instance = &mut self.instances[slot];
+                instance.open_outputs = instance.open_outputs.saturating_sub(1);
+            }
+            Ok(PipedLine::Line(bytes, at)) => {
+                let line = self.decoding.decode(&bytes);
+                let source = Source {
+                    name: &instance.name,
+                    process: Some(&instance.process_name),
+                    pid: Some(pid),
+                    stream: Some(stream),
+                    at,
+                };
+                match self.decoding {
+                    Decoding::Raw => log.output_raw(&source, &bytes),
+                    _ => log.output_at(&source, &line),
+                }
+                self.history.push(Record {
+                    name: instance.name.clone(),
+                    process: instance.process_name.clone(),
+                    index: instance.index,
+                    pid,
+                    stream: Some(stream),
+                    at: Record::at(&at),
+                    line: line.clone().into_owned(),
+                });
+                self.emit(Event::Output {
+                    name: instance.name.clone(),
+                    line: line.into_owned(),
+                });
+            }
+            Err(PipeError::IO(err)) => log.error(&instance.name, &err),
+        }
+    }
+
+    fn command(&mut self, command: Command) {
+        let name = match &command {
+            Command::Shutdown => return self.shutdown(ShutdownReason::Requested),
+            Command::Attach(name) => return self.attach(name.clone()),
+            Command::Quiet(..) | Command::Only(_) | Command::Include(..) | Command::Exclude(..) => {
+                return self.filter(command)
+            }
+            Command::Start(name) | Command::Stop(name) | Command::Restart(name) => name,
+        };
+        let slot = match self.instances.iter().position(|i| &i.name == name) {
+            Some(slot) => slot,
+            None => return self.system(&format!("no process called `{}`", name)),
+        };
+        let is_running = self.instances[slot].proc.is_some();
+
+        match command {
+            Command::Start(_) if !is_running && self.shutdown.is_none() => self.respawn(slot),
+            Command::Stop(_) if is_running => {
+                self.instances[slot].on_exit = OnExit::Stop;
+                self.terminate(slot);
+            }
+            Command::Restart(name) if self.shutdown.is_none() => {
+                self.emit(Event::Restarting { name });
+                if is_running {
+                    self.instances[slot].on_exit = OnExit::Restart;
+                    self.terminate(slot);
+                } else {
+                    self.respawn(slot);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn attach(&mut self, name: Option<String>) {
+        if !self.is_attach {
+            return self.system("stdin is not attached, start with --attach to attach it");
+        }
+        match name {
+            Some(name) => match self.find(&name) {
+                Some(slot) => {
+                    self.attached = Some(slot);
+                    self.system(&format!(
+                        "stdin is attached to {}",
+                        self.instances[slot].name
+                    ));
+                }
+                None => self.system(&format!("no process called `{}`", name)),
+            },
+            None => {
+                self.attached = None;
+                self.system("stdin is detached");
+            }
+        }
+    }
+
+    // Closing the pipe at EOF lets the attached instance see the end of its input too
+    fn input(&mut self, line: Option<Vec<u8>>) {
+        let slot = match self.attached {
+            Some(slot) => slot,
+            None => return,
+        };
+        let instance = &mut self.instances[slot];
+        let name = instance.name.clone();
+        let proc = match instance.proc.as_mut() {
+            Some(proc) => proc,
+            None => return self.system(&format!("{} is not running, input dropped", name)),
+        };
+        let result = match (line, proc.pty.as_mut()) {
+            // As if it were typed in the terminal
+            (Some(line), Some(master)) => master.write_all(&line),
+            (None, Some(master)) => master.write_all(&[pty::EOF]),
+            (Some(line), None) => match proc.child.stdin.as_mut() {
+                Some(stdin) => stdin.write_all(&line).and_then(|_| stdin.flush()),
+                None => return self.system(&format!("stdin of {} is closed", name)),
+            },
+            (None, None) => {
+                proc.child.stdin.take();
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            log::error(&name, &e, true, &self.opts);
+        }
+    }
+
+    // The terminal ultraman runs in was resized, so are the ones of the processes
+    fn resize(&self) {
+        for proc in self.instances.iter().filter_map(|i| i.proc.as_ref()) {
+            if let Some(master) = &proc.pty {
+                if let Err(e) = pty::resize(master) {
+                    log::error(&proc.name, &e, true, &self.opts);
+                }
+            }
+        }
+    }
+
+    // An instance (e.g. web.2), or the first instance of a process (e.g. web)
+    fn find(&self, name: &str) -> Option<usize> {
+        self.instances
+            .iter()
+            .position(|i| i.name == name)
+            .or_else(|| self.instances.iter().position(|i| i.process_name == name))
+    }
+
+    fn logs(&mut self, request: Request, reply: Sender<Reply>) {
+        let unknown = request.names.iter().find(|name| {
+            !self
+                .instances
+                .iter()
+                .any(|i| &&i.name == name || &&i.process_name == name)
+        });
+        if let Some(name) = unknown {
+            let mut defined = self
+                .instances
+                .iter()
+                .map(|i| i.process_name.as_str())
+                .collect::<Vec<_>>();
+            defined.dedup();
+            let _ = reply.send(Reply::UnknownProcess {
+                name: name.clone(),
+                defined: defined.join(", "),
+            });
+            return;
+        }
+        self.history.serve(request, reply);
+    }
+
+    // Filters are per process (e.g. web), so every instance of it is affected
+    fn filter(&mut self, command: Command) {
+        let unknown = match &command {
+            Command::Only(names) => names.iter().find(|name| self.process_index(name).is_none()),
+            Command::Quiet(name, _) | Command::Include(name, _) | Command::Exclude(name, _) => {
+                Some(name).filter(|name| self.process_index(name).is_none())
+            }
+            _ => None,
+        };
+        if let Some(name) = unknown {
+            return self.system(&format!("no process called `{}`", name));
+        }
+
+        let processes = self
+            .instances
+            .iter()
+            .map(|i| (i.index, i.process_name.as_str()))
+            .collect::<HashMap<_, _>>();
+        for (index, process_name) in processes {
+            let output = &self.outputs[index];
+            let mut filter = output.filter();
+            match &command {
+                Command::Only(names) => {
+                    filter.is_quiet = !names.is_empty() && !names.iter().any(|n| n == process_name)
+                }
+                Command::Quiet(name, is_quiet) if name == process_name => {
+                    filter.is_quiet = *is_quiet
+                }
+                Command::Include(name, regex) if name == process_name => {
+                    filter.include = regex.iter().cloned().collect()
+                }
+                Command::Exclude(name, regex) if name == process_name => {
+                    filter.exclude = regex.iter().cloned().collect()
+                }
+                _ => continue,
+            }
+            output.set_filter(filter);
+        }
+    }
+
+    fn process_index(&self, process_name: &str) -> Option<usize> {
+        self.instances
+            .iter()
+            .find(|i| i.process_name == process_name)
+            .map(|i| i.index)
+    }
+
+    // Several SIGCHLD can arrive as one, so every instance is checked. Each Child
+    // is waited on by its own pid, so an exit is never attributed to another instance
+    fn reap(&mut self) {
+        for slot in 0..self.instances.len() {
+            let status = match self.instances[slot].proc.as_mut() {
+                Some(proc) => proc.child.try_wait(),
+                None => continue,
+            };
+            match status {
+                Ok(Some(status)) => self.exited(slot, status),
+                Ok(None) => (),
+                Err(e) => log::error("system", &e, true, &self.opts),
+            }
+        }
+    }
+
+    fn exited(&mut self, slot: usize, status: ExitStatus) {
+        let instance = &mut self.instances[slot];
+        let proc = instance.proc.take().expect("failed take process");
+        instance.deadline = None;
+        let on_exit = mem::replace(&mut instance.on_exit, OnExit::Shutdown);
+        let name = instance.name.clone();
+
+        let message = match (status.code(), status.signal()) {
+            (Some(code), _) => format!("exited with code {}", code),
+            (None, Some(sig)) => match Signal::try_from(sig) {
+                Ok(sig) => format!("terminated by {}", sig.as_str()),
+                Err(_) => format!("terminated by signal {}", sig),
+            },
+            (None, None) => String::from("exited"),
+        };
+        let source = Source {
+            process: Some(&instance.process_name),
+            pid: Some(proc.child.id()),
+            ..Source::new(&name)
+        };
+        self.outputs[instance.index]
+            .log
+            .output_at(&source, &message);
+        self.history.push(Record {
+            name: name.clone(),
+            process: instance.process_name.clone(),
+            index: instance.index,
+            pid: proc.child.id(),
+            stream: None,
+            at: Record::at(&source.at),
+            line: message,
+        });
+        self.emit(Event::Exited {
+            name: name.clone(),
+            pid: proc.child.id(),
+            status,
+        });
+
+        match on_exit {
+            OnExit::Shutdown => self.shutdown(ShutdownReason::Exited(name)),
+            OnExit::Restart if self.shutdown.is_none() => self.respawn(slot),
+            OnExit::Restart | OnExit::Stop => (),
+        }
+    }
+
+    fn respawn(&mut self, slot: usize) {
+        if let Err(e) = self.spawn(slot) {
+            log::error("system", &e, true, &self.opts);
+        }
+    }
+
+    fn shutdown(&mut self, reason: ShutdownReason) {
+        if self.shutdown.is_some() {
+            return;
+        }
+
+        match reason {
+            ShutdownReason::Signal(sig) => {
+                // 2 is 「^C」 of 「^Csystem   | SIGINT received, starting shutdown」
+                let echoed = if sig == Signal::SIGINT { 2 } else { 0 };
+                log::output(
+                    "system",
+                    &format!("{} received, starting shutdown", sig.as_str()),
+                    None,
+                    &LogOpt {
+                        padding: self.opts.padding.saturating_sub(echoed),
+                        ..self.opts.clone()
+                    },
+                );
+                self.system("sending SIGTERM to all processes");
+            }
+            ShutdownReason::Requested => self.system("sending SIGTERM to all processes"),
+            ShutdownReason::Exited(_) => (),
+        }
+
+        self.emit(Event::ShutdownStarted {
+            reason: reason.clone(),
+        });
+        self.shutdown = Some(reason);
+
+        for slot in 0..self.instances.len() {
+            if self.instances[slot].proc.is_some() {
+                self.terminate(slot);
+            }
+        }
+    }
+
+    // SIGTERM now, SIGKILL once the timeout has passed
+    fn terminate(&mut self, slot: usize) {
+        let instance = &mut self.instances[slot];
+        if let Some(proc) = &instance.proc {
+            signal::kill(proc, Signal::SIGTERM, &self.opts);
+            instance.deadline = Some(Instant::now() + self.timeout);
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.instances
+            .iter()
+            .filter(|i| i.proc.is_some())
+            .filter_map(|i| i.deadline)
+            .min()
+    }
+
+    fn kill_expired(&mut self) {
+        let now = Instant::now();
+        let expired = self
+            .instances
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.proc.is_some() && i.deadline.is_some_and(|d| d <= now))
+            .map(|(slot, _)| slot)
+            .collect::<Vec<_>>();
+        if expired.is_empty() {
+            return;
+        }
+
+        // Ok, we have no other option than to kill them
+        if self.shutdown.is_some() {
+            self.system("sending SIGKILL to all processes");
+        }
+        for slot in expired {
+            let instance = &mut self.instances[slot];
+            instance.deadline = None;
+            if let Some(proc) = &instance.proc {
+                signal::kill(proc, Signal::SIGKILL, &self.opts);
+            }
+        }
+    }
+
+    fn kill_spawned(&mut self) {
+        for instance in self.instances.iter_mut() {
+            if let Some(proc) = instance.proc.as_mut() {
+                // The process may already have exited on its own
+                let _ = proc.child.kill();
+                let _ = proc.child.wait();
+            }
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(events) = &self.events {
+            // Nobody may be listening anymore
+            let _ = events.send(event);
+        }
+    }
+
+    fn system(&self, content: &str) {
+        log::output("system", content, None, &self.opts);
+    }
+}
+
+// What a line of stdin means to the supervisor
+#[derive(Debug, PartialEq)]
+enum Input {
+    Line(Vec<u8>),
+    // Ctrl-] and a name attaches it, Ctrl-] alone detaches
+    Attach(Option<String>),
+}
+
+impl Input {
+    fn parse(line: Vec<u8>) -> Self {
+        match line.split_first() {
+            Some((&ATTACH_CHORD, name)) => {
+                let name = String::from_utf8_lossy(name).trim().to_string();
+                Input::Attach(Some(name).filter(|name| !name.is_empty()))
+            }
+            _ => Input::Line(line),
+        }
+    }
+}
+
+// A terminal can be read again after ^D, a pipe or file is done at its first EOF
+fn read_input_thread(
+    stdin: Box<dyn Read + Send>,
+    is_terminal: bool,
+    tx: Sender<Message>,
+) -> Result<()> {
+    thread::Builder::new()
+        .name(String::from("read stdin"))
+        .spawn(move || {
+            let mut stdin = BufReader::new(stdin);
+            loop {
+                let mut line = vec![];
+                let (message, is_done) = match stdin.read_until(b'\n', &mut line) {
+                    Ok(0) => (Message::Input(None), !is_terminal),
+                    Err(_) => (Message::Input(None), true),
+                    Ok(_) => match Input::parse(line) {
+                        Input::Line(line) => (Message::Input(Some(line)), false),
+                        Input::Attach(name) => (Message::Command(Command::Attach(name)), false),
+                    },
+                };
+                if tx.send(message).is_err() || is_done {
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::UltramanError;
+    use std::thread;
+
+    #[test]
+    fn test_run_when_formation_is_invalid() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("web: ./web.sh");
+        let result = Supervisor::new(procfile)
+            .formation("worker=1".parse()?)
+            .run();
+
+        assert!(matches!(result, Err(UltramanError::Formation(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_when_hidden_process_is_unknown() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("web: ./web.sh");
+        let result = Supervisor::new(procfile)
+            .hide("wrker", Stream::Stderr)
+            .run();
+
+        assert!(matches!(result, Err(UltramanError::UnknownProcess { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_when_filtered_process_is_unknown() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("web: ./web.sh");
+        let result = Supervisor::new(procfile).only("web").quiet("wrker").run();
+
+        assert!(matches!(result, Err(UltramanError::UnknownProcess { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run() -> anyhow::Result<()> {
+        let procfile = Procfile::parse(
+            "exit_0: echo success && exit 0\nloop: ./test/fixtures/loop.sh supervisor_run",
+        );
+        let (tx, rx) = unbounded();
+        Supervisor::new(procfile)
+            .events(tx)
+            .timestamp(false)
+            .run()?;
+        let events = rx.try_iter().collect::<Vec<_>>();
+
+        let spawned = events
+            .iter()
+            .filter(|e| matches!(e, Event::Spawned { .. }))
+            .count();
+        assert_eq!(spawned, 2);
+        assert!(events.contains(&Event::Output {
+            name: String::from("exit_0.1"),
+            line: String::from("success"),
+        }));
+        assert!(events.contains(&Event::ShutdownStarted {
+            reason: ShutdownReason::Exited(String::from("exit_0.1")),
+        }));
+        assert!(events.iter().any(|e| match e {
+            Event::Exited { name, status, .. } =>
+                name == "loop.1" && status.signal() == Some(Signal::SIGTERM as i32),
+            _ => false,
+        }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_decoding() -> anyhow::Result<()> {
+        let procfile = Procfile::parse(r"latin1: printf 'caf\351\n'");
+        let (tx, rx) = unbounded();
+        Supervisor::new(procfile)
+            .decoding(Decoding::Hex)
+            .events(tx)
+            .timestamp(false)
+            .run()?;
+
+        assert!(rx.try_iter().any(|e| e
+            == Event::Output {
+                name: String::from("latin1.1"),
+                line: String::from(r"caf\xe9"),
+            }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_handle() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("loop: ./test/fixtures/loop.sh supervisor_handle");
+        let (tx, rx) = unbounded();
+        let supervisor = Supervisor::new(procfile).events(tx).timestamp(false);
+        let handle = supervisor.handle();
+        let thread = thread::spawn(move || supervisor.run());
+
+        let wait_for_spawn = || {
+            rx.iter()
+                .find(|e| matches!(e, Event::Spawned { .. }))
+                .expect("failed wait for spawn")
+        };
+        wait_for_spawn();
+        handle.restart("loop.1");
+        wait_for_spawn();
+        handle.shutdown();
+
+        thread.join().expect("failed join")?;
+        let events = rx.try_iter().collect::<Vec<_>>();
+        assert!(events.contains(&Event::ShutdownStarted {
+            reason: ShutdownReason::Requested
+        }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_attach() -> anyhow::Result<()> {
+        let procfile = Procfile::parse(r#"echo: read line && echo "got $line""#);
+        let (tx, rx) = unbounded();
+        Supervisor::new(procfile)
+            .attach("echo")
+            .stdin(&b"hello\n"[..])
+            .events(tx)
+            .timestamp(false)
+            .run()?;
+
+        assert!(rx.try_iter().any(|e| e
+            == Event::Output {
+                name: String::from("echo.1"),
+                line: String::from("got hello"),
+            }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_pty() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("tty: test -t 1 && test -t 2 && echo tty >&2");
+        let (tx, rx) = unbounded();
+        Supervisor::new(procfile)
+            .pty("tty")
+            .events(tx)
+            .timestamp(false)
+            .run()?;
+
+        assert!(rx.try_iter().any(|e| e
+            == Event::Output {
+                name: String::from("tty.1"),
+                line: String::from("tty"),
+            }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_with_attach_and_pty() -> anyhow::Result<()> {
+        let procfile = Procfile::parse(r#"echo: read line && echo "got $line""#);
+        let (tx, rx) = unbounded();
+        Supervisor::new(procfile)
+            .attach("echo")
+            .pty("echo")
+            .stdin(&b"hello\n"[..])
+            .events(tx)
+            .timestamp(false)
+            .run()?;
+
+        let lines = rx
+            .try_iter()
+            .filter_map(|e| match e {
+                Event::Output { line, .. } => Some(line),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // The input is not echoed back
+        assert_eq!(lines, ["got hello"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_parse() -> anyhow::Result<()> {
+        assert_eq!(
+            Input::parse(b"hello\n".to_vec()),
+            Input::Line(b"hello\n".to_vec())
+        );
+        assert_eq!(
+            Input::parse(b"\x1dweb.2\n".to_vec()),
+            Input::Attach(Some(String::from("web.2")))
+        );
+        assert_eq!(Input::parse(b"\x1d\n".to_vec()), Input::Attach(None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_when_terminated() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("loop: ./test/fixtures/loop.sh supervisor_terminated");
+        let (tx, rx) = unbounded();
+        let supervisor = Supervisor::new(procfile).events(tx).timestamp(false);
+        // As the signal thread would hand it over, without signaling the other tests
+        let signals = supervisor.tx.clone();
+        let thread = thread::spawn(move || supervisor.run());
+
+        rx.iter()
+            .find(|e| matches!(e, Event::Spawned { .. }))
+            .expect("failed wait for spawn");
+        signals.send(Message::Signal(libc::SIGTERM))?;
+
+        thread.join().expect("failed join")?;
+        let events = rx.try_iter().collect::<Vec<_>>();
+        assert!(events.contains(&Event::ShutdownStarted {
+            reason: ShutdownReason::Signal(Signal::SIGTERM)
+        }));
+
+        Ok(())
+    }
+
+    // Benchmark: cargo test --release -- --ignored --nocapture bench_idle_cpu_usage
+    #[test]
+    #[ignore]
+    #[cfg(target_os = "linux")]
+    fn bench_idle_cpu_usage() -> anyhow::Result<()> {
+        let procfile = Procfile::parse("sleep: sleep 30");
+        let (tx, rx) = unbounded();
+        let supervisor = Supervisor::new(procfile)
+            .formation("sleep=20".parse()?)
+            .events(tx)
+            .timestamp(false);
+        let handle = supervisor.handle();
+        let thread = thread::spawn(move || supervisor.run());
+
+        // Spawning is not idle, so only the time after it is measured
+        for _ in 0..20 {
+            rx.iter()
+                .find(|e| matches!(e, Event::Spawned { .. }))
+                .expect("failed wait for spawn");
+        }
+        thread::sleep(Duration::from_secs(1));
+        let before = process_cpu_time();
+        thread::sleep(Duration::from_secs(5));
+        let cpu_time = process_cpu_time() - before;
+
+        handle.shutdown();
+        thread.join().expect("failed join")?;
+
+        println!("20 idle processes for 5s cost {:?} of CPU", cpu_time);
+        assert!(cpu_time < Duration::from_millis(20));
+
+        Ok(())
+    }
+
+    // user + system time of every thread (supervisor, output readers, signals), not of children
+    #[cfg(target_os = "linux")]
+    fn process_cpu_time() -> Duration {
+        let mut usage: libc::rusage = unsafe { mem::zeroed() };
+        unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+        let to_duration = |t: libc::timeval| {
+            Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+        };
+        to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+    }
+}
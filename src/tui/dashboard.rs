@@ -0,0 +1,438 @@
+use crate::supervisor::{Event, ShutdownReason};
+use crate::tui::term::Key;
+
+use nix::sys::signal::Signal;
+use regex::{Regex, RegexBuilder};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::os::unix::process::ExitStatusExt;
+use std::time::Instant;
+
+// Older lines are dropped, per instance and in the combined view
+const MAX_LINES: usize = 10_000;
+
+// PORT of an instance, from its name (e.g. web.2)
+type PortFor = Box<dyn Fn(&str) -> Option<u32>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Running,
+    Restarting,
+    // e.g.) exited with code 1, terminated by SIGTERM
+    Exited(String),
+}
+
+// An instance of a process (e.g. web.1) in the process list
+pub struct Row {
+    pub name: String,
+    // index of the process in the Procfile, which picks the color
+    pub index: usize,
+    pub status: Status,
+    pub pid: Option<u32>,
+    pub started_at: Option<Instant>,
+    // times the instance was spawned again
+    pub restarts: usize,
+    pub port: Option<u32>,
+    pub lines: VecDeque<String>,
+}
+
+// A line of the combined view. `slot` is None for lines about the processes
+pub struct Line {
+    pub slot: Option<usize>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    Combined,
+    Instance(usize),
+}
+
+// What a key asks the supervisor to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Start(String),
+    Stop(String),
+    Restart(String),
+    Shutdown,
+    Quit,
+}
+
+// Everything the dashboard shows, kept up to date from supervisor events and keys
+pub struct Dashboard {
+    pub rows: Vec<Row>,
+    pub combined: VecDeque<Line>,
+    pub view: View,
+    // selected row in the process list
+    pub cursor: usize,
+    // lines scrolled up from the bottom of the log pane, 0 follows new lines
+    pub scroll: usize,
+    // lines of the log pane, to scroll by a page
+    pub page: usize,
+    // what is typed after /
+    pub prompt: Option<String>,
+    pub search: Option<Regex>,
+    pub shutdown: Option<ShutdownReason>,
+    pub is_finished: bool,
+    port_for: PortFor,
+    processes: Vec<String>,
+}
+
+impl Dashboard {
+    pub fn new<F>(port_for: F) -> Self
+    where
+        F: Fn(&str) -> Option<u32> + 'static,
+    {
+        Dashboard {
+            rows: vec![],
+            combined: VecDeque::new(),
+            view: View::Combined,
+            cursor: 0,
+            scroll: 0,
+            page: 10,
+            prompt: None,
+            search: None,
+            shutdown: None,
+            is_finished: false,
+            port_for: Box::new(port_for),
+            processes: vec![],
+        }
+    }
+
+    pub fn apply(&mut self, event: Event) {
+        match event {
+            Event::Spawned { name, pid } => {
+                let slot = self.slot(&name);
+                let row = &mut self.rows[slot];
+                if row.started_at.is_some() {
+                    row.restarts += 1;
+                }
+                row.status = Status::Running;
+                row.pid = Some(pid);
+                row.started_at = Some(Instant::now());
+                self.system(format!("{} start at pid: {}", name, pid));
+            }
+            Event::Output { name, line } => {
+                let slot = self.slot(&name);
+                let text = sanitize(&line);
+                push(&mut self.rows[slot].lines, text.clone());
+                self.push(Some(slot), text);
+            }
+            Event::Exited { name, status, .. } => {
+                let message = match (status.code(), status.signal()) {
+                    (Some(code), _) => format!("exited with code {}", code),
+                    (None, Some(sig)) => match Signal::try_from(sig) {
+                        Ok(sig) => format!("terminated by {}", sig.as_str()),
+                        Err(_) => format!("terminated by signal {}", sig),
+                    },
+                    (None, None) => String::from("exited"),
+                };
+                let slot = self.slot(&name);
+                let row = &mut self.rows[slot];
+                if row.status != Status::Restarting {
+                    row.status = Status::Exited(message.clone());
+                }
+                row.pid = None;
+                self.system(format!("{} {}", name, message));
+            }
+            Event::Restarting { name } => {
+                let slot = self.slot(&name);
+                self.rows[slot].status = Status::Restarting;
+                self.system(format!("restarting {}", name));
+            }
+            Event::ShutdownStarted { reason } => {
+                match &reason {
+                    ShutdownReason::Signal(sig) => {
+                        self.system(format!("{} received, starting shutdown", sig.as_str()))
+                    }
+                    ShutdownReason::Exited(name) => {
+                        self.system(format!("{} has exited, starting shutdown", name))
+                    }
+                    ShutdownReason::Requested => self.system(String::from("starting shutdown")),
+                }
+                self.shutdown = Some(reason);
+            }
+        }
+    }
+
+    pub fn key(&mut self, key: Key) -> Option<Action> {
+        if let Some(prompt) = self.prompt.as_mut() {
+            match key {
+                Key::Char(c) => prompt.push(c),
+                Key::Backspace => {
+                    prompt.pop();
+                }
+                Key::Enter => {
+                    let prompt = self.prompt.take().unwrap_or_default();
+                    self.search = search_regex(&prompt);
+                    self.scroll = 0;
+                }
+                Key::Esc => self.prompt = None,
+                Key::CtrlC => return self.quit(),
+                _ => (),
+            }
+            return None;
+        }
+
+        let selected = self.rows.get(self.cursor).map(|row| row.name.clone());
+        match key {
+            Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Down | Key::Char('j') => {
+                self.cursor = (self.cursor + 1).min(self.rows.len().saturating_sub(1))
+            }
+            Key::Enter if !self.rows.is_empty() => {
+                self.view = View::Instance(self.cursor);
+                self.scroll = 0;
+            }
+            Key::Esc => {
+                if self.search.is_some() {
+                    self.search = None;
+                } else {
+                    self.view = View::Combined;
+                }
+                self.scroll = 0;
+            }
+            Key::Char('c') => {
+                self.view = View::Combined;
+                self.scroll = 0;
+            }
+            Key::PageUp => self.scroll = self.scroll.saturating_add(self.page),
+            Key::PageDown => self.scroll = self.scroll.saturating_sub(self.page),
+            Key::Home | Key::Char('g') => self.scroll = usize::MAX,
+            Key::End | Key::Char('G') => self.scroll = 0,
+            Key::Char('/') => self.prompt = Some(String::new()),
+            Key::Char('s') => return selected.map(Action::Start),
+            Key::Char('x') => return selected.map(Action::Stop),
+            Key::Char('r') => return selected.map(Action::Restart),
+            Key::Char('q') | Key::CtrlC => return self.quit(),
+            _ => (),
+        }
+        None
+    }
+
+    // Lines of the current view that match the search, oldest first
+    pub fn lines(&self) -> Vec<(Option<usize>, &str)> {
+        let lines: Box<dyn Iterator<Item = (Option<usize>, &str)>> = match self.view {
+            View::Combined => Box::new(
+                self.combined
+                    .iter()
+                    .map(|line| (line.slot, line.text.as_str())),
+            ),
+            View::Instance(slot) => Box::new(
+                self.rows[slot]
+                    .lines
+                    .iter()
+                    .map(move |line| (Some(slot), line.as_str())),
+            ),
+        };
+        match &self.search {
+            Some(search) => lines.filter(|(_, text)| search.is_match(text)).collect(),
+            None => lines.collect(),
+        }
+    }
+
+    // The first q shuts the processes down, and the dashboard is closed once they are gone
+    fn quit(&mut self) -> Option<Action> {
+        if self.is_finished {
+            Some(Action::Quit)
+        } else if self.shutdown.is_none() {
+            Some(Action::Shutdown)
+        } else {
+            None
+        }
+    }
+
+    // Instances are listed in the order they are first spawned, which is the Procfile order
+    fn slot(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.rows.iter().position(|row| row.name == name) {
+            return slot;
+        }
+        let process_name = name.rsplit_once('.').map_or(name, |(process, _)| process);
+        let index = match self.processes.iter().position(|p| p == process_name) {
+            Some(index) => index,
+            None => {
+                self.processes.push(process_name.to_string());
+                self.processes.len() - 1
+            }
+        };
+        self.rows.push(Row {
+            name: name.to_string(),
+            index,
+            status: Status::Running,
+            pid: None,
+            started_at: None,
+            restarts: 0,
+            port: (self.port_for)(name),
+            lines: VecDeque::new(),
+        });
+        self.rows.len() - 1
+    }
+
+    fn system(&mut self, text: String) {
+        self.push(None, text);
+    }
+
+    fn push(&mut self, slot: Option<usize>, text: String) {
+        // Scrolled up lines stay where they are
+        let is_shown = match self.view {
+            View::Combined => true,
+            View::Instance(shown) => slot == Some(shown),
+        } && self
+            .search
+            .as_ref()
+            .is_none_or(|search| search.is_match(&text));
+        if self.scroll > 0 && is_shown {
+            self.scroll = self.scroll.saturating_add(1);
+        }
+
+        if self.combined.len() == MAX_LINES {
+            self.combined.pop_front();
+        }
+        self.combined.push_back(Line { slot, text });
+    }
+}
+
+fn push(lines: &mut VecDeque<String>, text: String) {
+    if lines.len() == MAX_LINES {
+        lines.pop_front();
+    }
+    lines.push_back(text);
+}
+
+// Searches ignore case, and text that is not a regex is searched as it is
+fn search_regex(text: &str) -> Option<Regex> {
+    if text.is_empty() {
+        return None;
+    }
+    RegexBuilder::new(text)
+        .case_insensitive(true)
+        .build()
+        .or_else(|_| {
+            RegexBuilder::new(&regex::escape(text))
+                .case_insensitive(true)
+                .build()
+        })
+        .ok()
+}
+
+// Colors and cursor movements of a process would draw over the dashboard
+fn sanitize(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                // CSI sequences end with a letter (e.g. \x1b[31m), others are two characters
+                if chars.next_if_eq(&'[').is_some() {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                } else {
+                    chars.next();
+                }
+            }
+            '\t' => text.push_str("    "),
+            c if c.is_control() => (),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::ExitStatus;
+
+    fn dashboard() -> Dashboard {
+        let mut dashboard = Dashboard::new(|_| Some(5000));
+        dashboard.apply(Event::Spawned {
+            name: String::from("web.1"),
+            pid: 100,
+        });
+        dashboard.apply(Event::Spawned {
+            name: String::from("worker.1"),
+            pid: 200,
+        });
+        dashboard.apply(Event::Output {
+            name: String::from("web.1"),
+            line: String::from("\x1b[32mGET\x1b[0m /"),
+        });
+        dashboard.apply(Event::Output {
+            name: String::from("worker.1"),
+            line: String::from("job done"),
+        });
+        dashboard
+    }
+
+    #[test]
+    fn test_apply() -> anyhow::Result<()> {
+        let mut dashboard = dashboard();
+        dashboard.apply(Event::Restarting {
+            name: String::from("web.1"),
+        });
+        dashboard.apply(Event::Exited {
+            name: String::from("web.1"),
+            pid: 100,
+            status: ExitStatus::from_raw(15),
+        });
+        dashboard.apply(Event::Spawned {
+            name: String::from("web.1"),
+            pid: 101,
+        });
+
+        let web = &dashboard.rows[0];
+        assert_eq!(web.status, Status::Running);
+        assert_eq!(web.pid, Some(101));
+        assert_eq!(web.restarts, 1);
+        assert_eq!(web.port, Some(5000));
+        assert_eq!(web.lines, vec!["GET /"]);
+        assert_eq!(dashboard.rows[1].index, 1);
+        assert_eq!(
+            dashboard.lines(),
+            vec![
+                (None, "web.1 start at pid: 100"),
+                (None, "worker.1 start at pid: 200"),
+                (Some(0), "GET /"),
+                (Some(1), "job done"),
+                (None, "restarting web.1"),
+                (None, "web.1 terminated by SIGTERM"),
+                (None, "web.1 start at pid: 101"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key() -> anyhow::Result<()> {
+        let mut dashboard = dashboard();
+
+        dashboard.key(Key::Down);
+        assert_eq!(
+            dashboard.key(Key::Char('r')),
+            Some(Action::Restart(String::from("worker.1")))
+        );
+        dashboard.key(Key::Enter);
+        assert_eq!(dashboard.lines(), vec![(Some(1), "job done")]);
+
+        dashboard.key(Key::Char('c'));
+        for key in "/get".chars().map(Key::Char) {
+            dashboard.key(key);
+        }
+        dashboard.key(Key::Enter);
+        assert_eq!(dashboard.lines(), vec![(Some(0), "GET /")]);
+
+        assert_eq!(dashboard.key(Key::Char('q')), Some(Action::Shutdown));
+        dashboard.apply(Event::ShutdownStarted {
+            reason: ShutdownReason::Requested,
+        });
+        assert_eq!(dashboard.key(Key::Char('q')), None);
+        dashboard.is_finished = true;
+        assert_eq!(dashboard.key(Key::Char('q')), Some(Action::Quit));
+
+        Ok(())
+    }
+}
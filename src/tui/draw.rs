@@ -0,0 +1,302 @@
+use crate::log::color::{color_for, SYSTEM_COLOR};
+use crate::tui::dashboard::{Dashboard, Status, View};
+
+use colored::*;
+use regex::Regex;
+use std::time::Instant;
+
+const HELP: &str = "↑↓ select  enter show  c combined  / search  esc back  s start  x stop  r restart  pgup/pgdn scroll  q quit";
+
+// The screen as `height` lines of at most `width` columns, from the top:
+// title, process list, log pane and the help line (or search prompt)
+pub fn draw(dashboard: &mut Dashboard, width: usize, height: usize) -> Vec<String> {
+    let name_width = dashboard
+        .rows
+        .iter()
+        .map(|row| row.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+    // The process list takes up to a third of the screen and scrolls with the cursor
+    let list_height = dashboard
+        .rows
+        .len()
+        .min((height.saturating_sub(4) / 3).max(1));
+    let log_height = height.saturating_sub(list_height + 4);
+    dashboard.page = log_height.max(1);
+
+    let mut lines = vec![title(dashboard, width)];
+    lines.extend(process_list(dashboard, width, name_width, list_height));
+    lines.push(
+        fit(&format!("── {} ", pane_title(dashboard)), width)
+            .chars()
+            .chain("─".chars().cycle())
+            .take(width)
+            .collect::<String>()
+            .dimmed()
+            .to_string(),
+    );
+    lines.extend(log_pane(dashboard, width, name_width, log_height));
+    lines.push(match &dashboard.prompt {
+        Some(prompt) => fit(&format!("/{}_", prompt), width),
+        None if dashboard.is_finished => fit("all processes have exited, press q to quit", width)
+            .bold()
+            .to_string(),
+        None => fit(HELP, width).dimmed().to_string(),
+    });
+    lines.truncate(height);
+    lines
+}
+
+fn title(dashboard: &Dashboard, width: usize) -> String {
+    let running = dashboard
+        .rows
+        .iter()
+        .filter(|row| row.pid.is_some())
+        .count();
+    let state = if dashboard.is_finished {
+        String::from("finished")
+    } else if dashboard.shutdown.is_some() {
+        String::from("shutting down")
+    } else {
+        format!("{}/{} running", running, dashboard.rows.len())
+    };
+    let title = format!(" ultraman  {}", state);
+    format!("{:1$}", fit(&title, width), width)
+        .reversed()
+        .to_string()
+}
+
+fn process_list(
+    dashboard: &Dashboard,
+    width: usize,
+    name_width: usize,
+    height: usize,
+) -> Vec<String> {
+    let status_width = dashboard
+        .rows
+        .iter()
+        .map(|row| status_text(&row.status).len())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+    let header = format!(
+        "  {:name$}  {:status$}  {:>7}  {:>8}  {:>8}  {:>5}",
+        "NAME",
+        "STATUS",
+        "PID",
+        "UPTIME",
+        "RESTARTS",
+        "PORT",
+        name = name_width,
+        status = status_width
+    );
+    let mut lines = vec![fit(&header, width).bold().to_string()];
+
+    let first = (dashboard.cursor + 1).saturating_sub(height);
+    let now = Instant::now();
+    for (slot, row) in dashboard.rows.iter().enumerate().skip(first).take(height) {
+        let is_selected = slot == dashboard.cursor;
+        let status = status_text(&row.status);
+        let uptime = match (row.pid, row.started_at) {
+            (Some(_), Some(started_at)) => uptime(now.duration_since(started_at).as_secs()),
+            _ => String::from("-"),
+        };
+        let columns = format!(
+            "  {:>7}  {:>8}  {:>8}  {:>5}",
+            row.pid.map_or(String::from("-"), |pid| pid.to_string()),
+            uptime,
+            row.restarts,
+            row.port.map_or(String::from("-"), |port| port.to_string()),
+        );
+        let name = format!("{:1$}", row.name, name_width);
+        let status = format!("{:1$}", status, status_width);
+        let plain = format!("{} {}  {}{}", marker(is_selected), name, status, columns);
+        // Colors are only added to lines that fit, since cutting one would cut an escape sequence
+        let line = if plain.chars().count() <= width {
+            let status = match row.status {
+                Status::Running => status.green(),
+                Status::Restarting => status.yellow(),
+                Status::Exited(_) => status.red(),
+            };
+            format!(
+                "{} {}  {}{}",
+                marker(is_selected),
+                name.color(color_for(row.index)),
+                status,
+                columns
+            )
+        } else {
+            fit(&plain, width)
+        };
+        lines.push(if is_selected {
+            line.bold().to_string()
+        } else {
+            line
+        });
+    }
+    lines
+}
+
+fn marker(is_selected: bool) -> char {
+    if is_selected {
+        '›'
+    } else {
+        ' '
+    }
+}
+
+fn status_text(status: &Status) -> &str {
+    match status {
+        Status::Running => "running",
+        Status::Restarting => "restarting",
+        Status::Exited(message) => message,
+    }
+}
+
+fn pane_title(dashboard: &Dashboard) -> String {
+    let view = match dashboard.view {
+        View::Combined => String::from("all processes"),
+        View::Instance(slot) => dashboard.rows[slot].name.clone(),
+    };
+    match &dashboard.search {
+        Some(search) => format!("{} matching /{}/", view, search),
+        None => view,
+    }
+}
+
+fn log_pane(
+    dashboard: &mut Dashboard,
+    width: usize,
+    name_width: usize,
+    height: usize,
+) -> Vec<String> {
+    let lines = dashboard.lines();
+    let max_scroll = lines.len().saturating_sub(height);
+    let scroll = dashboard.scroll.min(max_scroll);
+    let end = lines.len() - scroll;
+    let start = end.saturating_sub(height);
+
+    let mut pane = lines[start..end]
+        .iter()
+        .map(|(slot, text)| {
+            let (prefix, width) = match (dashboard.view, slot) {
+                (View::Instance(_), _) => (String::new(), width),
+                (View::Combined, Some(slot)) => {
+                    let row = &dashboard.rows[*slot];
+                    (
+                        format!("{:2$} {} ", row.name, "|", name_width)
+                            .color(color_for(row.index))
+                            .to_string(),
+                        width.saturating_sub(name_width + 3),
+                    )
+                }
+                (View::Combined, None) => (
+                    format!("{:1$} | ", "system", name_width)
+                        .color(SYSTEM_COLOR)
+                        .to_string(),
+                    width.saturating_sub(name_width + 3),
+                ),
+            };
+            let text = fit(text, width);
+            let text = match &dashboard.search {
+                Some(search) => highlight(&text, search),
+                None => text,
+            };
+            format!("{}{}", prefix, text)
+        })
+        .collect::<Vec<_>>();
+    pane.resize(height, String::new());
+    dashboard.scroll = scroll;
+    pane
+}
+
+fn highlight(text: &str, search: &Regex) -> String {
+    let mut line = String::new();
+    let mut last = 0;
+    for found in search.find_iter(text).filter(|found| !found.is_empty()) {
+        line.push_str(&text[last..found.start()]);
+        line.push_str(&found.as_str().reversed().to_string());
+        last = found.end();
+    }
+    line.push_str(&text[last..]);
+    line
+}
+
+// Lines are cut at the edge of the screen rather than wrapped
+fn fit(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+// e.g.) 12s, 3m05s, 1h02m, 2d03h
+fn uptime(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s if s < 24 * 60 * 60 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+        s => format!("{}d{:02}h", s / 86400, s % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::Event;
+
+    // Whether colors are on depends on the terminal the tests run in
+    fn plain(lines: Vec<String>) -> Vec<String> {
+        let color = Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        lines
+            .iter()
+            .map(|line| color.replace_all(line, "").into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_draw() -> anyhow::Result<()> {
+        let mut dashboard = Dashboard::new(|_| Some(5000));
+        dashboard.apply(Event::Spawned {
+            name: String::from("web.1"),
+            pid: 100,
+        });
+        for n in 1..=20 {
+            dashboard.apply(Event::Output {
+                name: String::from("web.1"),
+                line: format!("line {}", n),
+            });
+        }
+
+        let lines = plain(draw(&mut dashboard, 60, 12));
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[0].trim_end(), " ultraman  1/1 running");
+        assert_eq!(
+            lines[1],
+            "  NAME    STATUS       PID    UPTIME  RESTARTS   PORT"
+        );
+        assert_eq!(
+            lines[2],
+            "› web.1   running      100        0s         0   5000"
+        );
+        assert!(lines[3].starts_with("── all processes ──"));
+        // the log pane follows the last lines
+        assert_eq!(lines[9], "web.1  | line 19");
+        assert_eq!(lines[10], "web.1  | line 20");
+
+        dashboard.scroll = 100;
+        let lines = plain(draw(&mut dashboard, 60, 12));
+        assert_eq!(lines[4], "system | web.1 start at pid: 100");
+        assert_eq!(dashboard.scroll, 14);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uptime() -> anyhow::Result<()> {
+        assert_eq!(uptime(12), "12s");
+        assert_eq!(uptime(185), "3m05s");
+        assert_eq!(uptime(3720), "1h02m");
+        assert_eq!(uptime(183600), "2d03h");
+
+        Ok(())
+    }
+}
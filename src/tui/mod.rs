@@ -0,0 +1,103 @@
+use crate::error::{self, Result, UltramanError};
+use crate::log::writer;
+use crate::process;
+use crate::supervisor::Supervisor;
+
+use crossbeam::channel::{never, select, unbounded, Receiver};
+use dashboard::{Action, Dashboard};
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use term::{Key, Terminal};
+
+mod dashboard;
+mod draw;
+mod term;
+
+// Uptimes and the terminal size are refreshed at least this often
+const TICK: Duration = Duration::from_millis(500);
+
+// Runs the supervisor in a dashboard instead of printing its output, until q is pressed after
+// every process has exited. `env_path` and `port` are the ones given to the supervisor, for PORT
+pub fn run(supervisor: Supervisor, env_path: PathBuf, port: Option<u32>) -> Result<()> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err(UltramanError::NotATerminal);
+    }
+    // Lines would be printed over the dashboard, which shows them from events instead
+    writer::redirect_stdout(io::sink())?;
+
+    let (tx, mut events) = unbounded();
+    let supervisor = supervisor.events(tx);
+    let handle = supervisor.handle();
+    let mut supervisor = Some(
+        thread::Builder::new()
+            .name(String::from("supervise"))
+            .spawn(move || supervisor.run())?,
+    );
+
+    let terminal = Terminal::enter()?;
+    let mut keys = read_keys_thread()?;
+    // e.g.) web.2 -> the PORT of the second instance
+    let mut dashboard = Dashboard::new(move |name| {
+        let (_, n) = name.rsplit_once('.')?;
+        let concurrency_index = n.parse::<usize>().ok()?.checked_sub(1)?;
+        process::port_for(&env_path, port, concurrency_index).ok()
+    });
+
+    loop {
+        let (width, height) = terminal.size();
+        terminal.draw(&draw::draw(&mut dashboard, width, height))?;
+
+        select! {
+            recv(events) -> event => match event {
+                Ok(event) => {
+                    dashboard.apply(event);
+                    // Output comes in bursts, so a burst is drawn once
+                    for event in events.try_iter() {
+                        dashboard.apply(event);
+                    }
+                }
+                // The supervisor has returned
+                Err(_) => {
+                    events = never();
+                    if let Some(supervisor) = supervisor.take() {
+                        error::join(supervisor)??;
+                    }
+                    dashboard.is_finished = true;
+                }
+            },
+            recv(keys) -> pressed => match pressed {
+                Ok(pressed) => {
+                    for key in pressed {
+                        match dashboard.key(key) {
+                            Some(Action::Start(name)) => handle.start(&name),
+                            Some(Action::Stop(name)) => handle.stop(&name),
+                            Some(Action::Restart(name)) => handle.restart(&name),
+                            Some(Action::Shutdown) => handle.shutdown(),
+                            Some(Action::Quit) => return Ok(()),
+                            None => (),
+                        }
+                    }
+                }
+                // stdin is gone, so nothing can be done but wait for the processes
+                Err(_) => keys = never(),
+            },
+            default(TICK) => (),
+        }
+    }
+}
+
+fn read_keys_thread() -> io::Result<Receiver<Vec<Key>>> {
+    let (tx, rx) = unbounded();
+    thread::Builder::new()
+        .name(String::from("read keys"))
+        .spawn(move || {
+            while let Ok(keys) = term::read_keys() {
+                if tx.send(keys).is_err() {
+                    break;
+                }
+            }
+        })?;
+    Ok(rx)
+}
@@ -0,0 +1,157 @@
+use nix::libc;
+use nix::sys::termios::{self, SetArg, Termios};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Backspace,
+    Esc,
+    CtrlC,
+}
+
+// Raw mode on the alternate screen until it is dropped, so the shell gets its screen back
+// even when the dashboard returns early
+pub struct Terminal {
+    original: Termios,
+}
+
+impl Terminal {
+    pub fn enter() -> io::Result<Self> {
+        let fd = io::stdin().as_raw_fd();
+        let original = termios::tcgetattr(fd).map_err(io::Error::from)?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw).map_err(io::Error::from)?;
+
+        // alternate screen, hidden cursor
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+        Ok(Terminal { original })
+    }
+
+    // (columns, rows)
+    pub fn size(&self) -> (usize, usize) {
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCGWINSZ only writes to `size`
+        let result = unsafe { libc::ioctl(io::stdout().as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 && size.ws_row > 0 {
+            (size.ws_col as usize, size.ws_row as usize)
+        } else {
+            (80, 24)
+        }
+    }
+
+    // Every line is overwritten in place, which doesn't flicker like clearing the screen
+    pub fn draw(&self, lines: &[String]) -> io::Result<()> {
+        let mut frame = String::from("\x1b[H");
+        for (n, line) in lines.iter().enumerate() {
+            if n > 0 {
+                frame.push_str("\r\n");
+            }
+            frame.push_str(line);
+            frame.push_str("\x1b[0m\x1b[K");
+        }
+        frame.push_str("\x1b[J");
+        let mut stdout = io::stdout();
+        stdout.write_all(frame.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        let _ = termios::tcsetattr(io::stdin().as_raw_fd(), SetArg::TCSANOW, &self.original);
+    }
+}
+
+// Blocks until keys are pressed. A read can hold several keys (e.g. pasted text)
+pub fn read_keys() -> io::Result<Vec<Key>> {
+    let mut buf = [0; 64];
+    let len = io::stdin().read(&mut buf)?;
+    if len == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(parse_keys(&String::from_utf8_lossy(&buf[..len])))
+}
+
+// Escape sequences of the keys xterm and most terminals send, anything else is dropped
+pub fn parse_keys(input: &str) -> Vec<Key> {
+    let mut keys = vec![];
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' if chars.peek() == Some(&'[') || chars.peek() == Some(&'O') => {
+                chars.next();
+                let mut sequence = String::new();
+                for c in chars.by_ref() {
+                    sequence.push(c);
+                    if c.is_ascii_alphabetic() || c == '~' {
+                        break;
+                    }
+                }
+                match sequence.as_str() {
+                    "A" => Key::Up,
+                    "B" => Key::Down,
+                    "5~" => Key::PageUp,
+                    "6~" => Key::PageDown,
+                    "H" | "1~" | "7~" => Key::Home,
+                    "F" | "4~" | "8~" => Key::End,
+                    _ => continue,
+                }
+            }
+            '\x1b' => Key::Esc,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\x03' => Key::CtrlC,
+            c if c.is_control() => continue,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_keys("j\x1b[A\x1b[6~\x1bOH\r/\x7f\x1b\x03"),
+            vec![
+                Key::Char('j'),
+                Key::Up,
+                Key::PageDown,
+                Key::Home,
+                Key::Enter,
+                Key::Char('/'),
+                Key::Backspace,
+                Key::Esc,
+                Key::CtrlC,
+            ]
+        );
+        // unknown sequences and control characters are dropped
+        assert_eq!(parse_keys("\x1b[15~\tq"), vec![Key::Char('q')]);
+
+        Ok(())
+    }
+}